@@ -0,0 +1,241 @@
+//! filter 子命令：按条件从 HMSim trace 中选出一部分请求
+//!
+//! 逐行读取和写出，不会把整个 trace 读入内存。所有条件同时满足的请求才会被保留，
+//! 保留下来的请求之间的时间间隔重新计算，使它们之间的到达时间差与原 trace 中相同。
+
+use std::io::Write;
+
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    commands::generate_tape_trace::OpMix,
+    error::HMSimError,
+    output::{ratio, ratio_text, Report},
+    trace::{open_output, Op, RetimingWriter, TraceReader, TraceRecord},
+    utils::{range_to_num, size_range_to_start_end, time_range_to_num},
+};
+
+/// filter 的命令行参数，不指定的条件不做筛选
+#[derive(Debug, Clone, clap::Args)]
+pub struct FilterArgs {
+    /// 输入的 trace 文件名
+    #[arg(short, long)]
+    pub file: String,
+
+    /// 输出的 trace 文件名，`-` 表示输出到 stdout(此时统计结果输出到 stderr)
+    #[arg(short, long, default_value = "filtered.trace")]
+    pub output: String,
+
+    /// 只保留读(r)或写(w)请求，rw 表示都保留
+    #[arg(long, value_enum, ignore_case = true)]
+    pub op: Option<OpMix>,
+
+    /// 只保留完全落在地址范围 [start, end) 内的请求，需要加上单位，例如 0-100G
+    #[arg(long, value_name = "start-end")]
+    pub offset: Option<String>,
+
+    /// 只保留大小在 [min, max] 内的请求，需要加上单位，例如 4K-1M
+    #[arg(long, value_name = "min-max")]
+    pub length: Option<String>,
+
+    /// 只保留到达时间(之前所有请求的 nextinter 之和，单位: 毫秒)在 [start, end) 内的请求，例如 0-60000
+    #[arg(long, value_name = "start-end")]
+    pub time: Option<String>,
+
+    /// 只保留序号(从 0 开始)在 [first, last] 内的请求，例如 0-9999 为前 10000 个请求
+    #[arg(long, value_name = "first-last")]
+    pub index: Option<String>,
+}
+
+impl FilterArgs {
+    /// 解析所有条件，大小转化为扇区
+    pub fn resolve(&self) -> Result<Filter, HMSimError> {
+        let sectors = |range: &str| {
+            size_range_to_start_end(range).map(|pair| (pair.size_begin.block, pair.size_end.block))
+        };
+        Ok(Filter {
+            file: self.file.clone(),
+            output: self.output.clone(),
+            force: false,
+            op: self.op,
+            offset: self.offset.as_deref().map(sectors).transpose()?,
+            length: self.length.as_deref().map(sectors).transpose()?,
+            time: self.time.as_deref().map(time_range_to_num).transpose()?,
+            index: self.index.as_deref().map(range_to_num).transpose()?,
+        })
+    }
+}
+
+/// 筛选条件，为 None 的条件不做筛选，地址和大小的单位为扇区，时间的单位为毫秒
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    /// 输入和输出的 trace 文件名，输出文件已经存在时是否覆盖
+    pub file: String,
+    pub output: String,
+    pub force: bool,
+    pub op: Option<OpMix>,
+    /// [start, end)
+    pub offset: Option<(u64, u64)>,
+    /// [min, max]
+    pub length: Option<(u64, u64)>,
+    /// [start, end)
+    pub time: Option<(f64, f64)>,
+    /// [first, last]
+    pub index: Option<(u64, u64)>,
+}
+
+impl Filter {
+    /// 序号为 index、到达时间为 arrival 的请求是否满足所有条件
+    pub fn matches(&self, index: u64, arrival: f64, record: &TraceRecord) -> bool {
+        let op = match (self.op, record.op) {
+            (None, _) | (Some(OpMix::Rw), _) => true,
+            (Some(mix), Op::Read) => mix.reads(),
+            (Some(mix), Op::Write) => mix.writes(),
+        };
+        op && self.offset.is_none_or(|(start, end)| record.offset_sectors >= start && record.end_sectors() <= end)
+            && self.length.is_none_or(|(min, max)| (min..=max).contains(&record.len_sectors))
+            && self.time.is_none_or(|(start, end)| arrival >= start && arrival < end)
+            && self.index.is_none_or(|(first, last)| (first..=last).contains(&index))
+    }
+
+    /// 序号和到达时间只会增加，超出范围之后的请求都不会被保留，可以提前结束
+    pub fn finished(&self, index: u64, arrival: f64) -> bool {
+        self.index.is_some_and(|(_, last)| index > last) || self.time.is_some_and(|(_, end)| arrival >= end)
+    }
+}
+
+/// filter 子命令输出的统计信息，数据量单位为扇区(512B)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FilterStats {
+    pub file: String,
+    pub output: String,
+    /// 读取的请求数量(提前结束时不包含之后的请求)和保留的请求数量
+    pub scanned: u64,
+    pub kept: u64,
+    pub read_op: u64,
+    pub write_op: u64,
+    pub read_data: u64,
+    pub write_data: u64,
+    /// 保留的请求数量占读取的请求数量的比例
+    pub kept_ratio: Option<f64>,
+    /// 保留的第一个请求和最后一个请求的到达时间(单位: 毫秒)
+    pub first_arrival: Option<f64>,
+    pub last_arrival: Option<f64>,
+}
+
+impl Report for FilterStats {
+    fn to_text(&self) -> String {
+        format!(
+            "file: {}  output: {}\n\
+            scanned: {:<10}  kept: {:<10}  ratio: {}\n\
+            read_op:   {:<10}    write_op:   {:<10}\n\
+            read_data: {:<10}MB  write_data: {:<10}MB\n\
+            arrival: {}..{}ms",
+            self.file,
+            self.output,
+            self.scanned,
+            self.kept,
+            ratio_text(self.kept_ratio),
+            self.read_op,
+            self.write_op,
+            self.read_data as f32 / 2048f32,
+            self.write_data as f32 / 2048f32,
+            self.first_arrival.map_or(String::from("-"), |t| format!("{:.3}", t)),
+            self.last_arrival.map_or(String::from("-"), |t| format!("{:.3}", t)),
+        )
+    }
+}
+
+/// 对外暴露的函数，筛选 file 中的请求写入 output，返回统计信息
+pub fn filter(filter: &Filter) -> Result<FilterStats, HMSimError> {
+    if filter.file == filter.output {
+        return Err(HMSimError::CommandError(format!("输出文件 {} 不能同时是输入文件", filter.output)));
+    }
+    let reader = TraceReader::open(&filter.file)?;
+    let mut writer = RetimingWriter::new(open_output(&filter.output, filter.force)?);
+    let mut stats = filter_records(reader, &mut writer, filter)?;
+    writer.flush().map_err(|e| e.with_path(&filter.output))?;
+
+    info!("filter running done.");
+    stats.file = filter.file.clone();
+    stats.output = filter.output.clone();
+    Ok(stats)
+}
+
+/// 筛选请求并写出，保留下来的请求的时间间隔改为与下一个保留的请求的到达时间之差，最后一个请求保留原来的时间间隔
+fn filter_records<I, W>(records: I, writer: &mut RetimingWriter<W>, filter: &Filter) -> Result<FilterStats, HMSimError>
+where
+    I: Iterator<Item = Result<TraceRecord, HMSimError>>,
+    W: Write,
+{
+    let mut stats = FilterStats::default();
+    let mut clock = 0.0;
+
+    for (index, record) in (0u64..).zip(records) {
+        if filter.finished(index, clock) {
+            break;
+        }
+        let record = record?;
+        let arrival = clock;
+        clock += record.next_inter;
+        stats.scanned += 1;
+
+        if !filter.matches(index, arrival, &record) {
+            continue;
+        }
+        stats.kept += 1;
+        if record.op == Op::Read {
+            stats.read_op += 1;
+            stats.read_data += record.len_sectors;
+        } else {
+            stats.write_op += 1;
+            stats.write_data += record.len_sectors;
+        }
+        stats.first_arrival.get_or_insert(arrival);
+        stats.last_arrival = Some(arrival);
+        writer.write_record(arrival, record)?;
+    }
+
+    stats.kept_ratio = ratio(stats.kept, stats.scanned);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::TraceWriter;
+
+    #[test]
+    fn test_filter_records() {
+        let input = "W Hit 0 8 0.000000 1.000000\n\
+            R Hit 8 16 0.000000 2.000000\n\
+            W Hit 2048 8 0.000000 3.000000\n\
+            R Hit 24 8 0.000000 4.000000\n\
+            R Hit 32 8 0.000000 5.000000\n";
+
+        let run = |filter: &Filter| {
+            let mut writer = RetimingWriter::new(TraceWriter::new(Vec::new()));
+            let stats = filter_records(TraceReader::new(input.as_bytes()), &mut writer, filter).unwrap();
+            let output = writer.into_inner().unwrap();
+            let records: Vec<TraceRecord> = TraceReader::new(&output[..]).collect::<Result<_, _>>().unwrap();
+            (stats, records)
+        };
+
+        // 到达时间为 0、1、3、6、10，读请求的时间间隔重新计算
+        let (stats, records) = run(&Filter { op: Some(OpMix::R), ..Filter::default() });
+        assert_eq!((stats.scanned, stats.kept, stats.read_data), (5, 3, 32));
+        let kept: Vec<_> = records.iter().map(|r| (r.offset_sectors, r.next_inter)).collect();
+        assert_eq!(kept, [(8, 5.0), (24, 4.0), (32, 5.0)]);
+
+        let (_, records) = run(&Filter { offset: Some((0, 1024)), length: Some((8, 8)), ..Filter::default() });
+        let kept: Vec<_> = records.iter().map(|r| r.offset_sectors).collect();
+        assert_eq!(kept, [0, 24, 32]);
+
+        // 超出时间窗口或序号范围后提前结束
+        let (stats, records) = run(&Filter { time: Some((1.0, 6.0)), ..Filter::default() });
+        assert_eq!((stats.scanned, records.len(), stats.first_arrival, stats.last_arrival), (3, 2, Some(1.0), Some(3.0)));
+        let (stats, records) = run(&Filter { index: Some((1, 2)), ..Filter::default() });
+        assert_eq!((stats.scanned, records.len()), (3, 2));
+    }
+}
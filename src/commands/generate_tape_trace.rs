@@ -1,12 +1,8 @@
-use std::{
-    fs::{self, remove_file, OpenOptions},
-    io::Write,
-    vec,
-};
+use std::{fmt, fs, io::Write};
 
-use log::{debug, error, info};
-use rand::{rngs::ThreadRng, Rng};
-use rand_distr::Distribution;
+use log::info;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 /// 生成的 trace 包含以下参数：
 ///
@@ -14,6 +10,8 @@ use rand_distr::Distribution;
 ///
 /// rwrate(读写比例，读:写): a:b, 支持小数，若某一个为 0(例如 rwrate=0:5, 和 rwrate=0:1 等价)，则表示全为写操作
 ///
+/// rwrate_by(读写比例的计算方式): op 按请求数量(默认)，data 按数据量
+///
 /// write_size(写操作大小范围): 例如 write_size=1G-32G, 需要加上单位(忽略大小写)
 ///
 /// read_size(读操作大小范围): 例如 read_size=12M-1G, 需要加上单位(忽略大小写)
@@ -24,22 +22,43 @@ use rand_distr::Distribution;
 ///
 /// batch_size(设定每个 batch 的大小范围)=512M-8G, 表示每个 batch 大小为 1G
 ///
-use crate::{error::HMSimError, Dist};
+use crate::{
+    error::HMSimError,
+    output::{ratio, ratio_text, Report},
+    trace::{open_output, Op, TraceRecord, TraceWriter},
+    config::TapeTraceConfig,
+    utils::seeded_rng,
+    Dist, TraceRng,
+};
 // #[warn(dead_code)]
 
 /// 通过子命令参数转化成的 TapeTrace 结构体
 ///
 /// 大小单位全部转化为 block(512B)
-#[derive(Debug, Clone)]
+///
+/// 序列化为完整的 `TapeTraceConfig`，即与命令行参数同名、写法相同的配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "TapeTraceConfig", try_from = "TapeTraceConfig")]
 pub struct TapeTrace {
+    /// 阶段名，只用于多阶段负载
+    pub name: String,
+
+    /// 读写操作的总大小，以及请求数量和时长(单位: 秒)，为 0 表示不限制，用完其中任意一个即停止
     pub total_size: u64,
+    pub count: u64,
+    pub duration: f64,
+
+    /// 读写比例(读:写)，两者均为 0 表示未指定，此时 rw=rw 时读写各占一半
+    pub read_rate: f32,
+    pub write_rate: f32,
+    /// 读写比例按请求数量还是按数据量计算
+    pub rwrate_by: RateBasis,
 
-    // pub read_rate: f32,
-    // pub write_rate: f32,
     pub block_size: u64,
-    pub rw: String,
-    pub read_order: String,
-    pub write_order: String,
+    /// 生成的请求类型，为 None 表示未指定，此时由读写比例推断
+    pub rw: Option<OpMix>,
+    pub read_order: AccessOrder,
+    pub write_order: AccessOrder,
 
     pub write_offset: u64,
 
@@ -55,7 +74,7 @@ pub struct TapeTrace {
     pub rwsize_end: u64,
     pub rwsize_range: u64,
 
-    pub batch: String,
+    pub batch: BatchMode,
     pub batch_iow_num_begin: u64,
     pub batch_iow_num_end: u64,
     pub batch_iow_num_range: u64,
@@ -65,18 +84,52 @@ pub struct TapeTrace {
     pub batch_ior_num_range: u64,
     pub time_interval_dist: Dist,
     pub req_length_dist: Dist,
+    /// 随机读请求的寻道距离的分布，为 None 时在已写区域内均匀随机
+    pub seek_dist: Dist,
+
+    /// ro/wo=zipf 时 Zipf 分布的参数 theta，越大访问越集中
+    pub zipf_theta: f64,
+    /// ro/wo=hotcold 时热数据占地址空间的比例，以及访问热数据的概率
+    pub hot_fraction: f64,
+    pub hot_probability: f64,
+    /// zipf 和 hotcold 划分地址空间的粒度(单位: 扇区)，为 0 时与 block_size 相同
+    pub extent_size: u64,
+    /// 非顺序写请求覆盖写的区域 [0, write_region)(单位: 扇区)，为 0 时与 write_offset 相同
+    pub write_region: u64,
+
+    /// 随机数种子，为 None 时随机生成一个
+    pub seed: Option<u64>,
+
+    /// 输出的 trace 文件，`-` 表示 stdout
+    pub output: String,
+    /// 输出文件已经存在时是否覆盖
+    pub force: bool,
+
+    /// 多阶段负载，不为空时依次生成每个阶段，每个阶段都是完整的参数
+    pub phases: Vec<TapeTrace>,
+}
+
+impl Default for TapeTrace {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TapeTrace {
     pub fn new() -> TapeTrace {
         TapeTrace {
+            name: String::new(),
             total_size: 0,
-            // read_rate: 0.0,
-            // write_rate: 0.0,
+            count: 0,
+            duration: 0.0,
+            read_rate: 0.0,
+            write_rate: 0.0,
+            rwrate_by: RateBasis::Op,
             block_size: 0,
-            rw: String::new(),
-            read_order: String::new(),
-            write_order: String::new(),
+            rw: None,
+            read_order: AccessOrder::Rand,
+            // 磁带只支持追加写，默认为顺序写
+            write_order: AccessOrder::Seq,
             write_offset: 0,
             write_size_start: 0,
             write_size_end: 0,
@@ -87,7 +140,7 @@ impl TapeTrace {
             rwsize_start: 0,
             rwsize_end: 0,
             rwsize_range: 0,
-            batch: String::new(),
+            batch: BatchMode::None,
             batch_iow_num_begin: 0,
             batch_iow_num_end: 0,
             batch_iow_num_range: 0,
@@ -95,119 +148,137 @@ impl TapeTrace {
             batch_ior_num_end: 0,
             batch_ior_num_range: 0,
             time_interval_dist: Dist::None,
-            req_length_dist: Dist::None
+            req_length_dist: Dist::None,
+            seek_dist: Dist::None,
+            zipf_theta: 0.99,
+            hot_fraction: 0.2,
+            hot_probability: 0.8,
+            extent_size: 0,
+            write_region: 0,
+            seed: None,
+            output: String::from("tape.trace"),
+            force: false,
+            phases: vec![],
         }
     }
 
-    /// 生成读写请求，返回 (op_num, return_size)
-    fn operation(&self, rand: &mut ThreadRng, trace: &TapeTrace, rw: &str, cur_write_offset: &mut u64, cur_read_offset: &mut u64) -> (u64, u64) {
+    /// 生成读写请求，一个 batch 最多生成 max_ops 个请求，返回实际写出的 (op_num, return_size)
+    fn operation<W: Write>(&self, state: &mut GenState<W>, trace: &TapeTrace, op: Op, max_ops: u64) -> Result<(u64, u64), HMSimError> {
         let mut op_num = 0;
-        if rw == "R" {
-            if *cur_write_offset == 0 {
-                return (0, 0);
+        if op == Op::Read {
+            if state.cur_write_offset == 0 {
+                return Ok((0, 0));
             }
-            if self.batch.contains("r") {
+            if self.batch.reads() {
                 // debug!("batch_ior_num_begin: {}, batch_ior_num_begin: {}", self.batch_ior_num_begin, self.batch_ior_num_end);
                 // 随机生成一个 batch 大小
                 let mut op_num_per_batch =
-                    rand.gen_range(self.batch_ior_num_begin..=self.batch_ior_num_end);
+                    state.rand.gen_range(self.batch_ior_num_begin..=self.batch_ior_num_end).min(max_ops);
                 // debug!("op_num_per_batch: {}", op_num_per_batch);
                 
                 let mut return_size = 0;
                 while op_num_per_batch > 0 {
-                    let blocksize = self.generate_one(rand, trace, "R", cur_write_offset, cur_read_offset);
-                    op_num += 1;
+                    let blocksize = self.generate_one(state, trace, Op::Read)?;
+                    // 请求大小为 0 时没有写出请求，不计入请求数量
+                    op_num += u64::from(blocksize > 0);
                     return_size += blocksize;
                     op_num_per_batch -= 1;
                 }
-                return (op_num, return_size);
+                Ok((op_num, return_size))
             } else {
-                return (1, self.generate_one(rand, trace, "R", cur_write_offset, cur_read_offset));
+                let blocksize = self.generate_one(state, trace, Op::Read)?;
+                Ok((u64::from(blocksize > 0), blocksize))
             }
-        } else if rw == "W" {
-            if self.batch.contains("w") {
+        } else {
+            if self.batch.writes() {
                 // 随机生成一个 batch 大小，按照 ALIEN 对齐
                 let mut op_num_per_batch =
-                rand.gen_range(self.batch_iow_num_begin..=self.batch_iow_num_end);
+                state.rand.gen_range(self.batch_iow_num_begin..=self.batch_iow_num_end).min(max_ops);
                 // debug!("op_num_per_batch: {}", op_num_per_batch);
                 
                 let mut return_size = 0;
                 while op_num_per_batch > 0 {
-                    let blocksize = self.generate_one(rand, trace, "W", cur_write_offset, cur_read_offset);
-                    op_num += 1;
+                    let blocksize = self.generate_one(state, trace, Op::Write)?;
+                    // 请求大小为 0 时没有写出请求，不计入请求数量
+                    op_num += u64::from(blocksize > 0);
                     return_size += blocksize;
                     op_num_per_batch -= 1;
                 }
                 // debug!("return size: {}", return_size);
-                return (op_num, return_size);
+                Ok((op_num, return_size))
             } else {
-                return (1, self.generate_one(rand, trace, "W", cur_write_offset, cur_read_offset));
+                let blocksize = self.generate_one(state, trace, Op::Write)?;
+                Ok((u64::from(blocksize > 0), blocksize))
             }
         }
-        (0, 0)
     }
 
-    /// 生成一条请求的请求大小和请求偏移，返回生成的请求大小
+    /// 生成一条请求的请求大小和请求偏移，返回生成的请求大小，为 0 时没有写出请求
     /// 首先看是读操作还是写操作
     /// 再看 read_order/write_order 是随机还是顺序
     /// 然后看是否有 batch
-    fn generate_one(&self, rand: &mut ThreadRng, trace: &TapeTrace, rw: &str, cur_write_offset: &mut u64, cur_read_offset: &mut u64) -> u64 {
-        if rw == "R" {
+    fn generate_one<W: Write>(&self, state: &mut GenState<W>, trace: &TapeTrace, op: Op) -> Result<u64, HMSimError> {
+        if op == Op::Read {
             let mut read_blocksize;
             match trace.req_length_dist {
                 // 随机生成一个读请求大小
                 Dist::None => {
-                    read_blocksize = rand.gen_range(self.read_size_start..=self.read_size_end) * self.block_size;
+                    read_blocksize = state.rand.gen_range(self.read_size_start..=self.read_size_end) * self.block_size;
                     // debug!("read_size: {}-{}", self.read_size_start, self.read_size_end);
                 },
                 // 根据数学分布生成请求大小
                 _ => {
-                    // info!("Genereate Exp req");
-                    let exp = get_timeinteval_from_distribution(&trace.req_length_dist);
-                    read_blocksize = exp as u64 / self.block_size * self.block_size;
-                    if read_blocksize == 0 {
-                        // error!("req_len can't be zero!!!");
-                        return 0;
-                    }
+                    read_blocksize = get_req_length_from_distribution(&trace.req_length_dist, &mut state.rand, self.block_size);
                 }
             }
+            if read_blocksize == 0 {
+                // error!("req_len can't be zero!!!");
+                return Ok(0);
+            }
 
             // 要生成的地址的起始地址
             // let addr_begin = 263680000;
             let addr_begin = 0;
 
-            // 根据读写的顺序参数(rand or seq)生成请求偏移量
-            let mut read_offset = 0;
+            // 根据读写的顺序参数(rand, seq, zipf, hotcold)生成请求偏移量
+            let read_offset;
             // debug!("read_order: {}", trace.read_order);
-            if trace.read_order.eq_ignore_ascii_case("rand") {
-                read_offset = rand.gen_range(addr_begin..*cur_write_offset) / self.block_size * self.block_size;
-            } else if trace.read_order.eq_ignore_ascii_case("seq") {
-                read_offset = *cur_read_offset;
-                *cur_read_offset = *cur_read_offset + read_blocksize;
+            if trace.read_order == AccessOrder::Rand && !matches!(trace.seek_dist, Dist::None) {
+                // 从上一个请求的结束地址按寻道距离跳转，超出已写区域时回绕
+                let seek = trace.seek_dist.sample(&mut state.rand) as i128;
+                let offset = (state.last_end as i128 + seek).rem_euclid(state.cur_write_offset as i128) as u64;
+                read_offset = offset / self.block_size * self.block_size;
+            } else if trace.read_order == AccessOrder::Seq {
+                // 顺序读 [0, size)，读到末尾后回到 0 重新开始，不生成长度为 0 的请求
+                if state.cur_read_offset >= trace.total_size {
+                    state.cur_read_offset = 0;
+                }
+                read_offset = state.cur_read_offset;
+                read_blocksize = read_blocksize.min(trace.total_size - read_offset);
+                state.cur_read_offset += read_blocksize;
+            } else {
+                // rand、zipf、hotcold 在已经写过的区域内选择偏移量
+                read_offset = addr_begin + self.pick_offset(state, trace.read_order, state.cur_write_offset - addr_begin);
             }
 
             // debug!("read_offset: {}", read_offset);
-            if trace.read_order.eq_ignore_ascii_case("seq") {
-                if read_blocksize + read_offset > trace.total_size {
-                    // debug!("trace.total_size - read_offset: {}", trace.total_size - read_offset);
-                    // debug!("trace.total_size: {}", trace.total_size);
-                    // debug!("read_offset: {}", read_offset);
-                    // debug!("read_blocksize: {}", read_blocksize);
-                    read_blocksize = trace.total_size - read_offset;
-                }
-            } else if trace.read_order.eq_ignore_ascii_case("rand") {
-                if read_blocksize + read_offset > *cur_write_offset {
-                    read_blocksize = *cur_write_offset - read_offset;
-                }
+            if trace.read_order != AccessOrder::Seq && read_blocksize + read_offset > state.cur_write_offset {
+                read_blocksize = state.cur_write_offset - read_offset;
+            }
+
+            Self::write_to_file(state, Op::Read, read_offset, read_blocksize, trace)?;
+
+            Ok(read_blocksize)
+        } else {
+            let mut write_blocksize = match trace.req_length_dist {
+                // 随机生成一个写请求大小
+                Dist::None => state.rand.gen_range(self.write_size_start..=self.write_size_end) * self.block_size,
+                // 根据数学分布生成请求大小
+                _ => get_req_length_from_distribution(&trace.req_length_dist, &mut state.rand, self.block_size),
+            };
+            if write_blocksize == 0 {
+                return Ok(0);
             }
-            
-            Self::write_to_file("R", read_offset, read_blocksize, trace);
-            
-            return read_blocksize;
-        } else if rw == "W" {
-            // 随机生成一个写请求大小
-            let write_blocksize =
-            rand.gen_range(self.write_size_start..=self.write_size_end) * self.block_size;
             // debug!("write_size: {}-{}", self.write_size_start, self.write_size_end);
             // debug!("block_size: {}", self.block_size);
 
@@ -216,100 +287,524 @@ impl TapeTrace {
 
             // debug!("write_blocksize: {}", write_blocksize);
 
-            Self::write_to_file("W", *cur_write_offset, write_blocksize, trace);
+            let write_offset;
+            if trace.write_order == AccessOrder::Seq {
+                write_offset = state.cur_write_offset;
+                state.cur_write_offset += write_blocksize;
+            } else {
+                // rand、zipf、hotcold 在 [0, write_region) 内覆盖写，请求不超出该区域
+                let region = self.write_region();
+                write_offset = self.pick_offset(state, trace.write_order, region);
+                write_blocksize = write_blocksize.min(region - write_offset);
+                state.cur_write_offset = state.cur_write_offset.max(write_offset + write_blocksize);
+            }
+            Self::write_to_file(state, Op::Write, write_offset, write_blocksize, trace)?;
 
-            *cur_write_offset += write_blocksize;
             // debug!("cur_offset: {}", cur_offset);
 
-            return write_blocksize;
+            Ok(write_blocksize)
         }
-        0
     }
 
-    /// 将生成的请求写入 trace 文件，时间间隔(包含时间间隔的分布)在写文件时生成
-    fn write_to_file(rw: &str, offset: u64, blocksize: u64, trace: &TapeTrace) {
-        let mut output_file = OpenOptions::new()
-            .append(true)
-            .create(true)
-            // .write(true)
-            .open("tape.trace")
-            .unwrap();
+    /// 生成前检查所有参数，不合法时返回说明原因的错误，有多个阶段时依次检查每个阶段
+    pub fn validate(&self) -> Result<(), HMSimError> {
+        let phases = if self.phases.is_empty() { std::slice::from_ref(self) } else { self.phases.as_slice() };
+
+        // 每个阶段可以读 woff 之前的数据，以及之前阶段写过的数据
+        let mut written = false;
+        for phase in phases {
+            written |= phase.write_offset > 0;
+            phase.validate_phase(written).map_err(|e| match e {
+                HMSimError::CommandError(msg) if !self.phases.is_empty() => {
+                    HMSimError::CommandError(format!("阶段 {}: {}", phase.name, msg))
+                },
+                e => e,
+            })?;
+            written |= phase.has_op(Op::Write) || (phase.has_op(Op::Read) && phase.read_order == AccessOrder::Seq && phase.total_size > 0);
+        }
+        Ok(())
+    }
+
+    /// 检查一个阶段的参数，`written` 表示该阶段开始时是否已经有写过的数据可以读
+    fn validate_phase(&self, written: bool) -> Result<(), HMSimError> {
+        if self.block_size == 0 {
+            return Err(HMSimError::CommandError(String::from("需要指定 blk_size")));
+        }
+        if self.total_size == 0 && self.count == 0 && self.duration <= 0.0 {
+            return Err(HMSimError::CommandError(String::from("需要指定 size、count 或 duration 中的至少一个")));
+        }
+        if !(self.duration.is_finite() && self.duration >= 0.0) {
+            return Err(HMSimError::CommandError(format!("duration 应为非负数，实际为 {}", self.duration)));
+        }
+        // 模拟时钟由时间间隔累加，不指定时间间隔的分布时时钟不会前进
+        if self.total_size == 0 && self.count == 0 && matches!(self.time_interval_dist, Dist::None) {
+            return Err(HMSimError::CommandError(String::from("只指定 duration 时需要指定 time_dist 或 iops")));
+        }
+
+        let Some(rw) = self.rw else {
+            return Err(HMSimError::CommandError(String::from("需要指定 rw 或 rwrate")));
+        };
+
+        // 读写比例需要与 rw 一致
+        for (name, rate) in [("读", self.read_rate), ("写", self.write_rate)] {
+            if !(rate.is_finite() && rate >= 0.0) {
+                return Err(HMSimError::CommandError(format!("rwrate 中{}的比例应为非负数，实际为 {}", name, rate)));
+            }
+        }
+        if (self.read_rate > 0.0 && !rw.reads()) || (self.write_rate > 0.0 && !rw.writes()) {
+            return Err(HMSimError::CommandError(format!(
+                "rwrate={}:{} 与 rw={} 冲突",
+                self.read_rate, self.write_rate, rw
+            )));
+        }
+
+        for (name, start, end) in [
+            ("wsize", self.write_size_start, self.write_size_end),
+            ("rsize", self.read_size_start, self.read_size_end),
+            ("rwsize", self.rwsize_start, self.rwsize_end),
+            ("batch_IOw_num", self.batch_iow_num_begin, self.batch_iow_num_end),
+            ("batch_IOr_num", self.batch_ior_num_begin, self.batch_ior_num_end),
+        ] {
+            if start > end {
+                return Err(HMSimError::CommandError(format!("{} 的范围 {}-{} 不合法，起始值不能大于结束值", name, start, end)));
+            }
+        }
+
+        // 不指定请求大小的分布时按 rsize/wsize 生成请求，范围为 0-0 时只会生成大小为 0 的请求
+        if matches!(self.req_length_dist, Dist::None) {
+            if rw.reads() && self.read_size_end == 0 {
+                return Err(HMSimError::CommandError(String::from("rw 包含 r 时需要指定 rsize 或 req_dist")));
+            }
+            if rw.writes() && self.write_size_end == 0 {
+                return Err(HMSimError::CommandError(String::from("rw 包含 w 时需要指定 wsize 或 req_dist")));
+            }
+        }
+        if rw.reads() && self.batch.reads() && self.batch_ior_num_begin == 0 {
+            return Err(HMSimError::CommandError(String::from("batch 包含 r 时需要指定 batch_IOr_num，且最小值不小于 1")));
+        }
+        if rw.writes() && self.batch.writes() && self.batch_iow_num_begin == 0 {
+            return Err(HMSimError::CommandError(String::from("batch 包含 w 时需要指定 batch_IOw_num，且最小值不小于 1")));
+        }
+
+        // 读请求只在已经写过的区域内生成，顺序读的范围为 [0, size]
+        if rw.reads() {
+            if self.read_order == AccessOrder::Seq {
+                if self.total_size == 0 {
+                    return Err(HMSimError::CommandError(String::from("ro=seq 时需要指定 size 作为顺序读的范围")));
+                }
+            } else if !written && !rw.writes() {
+                return Err(HMSimError::CommandError(format!(
+                    "rw={} 且 ro={} 时需要指定不为 0 的 woff，否则没有可以读的数据",
+                    rw, self.read_order
+                )));
+            }
+        }
+
+        // 直接构造的 TapeTrace 不经过 dist_analyze，这里再检查一次分布参数
+        for (name, dist) in [("time_dist", &self.time_interval_dist), ("req_dist", &self.req_length_dist), ("seek_dist", &self.seek_dist)] {
+            if !dist.is_valid() {
+                return Err(HMSimError::CommandError(format!("{} 的参数不合法: {:?}", name, dist)));
+            }
+        }
+
+        // 访问局部性的参数
+        if !(self.zipf_theta.is_finite() && self.zipf_theta >= 0.0) {
+            return Err(HMSimError::CommandError(format!("zipf_theta 应为非负数，实际为 {}", self.zipf_theta)));
+        }
+        if !(self.hot_fraction > 0.0 && self.hot_fraction <= 1.0) {
+            return Err(HMSimError::CommandError(format!("hot_frac 应在 (0, 1] 内，实际为 {}", self.hot_fraction)));
+        }
+        if !(0.0..=1.0).contains(&self.hot_probability) {
+            return Err(HMSimError::CommandError(format!("hot_prob 应在 [0, 1] 内，实际为 {}", self.hot_probability)));
+        }
+        if rw.writes() && self.write_order != AccessOrder::Seq && self.write_region() == 0 {
+            return Err(HMSimError::CommandError(format!("wo={} 时需要指定 wregion 或 woff 作为覆盖写的区域", self.write_order)));
+        }
+        Ok(())
+    }
+
+    /// 是否会生成读或写请求
+    fn has_op(&self, op: Op) -> bool {
+        match op {
+            Op::Read => self.rw.is_some_and(OpMix::reads),
+            Op::Write => self.rw.is_some_and(OpMix::writes),
+        }
+    }
 
-        let mut req = vec![];
+    /// 该阶段的数据量、请求数量和时长(为 0 表示不限制)中已经用完的一个，都没有用完时返回 None，`clock` 为当前模拟时钟
+    fn exhausted_budget(&self, stats: &PhaseStats, clock: f64) -> Option<Budget> {
+        if self.total_size > 0 && stats.read_data + stats.write_data >= self.total_size {
+            Some(Budget::Size)
+        } else if self.count > 0 && stats.requests >= self.count {
+            Some(Budget::Count)
+        } else if self.duration > 0.0 && clock - stats.start_time >= self.duration * 1000.0 {
+            Some(Budget::Duration)
+        } else {
+            None
+        }
+    }
 
-        // 模拟器 trace 第一个参数: 读写
-        req.push(rw);
+    /// 非顺序写请求覆盖写的区域大小
+    fn write_region(&self) -> u64 {
+        if self.write_region > 0 {
+            self.write_region
+        } else {
+            self.write_offset
+        }
+    }
 
-        // 模拟器 trace 第二个参数: Hit
-        req.push("Hit");
+    /// 按照 rand、zipf 或 hotcold 在 [0, region) 内选择一个按 block_size 对齐的偏移量
+    ///
+    /// zipf 和 hotcold 先把地址空间按 extent_size 划分为若干个 extent，选出 extent 后在其中均匀随机，
+    /// zipf 中地址越小的 extent 越热，hotcold 中地址最小的 hot_fraction 部分为热数据
+    fn pick_offset<W: Write>(&self, state: &mut GenState<W>, order: AccessOrder, region: u64) -> u64 {
+        let extent = if self.extent_size > 0 { self.extent_size.min(region) } else { self.block_size.min(region) };
+        let extent_num = (region / extent).max(1);
 
-        // 模拟器 trace 第三个参数: 偏移量
-        let tmp = offset.to_string();
-        req.push(tmp.as_str());
+        let extent_index = match order {
+            AccessOrder::Zipf => {
+                let zipf = rand_distr::Zipf::new(extent_num, self.zipf_theta).expect("zipf_theta 应已通过校验");
+                state.rand.sample(zipf) as u64 - 1
+            },
+            AccessOrder::Hotcold => {
+                let hot_num = ((extent_num as f64 * self.hot_fraction).ceil() as u64).clamp(1, extent_num);
+                if hot_num == extent_num || state.rand.gen_bool(self.hot_probability) {
+                    state.rand.gen_range(0..hot_num)
+                } else {
+                    state.rand.gen_range(hot_num..extent_num)
+                }
+            },
+            // 顺序访问不经过该函数
+            AccessOrder::Rand | AccessOrder::Seq => {
+                return state.rand.gen_range(0..region) / self.block_size * self.block_size;
+            },
+        };
 
-        // 模拟器 trace 第四个参数: 长度
-        let tmp = blocksize.to_string();
-        req.push(tmp.as_str());
+        // 最后一个 extent 包含 region 末尾不足一个 extent 的部分
+        let start = extent_index * extent;
+        let end = if extent_index == extent_num - 1 { region } else { start + extent };
+        state.rand.gen_range(start..end) / self.block_size * self.block_size
+    }
 
-        // 模拟器 trace 第五个参数: 服务时间
-        req.push("0.000000");
+    /// 将生成的请求写入 trace 文件，时间间隔(包含时间间隔的分布)在写文件时生成
+    fn write_to_file<W: Write>(state: &mut GenState<W>, op: Op, offset: u64, blocksize: u64, trace: &TapeTrace) -> Result<(), HMSimError> {
+        // 模拟器 trace 的 Hit 和服务时间两个参数使用默认值
+        let mut record = TraceRecord::new(op, offset, blocksize);
 
         // 模拟器 trace 第六个参数: 时间间隔
-        let mut time_interval = String::from("0.000000");
         match trace.time_interval_dist {
-            Dist::None => {
-                req.push(time_interval.as_str());
-            },
+            Dist::None => {},
             _ => {
-                time_interval = get_timeinteval_from_distribution(&trace.time_interval_dist).to_string()[0..=7].to_string();
-                req.push(time_interval.as_str());
+                record.next_inter = get_timeinteval_from_distribution(&trace.time_interval_dist, &mut state.rand);
+            }
+        }
+
+        state.last_end = record.end_sectors();
+        state.requests += 1;
+        state.clock += record.next_inter;
+        state.writer.write_record(&record)
+    }
+
+    /// 读请求的平均大小(单位: 扇区)，请求大小分布的期望不存在时按 rsize 计算
+    fn avg_read_size(&self) -> f64 {
+        self.req_length_dist
+            .mean()
+            .unwrap_or_else(|| mean(&[self.read_size_start, self.read_size_end]).unwrap() as f64 * self.block_size as f64)
+    }
+
+    /// 写请求的平均大小(单位: 扇区)，请求大小分布的期望不存在时按 wsize 计算
+    fn avg_write_size(&self) -> f64 {
+        self.req_length_dist
+            .mean()
+            .unwrap_or_else(|| mean(&[self.write_size_start, self.write_size_end]).unwrap() as f64 * self.block_size as f64)
+    }
+
+    /// 在考虑 batch 和读写请求大小不同的情况下，计算每次选择读操作的概率
+    ///
+    /// 一次读操作平均生成 nr 个请求、每个请求平均 sr 个扇区，写操作为 nw、sw，
+    /// 读概率为 p 时，按请求数量的读写比为 p * nr : (1 - p) * nw，按数据量为 p * nr * sr : (1 - p) * nw * sw
+    fn recalculate_rwrate(&self) -> f64 {
+        let mut read_weight = 1.0;
+        let mut write_weight = 1.0;
+
+        if self.batch.reads() {
+            read_weight *= mean(&[self.batch_ior_num_begin, self.batch_ior_num_end]).unwrap() as f64;
+        }
+        if self.batch.writes() {
+            write_weight *= mean(&[self.batch_iow_num_begin, self.batch_iow_num_end]).unwrap() as f64;
+        }
+
+        if self.rwrate_by == RateBasis::Data {
+            read_weight *= self.avg_read_size();
+            write_weight *= self.avg_write_size();
+        }
+
+        let read = self.read_rate as f64 * write_weight;
+        let write = self.write_rate as f64 * read_weight;
+        if read + write > 0.0 && read.is_finite() && write.is_finite() {
+            read / (read + write)
+        } else {
+            0.5
+        }
+    }
+
+    /// 目标读比例(读 / (读 + 写))，未指定读写比例时返回 None
+    fn target_read_share(&self) -> Option<f64> {
+        let total = self.read_rate as f64 + self.write_rate as f64;
+        if total > 0.0 {
+            Some(self.read_rate as f64 / total)
+        } else {
+            None
+        }
+    }
+}
+
+/// 生成的请求类型(rw)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpMix {
+    /// 只有读请求
+    R,
+    /// 只有写请求
+    W,
+    /// 读写混合
+    Rw,
+}
+
+impl OpMix {
+    pub fn reads(self) -> bool {
+        matches!(self, OpMix::R | OpMix::Rw)
+    }
+
+    pub fn writes(self) -> bool {
+        matches!(self, OpMix::W | OpMix::Rw)
+    }
+}
+
+/// 读写请求的访问顺序(ro/wo)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessOrder {
+    /// 在区域内均匀随机
+    Rand,
+    /// 顺序读，或顺序追加写
+    Seq,
+    /// 按 extent 服从 Zipf 分布，地址越小越热
+    Zipf,
+    /// 按 hot_frac 和 hot_prob 区分冷热数据
+    Hotcold,
+}
+
+/// 连续生成一批相同操作请求(batch)的请求类型
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchMode {
+    /// 不成批生成
+    #[default]
+    None,
+    /// 读请求成批生成
+    R,
+    /// 写请求成批生成
+    W,
+    /// 读写请求都成批生成
+    Rw,
+}
+
+impl BatchMode {
+    pub fn reads(self) -> bool {
+        matches!(self, BatchMode::R | BatchMode::Rw)
+    }
+
+    pub fn writes(self) -> bool {
+        matches!(self, BatchMode::W | BatchMode::Rw)
+    }
+}
+
+/// 与命令行中的写法相同
+macro_rules! display_value_enum {
+    ($($name:ty),*) => {
+        $(impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let value = clap::ValueEnum::to_possible_value(self).expect("没有跳过的取值");
+                f.write_str(value.get_name())
             }
+        })*
+    };
+}
+
+display_value_enum!(OpMix, AccessOrder, BatchMode, RateBasis);
+
+/// 读写比例的计算方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateBasis {
+    /// 按请求数量
+    #[default]
+    Op,
+    /// 按数据量
+    Data,
+}
+
+// 读写比例的反馈系数：实际读比例每偏离目标 1%，读概率修正 4%
+const RWRATE_FEEDBACK: f64 = 4.0;
+
+// 连续这么多轮都没有消耗任何结束条件(数据量、请求数量或时长)时，认为生成过程无法结束
+const MAX_STALLED_ROUNDS: u32 = 10_000;
+
+/// 生成过程中需要修改的状态
+struct GenState<W: Write> {
+    rand: TraceRng,
+    writer: TraceWriter<W>,
+    // 顺序写请求已经写到的偏移量
+    cur_write_offset: u64,
+    // 顺序读请求已经读到的偏移量
+    cur_read_offset: u64,
+    // 上一个请求的结束地址，用于按寻道距离生成读请求
+    last_end: u64,
+    // 已经生成的请求数量
+    requests: u64,
+    // 模拟的时钟，即已生成请求的时间间隔之和(单位与 nextinter 相同，毫秒)
+    clock: f64,
+}
+
+/// generate-tape-trace 子命令输出的统计信息，数据量单位为扇区(512B)
+///
+/// 比例的分母为 0 时为 null
+#[derive(Debug, Clone, Serialize)]
+pub struct TapeTraceStats {
+    /// 生成时使用的随机数种子，用它可以复现同样的 trace
+    pub seed: u64,
+    pub read_op: u64,
+    pub write_op: u64,
+    pub read_data: u64,
+    pub write_data: u64,
+    /// 写请求数量与读请求数量之比
+    pub op_rate_w_r: Option<f64>,
+    /// 写数据量与读数据量之比
+    pub data_rate_w_r: Option<f64>,
+    /// trace 中的请求数量，以及模拟时长(单位: 秒，即所有请求的时间间隔之和)
+    pub requests: u64,
+    pub duration: f64,
+    /// 因为哪个条件用完而结束，多阶段负载中为最后一个阶段的结束条件
+    pub stopped_by: Budget,
+    /// 多阶段负载中每个阶段的统计信息及边界，没有多阶段负载时不输出
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub phases: Vec<PhaseStats>,
+}
+
+/// 结束生成的条件
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Budget {
+    /// 数据量(size)
+    #[default]
+    Size,
+    /// 请求数量(count)
+    Count,
+    /// 模拟时长(duration)
+    Duration,
+}
+
+impl fmt::Display for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Budget::Size => f.write_str("size"),
+            Budget::Count => f.write_str("count"),
+            Budget::Duration => f.write_str("duration"),
+        }
+    }
+}
+
+/// 一个阶段的统计信息，请求序号从 0 开始，时间为模拟时钟(单位: 毫秒)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PhaseStats {
+    pub name: String,
+    /// 该阶段第一个请求在 trace 中的序号，以及该阶段的请求数量
+    pub first_request: u64,
+    pub requests: u64,
+    /// 该阶段开始和结束时的模拟时钟
+    pub start_time: f64,
+    pub end_time: f64,
+    pub read_op: u64,
+    pub write_op: u64,
+    pub read_data: u64,
+    pub write_data: u64,
+    /// 该阶段因为哪个条件用完而结束
+    pub stopped_by: Budget,
+}
+
+impl TapeTraceStats {
+    pub fn new(seed: u64, read_op: u64, write_op: u64, read_data: u64, write_data: u64) -> TapeTraceStats {
+        TapeTraceStats {
+            seed,
+            read_op,
+            write_op,
+            read_data,
+            write_data,
+            op_rate_w_r: ratio(write_op, read_op),
+            data_rate_w_r: ratio(write_data, read_data),
+            requests: 0,
+            duration: 0.0,
+            stopped_by: Budget::default(),
+            phases: vec![],
         }
+    }
+}
+
+/// 文本格式为可以直接作为 `--config` 使用的 TOML
+impl Report for TapeTrace {
+    fn to_text(&self) -> String {
+        toml::to_string(self).expect("TapeTraceConfig 总是可以序列化为 TOML")
+    }
+}
 
-        output_file.write_all(req.join(" ").as_bytes()).unwrap();
-        output_file.write_all("\n".as_bytes()).unwrap();
-    }
-
-    // /// 如果有 batch 操作，在考虑 batch 的情况下重新计算读写比
-    // fn recalculate_rwrate(&mut self) {
-    //     let avg_write_size = mean([self.write_size_start, self.write_size_end].as_slice()).unwrap();
-
-    //     let avg_read_size = mean([self.read_size_start, self.read_size_end].as_slice()).unwrap();
-
-    //     if self.batch.contains("w") {
-    //         let avg_batch_write_size =
-    //             mean([self.batch_write_size_begin, self.batch_write_size_end].as_slice()).unwrap();
-    //         self.read_rate *= (avg_batch_write_size / avg_write_size) as f32;
-    //     }
-
-    //     if self.batch.contains("r") {
-    //         let avg_batch_read_size =
-    //             mean([self.batch_read_size_begin, self.batch_read_size_end].as_slice()).unwrap();
-    //         // self.write_rate *= (avg_batch_read_size / avg_read_size) as f32 / 5f32 * 3.25;
-    //         self.write_rate *= (avg_batch_read_size / avg_read_size) as f32;
-    //     }
-    // }
-
-    // /// 将读写比归一化
-    // fn rwrate_in_one(&mut self) {
-    //     let min_num = self.write_rate.min(self.read_rate);
-    //     if self.write_rate > 0.0 && self.read_rate > 0.0 {
-    //         self.write_rate /= min_num;
-    //         self.read_rate /= min_num;
-    //     }
-    //     if self.read_rate == 0.0 {
-    //         self.write_rate = 1.0;
-    //     } else if self.write_rate == 0.0 {
-    //         self.read_rate = 1.0;
-    //     }
-    // }
+impl Report for TapeTraceStats {
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "seed: {}\n\
+            read_op:   {:<10}    write_op:   {:<10}    rate(w:r): {}\n\
+            read_data: {:<10}MB  write_data: {:<10}MB  rate(w:r): {}\n\
+            requests:  {:<10}    duration:   {:.3}s  stopped_by: {}",
+            self.seed,
+            self.read_op,
+            self.write_op,
+            ratio_text(self.op_rate_w_r),
+            self.read_data as f32 / 2048f32,
+            self.write_data as f32 / 2048f32,
+            ratio_text(self.data_rate_w_r),
+            self.requests,
+            self.duration,
+            self.stopped_by
+        );
+        for phase in &self.phases {
+            text += &format!(
+                "\nphase {}: requests {}..{}  time {:.3}..{:.3}ms  read_op: {}  write_op: {}  read_data: {}MB  write_data: {}MB  stopped_by: {}",
+                phase.name,
+                phase.first_request,
+                phase.first_request + phase.requests,
+                phase.start_time,
+                phase.end_time,
+                phase.read_op,
+                phase.write_op,
+                phase.read_data as f32 / 2048f32,
+                phase.write_data as f32 / 2048f32,
+                phase.stopped_by,
+            );
+        }
+        text
+    }
 }
 
 // 地址对齐的单位，256KB
 // static ALIEN: u64 = 512;
 
 /// 对外暴露的函数
-pub fn generate_tape_trace(trace: TapeTrace) -> Result<(), HMSimError> {
+///
+/// 若指定了多阶段负载(phases)，依次生成每个阶段的请求，否则 trace 本身就是唯一的阶段。
+/// 所有阶段共享同一个随机数生成器、输出文件和已写到的偏移量，后面的阶段可以读取前面阶段写入的数据
+pub fn generate_tape_trace(trace: TapeTrace) -> Result<TapeTraceStats, HMSimError> {
+    let phases = if trace.phases.is_empty() { std::slice::from_ref(&trace) } else { trace.phases.as_slice() };
+    trace.validate()?;
 
     // 记录顺序写请求已经写到的偏移量
     // 注意：只会在已经写过的地址内生成读请求，因此当只生成读请求时请确保该偏移量足够大
@@ -317,40 +812,102 @@ pub fn generate_tape_trace(trace: TapeTrace) -> Result<(), HMSimError> {
     // 515000 - 940000 (wrap2-3, 230GB)
     // let mut cur_write_offset = 0;
     // let mut cur_write_offset = 481280000;
-    let mut cur_write_offset = trace.write_offset;
-    if trace.read_order.eq_ignore_ascii_case("seq") {
-        cur_write_offset = trace.total_size;
-    }
+    // 每个阶段开始时再与该阶段的 woff 取较大值
+    let cur_write_offset = 0;
 
-    // 只有顺序读时该参数才会被使用
-    let mut cur_read_offset = 0;
+    // 所有随机决策都使用同一个随机数生成器，指定相同的种子可以生成完全相同的 trace
+    let (rand, seed) = seeded_rng(trace.seed);
+    info!("seed: {}", seed);
 
-    // // 如果有 batch 操作，重新计算读写比
-    // trace.recalculate_rwrate();
+    // 整个生成过程只打开一次输出文件
+    let writer = open_output(&trace.output, trace.force)?;
 
-    // info!("read_rate: {}, write_rate: {}", trace.read_rate, trace.write_rate);
-    // // 将读写比归一化
-    // trace.rwrate_in_one();
-    // info!("after in one: read_rate: {}, write_rate: {}", trace.read_rate, trace.write_rate);
+    let mut state = GenState {
+        rand,
+        writer,
+        cur_write_offset,
+        // 只有顺序读时该参数才会被使用
+        cur_read_offset: 0,
+        last_end: 0,
+        requests: 0,
+        clock: 0.0,
+    };
 
-    // 通过读写比生成随机数，若随机数小于 1 则为读操作
-    let mut rand = rand::thread_rng();
+    // 生成失败时删除不完整的输出文件，否则重新运行时需要指定 --force
+    let phase_stats = match generate_phases(phases, &mut state, &trace.output) {
+        Ok(phase_stats) => phase_stats,
+        Err(e) => {
+            drop(state);
+            if trace.output != "-" {
+                let _ = fs::remove_file(&trace.output);
+            }
+            return Err(e);
+        },
+    };
 
-    // let rand_floor = trace.write_rate + trace.read_rate;
+    info!("generate_tape_trace running done.");
+    let sum = |f: fn(&PhaseStats) -> u64| phase_stats.iter().map(f).sum::<u64>();
+    let mut stats = TapeTraceStats::new(
+        seed,
+        sum(|p| p.read_op),
+        sum(|p| p.write_op),
+        sum(|p| p.read_data),
+        sum(|p| p.write_data),
+    );
+    stats.requests = state.requests;
+    stats.duration = state.clock / 1000.0;
+    stats.stopped_by = phase_stats.last().map(|p| p.stopped_by).unwrap_or_default();
+    if !trace.phases.is_empty() {
+        stats.phases = phase_stats;
+    }
+    Ok(stats)
+}
 
-    let mut op_read = 0u64;
-    let mut op_write = 0u64;
+/// 依次生成每个阶段的请求到 state 的输出中
+fn generate_phases<W: Write>(phases: &[TapeTrace], state: &mut GenState<W>, output: &str) -> Result<Vec<PhaseStats>, HMSimError> {
+    let mut phase_stats = vec![];
+    for (i, phase) in phases.iter().enumerate() {
+        state.cur_write_offset = state.cur_write_offset.max(phase.write_offset);
+        if phase.has_op(Op::Read) && phase.read_order == AccessOrder::Seq {
+            // 每个顺序读阶段都从 0 开始读该阶段的 size
+            state.cur_read_offset = 0;
+            state.cur_write_offset = state.cur_write_offset.max(phase.total_size);
+        }
+        let name = if phase.name.is_empty() { format!("phase{}", i) } else { phase.name.clone() };
+        info!("phase {} begins at request {}", name, state.requests);
+        phase_stats.push(generate_phase(phase, name, state)?);
+    }
 
-    let mut read_data = 0u64;
-    let mut write_data = 0u64;
+    state.writer.flush().map_err(|e| e.with_path(output))?;
+    Ok(phase_stats)
+}
 
-    if fs::metadata("tape.trace").is_ok() {
-        remove_file("tape.trace").unwrap();
+/// 生成一个阶段的请求，直到用完该阶段的数据量、请求数量或时长
+fn generate_phase<W: Write>(trace: &TapeTrace, name: String, state: &mut GenState<W>) -> Result<PhaseStats, HMSimError> {
+    // 如果指定了读写比例，在考虑 batch 和请求大小的情况下计算读操作的概率
+    let target_read_share = trace.target_read_share();
+    let read_probability = trace.recalculate_rwrate();
+    if target_read_share.is_some() {
+        info!("read_rate: {}, write_rate: {}, read probability: {:.4}", trace.read_rate, trace.write_rate, read_probability);
     }
 
+    let mut stats = PhaseStats {
+        name,
+        first_request: state.requests,
+        start_time: state.clock,
+        ..PhaseStats::default()
+    };
+
     let mut loop_rw;
-    let mut total_size = trace.total_size;
-    while total_size > 0 {
+    let mut stalled = 0;
+    loop {
+        if let Some(budget) = trace.exhausted_budget(&stats, state.clock) {
+            stats.stopped_by = budget;
+            break;
+        }
+
+        let before = (stats.read_data + stats.write_data, state.requests, state.clock);
+
         // let rand_num;
         // debug!("read: {}, write: {}", trace.read_size_end, trace.write_size_end);
         // if trace.read_size_end == 0 {
@@ -361,64 +918,64 @@ pub fn generate_tape_trace(trace: TapeTrace) -> Result<(), HMSimError> {
         //     rand_num = rand.gen_range(0..2);
         // }
 
-        if trace.rw.eq_ignore_ascii_case("r") {
-            loop_rw = "R";
-        } else if trace.rw.eq_ignore_ascii_case("w") {
-            loop_rw = "W";
-        } else if trace.rw.eq_ignore_ascii_case("rw") {
-            let rand_num = rand.gen_range(0..2);
-            // 随机数为 0 则为读操作，为 1 则为写操作
-            if rand_num < 1 {
-                loop_rw = "R";
-            } else {
-                loop_rw = "W";
-            }
-        } else {
-            return Err(HMSimError::CommandError)
-        }
-        
+        loop_rw = match trace.rw {
+            Some(OpMix::R) => Op::Read,
+            Some(OpMix::W) => Op::Write,
+            Some(OpMix::Rw) => match target_read_share {
+                Some(target) => {
+                    // 根据已经生成的读写比例修正读概率，使最终的比例与指定的一致
+                    let (read, write) = match trace.rwrate_by {
+                        RateBasis::Op => (stats.read_op, stats.write_op),
+                        RateBasis::Data => (stats.read_data, stats.write_data),
+                    };
+                    let achieved = ratio(read, read + write).unwrap_or(target);
+                    let probability = (read_probability + (target - achieved) * RWRATE_FEEDBACK).clamp(0.0, 1.0);
+                    if state.rand.gen_bool(probability) { Op::Read } else { Op::Write }
+                },
+                // 随机数为 0 则为读操作，为 1 则为写操作
+                None => if state.rand.gen_range(0..2) < 1 { Op::Read } else { Op::Write },
+            },
+            None => return Err(HMSimError::CommandError(String::from("需要指定 rw 或 rwrate"))),
+        };
+
         // debug!("rw: {}", rw);
 
         // 函数 operation 很重要，生成 trace 请求的所有操作都在该函数中
         // 生成 trace 请求，返回值是 (生成的请求数量, 生成的请求大小) tuple
-        let (op_num, generate_size) = trace.operation(&mut rand, &trace, loop_rw, &mut cur_write_offset, &mut cur_read_offset);
+        // 指定了请求数量时，最后一个 batch 不超过剩余的请求数量
+        let max_ops = if trace.count > 0 { trace.count - stats.requests } else { u64::MAX };
+        let (op_num, generate_size) = trace.operation(state, trace, loop_rw, max_ops)?;
 
         // debug!("op_num: {}, generate_size: {}", op_num, generate_size);
 
         // ============= 下面是统计信息 =============
-        if loop_rw == "R" {
-            op_read += op_num;
-            read_data += generate_size;
+        if loop_rw == Op::Read {
+            stats.read_op += op_num;
+            stats.read_data += generate_size;
         } else {
-            op_write += op_num;
-            write_data += generate_size;
+            stats.write_op += op_num;
+            stats.write_data += generate_size;
         }
+        stats.requests = state.requests - stats.first_request;
+        // ============= 统计信息结束 =============
 
-        if total_size <= generate_size {
-            total_size = 0;
-        } else {
-            total_size -= generate_size;
+        // 例如只读且没有已写数据、或者请求大小总是为 0 时，结束条件永远不会满足
+        let progressed = (trace.total_size > 0 && stats.read_data + stats.write_data > before.0)
+            || (trace.count > 0 && state.requests > before.1)
+            || (trace.duration > 0.0 && state.clock > before.2);
+        stalled = if progressed { 0 } else { stalled + 1 };
+        if stalled >= MAX_STALLED_ROUNDS {
+            return Err(HMSimError::CommandError(format!(
+                "阶段 {} 连续 {} 轮没有生成有效的请求(已生成 {} 个请求)，请检查 rw、woff、rsize/wsize、req_dist 等参数",
+                stats.name,
+                MAX_STALLED_ROUNDS,
+                state.requests - stats.first_request
+            )));
         }
-        // ============= 统计信息结束 =============
     }
 
-    // ============= 打印统计信息日志 =============
-    info!(
-        "read_op:   {:<10}    write_op:   {:<10}    rate(w:r): {}",
-        op_read,
-        op_write,
-        op_write as f32 / op_read as f32
-    );
-    info!(
-        "read_data: {:<10}MB  write_data: {:<10}MB  rate(w:r): {}",
-        read_data as f32 / 2048f32,
-        write_data as f32 / 2048f32,
-        write_data as f32 / read_data as f32
-    );
-    // ============= 打印统计信息日志结束 =============
-
-    info!("generate_tape_trace running done.");
-    Ok(())
+    stats.end_time = state.clock;
+    Ok(stats)
 }
 
 /// 求平均数
@@ -433,10 +990,11 @@ fn mean(data: &[u64]) -> Option<f32> {
 }
 
 /// 根据不同权重生成随机数
-fn generate_weighted_random_number(rng: &mut ThreadRng, start: u64, end: u64) -> u64 {
+#[allow(dead_code)]
+fn generate_weighted_random_number(rng: &mut TraceRng, start: u64, end: u64) -> u64 {
     let rand_num: u64 = rng.gen_range(0..=10); // 生成一个在 0 到 10 之间的随机整数
-    let range = vec![0, 2, 4, 7, 10];
-    let probility = vec![4, 7, 9, 10];
+    let range = [0, 2, 4, 7, 10];
+    let probility = [4, 7, 9, 10];
     let piece = (end - start) / 10;
     let res;
     if rand_num < probility[0] {
@@ -452,30 +1010,91 @@ fn generate_weighted_random_number(rng: &mut ThreadRng, start: u64, end: u64) ->
         res = rng.gen_range(start + piece * range[3]..=start + piece * range[4]);
         // 10% 的概率
     }
-    return res;
+    res
 }
 
 
-/// 根据数学分布生成时间间隔
-fn get_timeinteval_from_distribution(dist: &Dist) -> f64 {
-    match *dist {
-        Dist::Exponential(lambda) => {
-            let exp = rand_distr::Exp::new(lambda).unwrap();
-            exp.sample(&mut rand::thread_rng())
-        },
-        _ => 0.0
-    }
+/// 根据数学分布生成时间间隔，负数(正态分布)按 0 处理
+fn get_timeinteval_from_distribution(dist: &Dist, rand: &mut TraceRng) -> f64 {
+    dist.sample(rand).max(0.0)
+}
+
+/// 根据数学分布生成请求大小(单位: 扇区)，向下对齐到 block_size，不足一个块时为 0
+fn get_req_length_from_distribution(dist: &Dist, rand: &mut TraceRng, block_size: u64) -> u64 {
+    let len = dist.sample(rand).max(0.0) as u64;
+    len / block_size * block_size
 }
 
 #[cfg(test)]
 mod tests {
+    use rand_distr::Distribution;
+
     use super::*;
 
+    #[test]
+    fn test_validate() {
+        let mut valid = TapeTrace::new();
+        valid.total_size = 2048;
+        valid.block_size = 8;
+        valid.rw = Some(OpMix::Rw);
+        valid.read_size_end = 4;
+        valid.write_size_end = 4;
+        assert!(valid.validate().is_ok());
+
+        // 每一项修改一个参数，并给出错误信息中应该包含的内容
+        type Modify = fn(&mut TapeTrace);
+        let cases: [(Modify, &str); 9] = [
+            (|t| t.block_size = 0, "blk_size"),
+            (|t| t.total_size = 0, "size、count 或 duration"),
+            (|t| t.rw = None, "rw 或 rwrate"),
+            (|t| t.read_size_start = 8, "rsize 的范围 8-4"),
+            (|t| t.write_size_end = 0, "wsize"),
+            (|t| t.batch = BatchMode::R, "batch_IOr_num"),
+            (|t| (t.rw, t.read_rate) = (Some(OpMix::W), 1.0), "rwrate=1:0"),
+            (|t| t.rw = Some(OpMix::R), "rw=r 且 ro=rand"),
+            (|t| (t.total_size, t.duration) = (0, 10.0), "time_dist 或 iops"),
+        ];
+        for (modify, expected) in cases {
+            let mut trace = valid.clone();
+            modify(&mut trace);
+            match trace.validate() {
+                Err(HMSimError::CommandError(msg)) => assert!(msg.contains(expected), "{}", msg),
+                other => panic!("{}: {:?}", expected, other),
+            }
+        }
+
+        // 只读阶段可以读之前阶段写过的数据
+        let mut read = valid.clone();
+        read.rw = Some(OpMix::R);
+        read.name = String::from("read");
+        let mut scheduled = valid.clone();
+        scheduled.phases = vec![valid.clone(), read.clone()];
+        assert!(scheduled.validate().is_ok());
+        scheduled.phases = vec![read];
+        match scheduled.validate() {
+            Err(HMSimError::CommandError(msg)) => assert!(msg.starts_with("阶段 read: "), "{}", msg),
+            other => panic!("{:?}", other),
+        }
+    }
+
     #[test]
     fn test_get_timeinteval_from_distribution() {
         let exp = rand_distr::Exp::new(2.0).unwrap();
         let v = exp.sample(&mut rand::thread_rng());
         println!("{} is from a Exp(2) distribution", v);
     }
+
+    #[test]
+    fn test_seeded_distribution() {
+        let dist = Dist::Exponential(2.0);
+        let (mut a, _) = seeded_rng(Some(42));
+        let (mut b, _) = seeded_rng(Some(42));
+        for _ in 0..10 {
+            assert_eq!(
+                get_timeinteval_from_distribution(&dist, &mut a),
+                get_timeinteval_from_distribution(&dist, &mut b)
+            );
+        }
+    }
 }
 
@@ -0,0 +1,516 @@
+//! generate-trace 子命令：生成通用的磁盘 trace
+//!
+//! 与 generate-tape-trace 不同，这里不区分已写和未写的区域，读写请求都可以落在地址范围内的任意位置。
+//! 支持随机(rand)、顺序(seq)和固定步长(stride)三种访问模式，请求偏移和长度按 align 对齐，
+//! 请求按 qd 个一组成批到达，同一组内的时间间隔为 0，组与组之间的时间间隔服从 time_dist。
+
+use std::{fmt, fs, io::Write};
+
+use log::info;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::HMSimError,
+    output::{ratio, ratio_text, Report},
+    trace::{open_output, Op, TraceRecord, TraceWriter},
+    utils::{dist_analyze, rate_to_num, seeded_rng, size_range_to_start_end, string_to_hmsim_block},
+    Dist, TraceRng,
+};
+
+/// generate-trace 的命令行参数，大小均需要加上单位(忽略大小写)，不加单位默认为 B
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct DiskTraceArgs {
+    /// 指定 trace 的起始地址(seq 和 stride 的第一个请求)，若不指定该字段，则随机从一个地址开始
+    #[arg(name = "addr_start", short, long)]
+    pub addr_start: Option<String>,
+
+    /// 请求的地址范围，例如 0-100G，所有请求都落在 [start, end) 内 [默认值: 0-1G]
+    #[arg(long, value_name = "start-end")]
+    pub range: Option<String>,
+
+    /// 通过指定数据量确定 trace，与 num_request 可以同时指定，任意一个用完即停止
+    #[arg(name = "size_data", short, long)]
+    pub size_data: Option<String>,
+
+    /// 通过指定请求数量确定 trace(单位: 条数)
+    #[arg(name = "num_request", short, long)]
+    pub num_request: Option<u64>,
+
+    /// 指定每个请求的大小，若不指定，则在 length_range 的范围内随机变化
+    #[arg(name = "length_request", short, long)]
+    pub length_request: Option<String>,
+
+    /// 请求大小的范围，例如 4K-128K，在范围内按 align 均匀随机
+    #[arg(name = "length_range", long, value_name = "min-max")]
+    pub length_range: Option<String>,
+
+    /// 访问模式 [默认值: rand]
+    #[arg(long, value_enum, ignore_case = true)]
+    pub pattern: Option<Pattern>,
+
+    /// pattern=stride 时相邻两个请求起始地址之差(pattern=stride 时必须指定，且为 align 的整数倍)
+    #[arg(long)]
+    pub stride: Option<String>,
+
+    /// 请求偏移和长度的对齐单位 [默认值: 4K]
+    #[arg(long)]
+    pub align: Option<String>,
+
+    /// 读写比例(读:写)，例如 7:3，支持小数，某一项为 0 表示只生成另一种请求 [默认值: 1:1]
+    #[arg(long, value_name = "read:write")]
+    pub rwrate: Option<String>,
+
+    /// 队列深度，每 qd 个请求为一组同时到达，组内的时间间隔为 0 [默认值: 1]
+    #[arg(long)]
+    pub qd: Option<u64>,
+
+    /// 相邻两组请求的时间间隔(单位: 毫秒)满足的数学分布，写法与 generate-tape-trace 的 time_dist 相同 [默认值: none]
+    #[arg(name = "time_dist", long)]
+    pub time_dist: Option<String>,
+
+    /// 随机数种子，指定相同的种子和参数可以生成完全相同的 trace(不指定则随机生成，并在结果中输出)
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// 输出的 trace 文件名，`-` 表示输出到 stdout(此时统计结果输出到 stderr) [默认值: disk.trace]
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+impl DiskTraceArgs {
+    /// 用出现的参数覆盖默认参数，大小全部转化为扇区
+    pub fn resolve(&self) -> Result<DiskTrace, HMSimError> {
+        let mut trace = DiskTrace::new();
+
+        if let Some(range) = &self.range {
+            let range = size_range_to_start_end(range)?;
+            trace.range_start = range.size_begin.block;
+            trace.range_end = range.size_end.block;
+        }
+        if let Some(addr_start) = &self.addr_start {
+            trace.addr_start = Some(string_to_hmsim_block(addr_start)?.block);
+        }
+        if let Some(size_data) = &self.size_data {
+            trace.total_size = string_to_hmsim_block(size_data)?.block;
+        }
+        if let Some(num_request) = self.num_request {
+            trace.count = num_request;
+        }
+        if let Some(length_request) = &self.length_request {
+            let length = string_to_hmsim_block(length_request)?.block;
+            trace.length_start = length;
+            trace.length_end = length;
+        }
+        if let Some(length_range) = &self.length_range {
+            if self.length_request.is_some() {
+                return Err(HMSimError::CommandError(String::from("length_request 和 length_range 不能同时指定")));
+            }
+            let range = size_range_to_start_end(length_range)?;
+            trace.length_start = range.size_begin.block;
+            trace.length_end = range.size_end.block;
+        }
+        if let Some(pattern) = self.pattern {
+            trace.pattern = pattern;
+        }
+        if let Some(stride) = &self.stride {
+            trace.stride = string_to_hmsim_block(stride)?.block;
+        }
+        if let Some(align) = &self.align {
+            trace.align = string_to_hmsim_block(align)?.block;
+        }
+        if let Some(rwrate) = &self.rwrate {
+            (trace.read_rate, trace.write_rate) = rate_to_num(rwrate)?;
+        }
+        if let Some(qd) = self.qd {
+            trace.queue_depth = qd;
+        }
+        if let Some(time_dist) = &self.time_dist {
+            trace.time_interval_dist = dist_analyze(time_dist)?;
+        }
+        if let Some(seed) = self.seed {
+            trace.seed = Some(seed);
+        }
+        if let Some(output) = &self.output {
+            trace.output = output.clone();
+        }
+        Ok(trace)
+    }
+}
+
+/// 访问模式(pattern)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    /// 在地址范围内均匀随机
+    #[default]
+    Rand,
+    /// 紧接着上一个请求的结束地址
+    Seq,
+    /// 每个请求的起始地址比上一个请求大 stride
+    Stride,
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Rand => f.write_str("rand"),
+            Pattern::Seq => f.write_str("seq"),
+            Pattern::Stride => f.write_str("stride"),
+        }
+    }
+}
+
+/// 通过子命令参数转化成的 DiskTrace 结构体，大小单位全部转化为扇区(512B)
+#[derive(Debug, Clone)]
+pub struct DiskTrace {
+    /// 地址范围 [range_start, range_end)
+    pub range_start: u64,
+    pub range_end: u64,
+    /// seq 和 stride 的第一个请求的地址，为 None 时在地址范围内随机选择
+    pub addr_start: Option<u64>,
+
+    /// 总数据量和请求数量，为 0 表示不限制，用完其中任意一个即停止
+    pub total_size: u64,
+    pub count: u64,
+
+    /// 请求大小的范围，两者相等时为固定大小
+    pub length_start: u64,
+    pub length_end: u64,
+
+    pub pattern: Pattern,
+    pub stride: u64,
+    pub align: u64,
+
+    /// 读写比例(读:写)
+    pub read_rate: f32,
+    pub write_rate: f32,
+
+    /// 每组同时到达的请求数量
+    pub queue_depth: u64,
+    /// 相邻两组请求的时间间隔的分布
+    pub time_interval_dist: Dist,
+
+    /// 随机数种子，为 None 时随机生成一个
+    pub seed: Option<u64>,
+
+    /// 输出的 trace 文件，`-` 表示 stdout
+    pub output: String,
+    /// 输出文件已经存在时是否覆盖
+    pub force: bool,
+}
+
+impl Default for DiskTrace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DiskTrace {
+    pub fn new() -> DiskTrace {
+        DiskTrace {
+            range_start: 0,
+            // 1GB
+            range_end: 2 * 1024 * 1024,
+            addr_start: None,
+            total_size: 0,
+            count: 0,
+            length_start: 0,
+            length_end: 0,
+            pattern: Pattern::Rand,
+            stride: 0,
+            // 4KB
+            align: 8,
+            read_rate: 1.0,
+            write_rate: 1.0,
+            queue_depth: 1,
+            time_interval_dist: Dist::None,
+            seed: None,
+            output: String::from("disk.trace"),
+            force: false,
+        }
+    }
+
+    /// 生成前检查所有参数，不合法时返回说明原因的错误
+    pub fn validate(&self) -> Result<(), HMSimError> {
+        if self.total_size == 0 && self.count == 0 {
+            return Err(HMSimError::CommandError(String::from("需要指定 size_data 或 num_request 中的至少一个")));
+        }
+        if self.align == 0 {
+            return Err(HMSimError::CommandError(String::from("align 不能小于一个扇区(512B)")));
+        }
+        if self.length_end == 0 {
+            return Err(HMSimError::CommandError(String::from("需要指定 length_request 或 length_range")));
+        }
+        if self.length_start > self.length_end {
+            return Err(HMSimError::CommandError(format!(
+                "length_range 的范围 {}-{} 不合法，起始值不能大于结束值",
+                self.length_start, self.length_end
+            )));
+        }
+        let (min_len, max_len) = self.aligned_lengths();
+        if min_len > max_len {
+            return Err(HMSimError::CommandError(format!(
+                "请求大小的范围 {}-{} 扇区内没有 align={} 扇区的整数倍",
+                self.length_start, self.length_end, self.align
+            )));
+        }
+
+        let (start, end) = self.aligned_range();
+        if end <= start || end - start < max_len {
+            return Err(HMSimError::CommandError(format!(
+                "地址范围 {}-{} 扇区(按 align 对齐后)放不下最大的请求({} 扇区)",
+                self.range_start, self.range_end, max_len
+            )));
+        }
+        if let Some(addr_start) = self.addr_start {
+            if addr_start < self.range_start || addr_start >= self.range_end {
+                return Err(HMSimError::CommandError(format!(
+                    "addr_start={} 扇区不在地址范围 {}-{} 扇区内",
+                    addr_start, self.range_start, self.range_end
+                )));
+            }
+        }
+        if self.pattern == Pattern::Stride && self.stride == 0 {
+            return Err(HMSimError::CommandError(String::from("pattern=stride 时需要指定不为 0 的 stride")));
+        }
+        if self.pattern == Pattern::Stride && !self.stride.is_multiple_of(self.align) {
+            return Err(HMSimError::CommandError(format!(
+                "stride={} 扇区不是 align={} 扇区的整数倍",
+                self.stride, self.align
+            )));
+        }
+
+        for (name, rate) in [("读", self.read_rate), ("写", self.write_rate)] {
+            if !(rate.is_finite() && rate >= 0.0) {
+                return Err(HMSimError::CommandError(format!("rwrate 中{}的比例应为非负数，实际为 {}", name, rate)));
+            }
+        }
+        if self.read_rate + self.write_rate <= 0.0 {
+            return Err(HMSimError::CommandError(String::from("rwrate 的读写比例不能同时为 0")));
+        }
+        if self.queue_depth == 0 {
+            return Err(HMSimError::CommandError(String::from("qd 不能为 0")));
+        }
+        if !self.time_interval_dist.is_valid() {
+            return Err(HMSimError::CommandError(format!("time_dist 的参数不合法: {:?}", self.time_interval_dist)));
+        }
+        Ok(())
+    }
+
+    /// 按 align 对齐后的地址范围，起始地址向上对齐，结束地址向下对齐
+    fn aligned_range(&self) -> (u64, u64) {
+        (self.range_start.div_ceil(self.align) * self.align, self.range_end / self.align * self.align)
+    }
+
+    /// 按 align 对齐后请求大小的最小值和最大值，最小为一个 align
+    fn aligned_lengths(&self) -> (u64, u64) {
+        (self.length_start.div_ceil(self.align).max(1) * self.align, self.length_end / self.align * self.align)
+    }
+
+    /// 随机生成一个按 align 对齐的请求大小
+    fn pick_length(&self, rand: &mut impl Rng) -> u64 {
+        let (min_len, max_len) = self.aligned_lengths();
+        rand.gen_range(min_len / self.align..=max_len / self.align) * self.align
+    }
+
+    /// 在地址范围内随机选择一个按 align 对齐、能放下 len 的偏移量
+    fn pick_offset(&self, rand: &mut impl Rng, len: u64) -> u64 {
+        let (start, end) = self.aligned_range();
+        rand.gen_range(start / self.align..=(end - len) / self.align) * self.align
+    }
+}
+
+/// generate-trace 子命令输出的统计信息，数据量单位为扇区(512B)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiskTraceStats {
+    /// 生成时使用的随机数种子，用它可以复现同样的 trace
+    pub seed: u64,
+    pub pattern: Pattern,
+    pub read_op: u64,
+    pub write_op: u64,
+    pub read_data: u64,
+    pub write_data: u64,
+    /// 写请求数量与读请求数量之比
+    pub op_rate_w_r: Option<f64>,
+    /// 写数据量与读数据量之比
+    pub data_rate_w_r: Option<f64>,
+    /// trace 中的请求数量，以及模拟时长(单位: 秒，即所有请求的时间间隔之和)
+    pub requests: u64,
+    pub duration: f64,
+}
+
+impl Report for DiskTraceStats {
+    fn to_text(&self) -> String {
+        format!(
+            "seed: {}  pattern: {}\n\
+            read_op:   {:<10}    write_op:   {:<10}    rate(w:r): {}\n\
+            read_data: {:<10}MB  write_data: {:<10}MB  rate(w:r): {}\n\
+            requests:  {:<10}    duration:   {:.3}s",
+            self.seed,
+            self.pattern,
+            self.read_op,
+            self.write_op,
+            ratio_text(self.op_rate_w_r),
+            self.read_data as f32 / 2048f32,
+            self.write_data as f32 / 2048f32,
+            ratio_text(self.data_rate_w_r),
+            self.requests,
+            self.duration,
+        )
+    }
+}
+
+/// 对外暴露的函数，生成 trace 并返回统计信息
+pub fn generate_trace(trace: DiskTrace) -> Result<DiskTraceStats, HMSimError> {
+    trace.validate()?;
+
+    let (mut rand, seed) = seeded_rng(trace.seed);
+    info!("seed: {}", seed);
+
+    let mut writer = open_output(&trace.output, trace.force)?;
+
+    let mut stats = DiskTraceStats {
+        seed,
+        pattern: trace.pattern,
+        read_op: 0,
+        write_op: 0,
+        read_data: 0,
+        write_data: 0,
+        op_rate_w_r: None,
+        data_rate_w_r: None,
+        requests: 0,
+        duration: 0.0,
+    };
+
+    // 生成失败时删除不完整的输出文件，否则重新运行时需要指定 --force
+    let clock = match generate_records(&trace, &mut rand, &mut writer, &mut stats) {
+        Ok(clock) => clock,
+        Err(e) => {
+            drop(writer);
+            if trace.output != "-" {
+                let _ = fs::remove_file(&trace.output);
+            }
+            return Err(e);
+        },
+    };
+
+    info!("generate_trace running done.");
+
+    stats.op_rate_w_r = ratio(stats.write_op, stats.read_op);
+    stats.data_rate_w_r = ratio(stats.write_data, stats.read_data);
+    stats.duration = clock / 1000.0;
+    Ok(stats)
+}
+
+/// 生成 trace 的全部请求写入 writer，统计结果记录在 stats 中，返回所有请求的时间间隔之和(单位: 毫秒)
+fn generate_records<W: Write>(
+    trace: &DiskTrace,
+    rand: &mut TraceRng,
+    writer: &mut TraceWriter<W>,
+    stats: &mut DiskTraceStats,
+) -> Result<f64, HMSimError> {
+    let (range_start, range_end) = trace.aligned_range();
+    let read_probability = trace.read_rate as f64 / (trace.read_rate as f64 + trace.write_rate as f64);
+
+    // seq 和 stride 的下一个请求的地址
+    let mut cursor = match trace.addr_start {
+        Some(addr) => (addr / trace.align * trace.align).max(range_start),
+        None => trace.pick_offset(rand, 0),
+    };
+    let mut clock = 0.0;
+
+    while !((trace.total_size > 0 && stats.read_data + stats.write_data >= trace.total_size)
+        || (trace.count > 0 && stats.requests >= trace.count))
+    {
+        let op = if rand.gen_bool(read_probability) { Op::Read } else { Op::Write };
+        let len = trace.pick_length(rand);
+
+        let offset = match trace.pattern {
+            Pattern::Rand => trace.pick_offset(rand, len),
+            Pattern::Seq | Pattern::Stride => {
+                // 超出地址范围时回到范围的起始地址
+                if cursor + len > range_end {
+                    cursor = range_start;
+                }
+                let offset = cursor;
+                cursor += if trace.pattern == Pattern::Seq { len } else { trace.stride };
+                offset
+            },
+        };
+
+        let mut record = TraceRecord::new(op, offset, len);
+        // 每组的最后一个请求之后才有时间间隔
+        stats.requests += 1;
+        if stats.requests.is_multiple_of(trace.queue_depth) && !matches!(trace.time_interval_dist, Dist::None) {
+            record.next_inter = trace.time_interval_dist.sample(rand).max(0.0);
+        }
+        clock += record.next_inter;
+        writer.write_record(&record)?;
+
+        if op == Op::Read {
+            stats.read_op += 1;
+            stats.read_data += len;
+        } else {
+            stats.write_op += 1;
+            stats.write_data += len;
+        }
+    }
+
+    writer.flush().map_err(|e| e.with_path(&trace.output))?;
+    Ok(clock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut valid = DiskTrace::new();
+        valid.count = 10;
+        valid.length_start = 8;
+        valid.length_end = 256;
+        assert!(valid.validate().is_ok());
+
+        // 每一项修改一个参数，并给出错误信息中应该包含的内容
+        type Modify = fn(&mut DiskTrace);
+        let cases: [(Modify, &str); 8] = [
+            (|t| t.count = 0, "size_data 或 num_request"),
+            (|t| t.length_end = 0, "length_request 或 length_range"),
+            (|t| (t.length_start, t.length_end) = (1, 7), "没有 align=8 扇区的整数倍"),
+            (|t| t.range_end = 128, "放不下最大的请求"),
+            (|t| t.addr_start = Some(1 << 30), "addr_start"),
+            (|t| t.pattern = Pattern::Stride, "stride"),
+            (|t| (t.pattern, t.stride) = (Pattern::Stride, 12), "不是 align=8 扇区的整数倍"),
+            (|t| (t.read_rate, t.write_rate) = (0.0, 0.0), "不能同时为 0"),
+        ];
+        for (modify, expected) in cases {
+            let mut trace = valid.clone();
+            modify(&mut trace);
+            match trace.validate() {
+                Err(HMSimError::CommandError(msg)) => assert!(msg.contains(expected), "{}", msg),
+                other => panic!("{}: {:?}", expected, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_aligned() {
+        let mut trace = DiskTrace::new();
+        trace.range_start = 3;
+        trace.range_end = 101;
+        trace.length_start = 5;
+        trace.length_end = 20;
+        assert_eq!(trace.aligned_range(), (8, 96));
+        assert_eq!(trace.aligned_lengths(), (8, 16));
+
+        let (mut rand, _) = seeded_rng(Some(1));
+        for _ in 0..100 {
+            let len = trace.pick_length(&mut rand);
+            let offset = trace.pick_offset(&mut rand, len);
+            assert!(len.is_multiple_of(8) && offset.is_multiple_of(8));
+            assert!(offset >= 8 && offset + len <= 96);
+        }
+    }
+}
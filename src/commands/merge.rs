@@ -0,0 +1,325 @@
+//! merge 子命令：把多个 HMSim trace 合并为一个
+//!
+//! concat 按输入的顺序首尾相接；interleave 按到达时间交错合并，
+//! 每个请求的到达时间为该文件中之前所有请求的时间间隔(nextinter)之和，
+//! 合并后重新计算时间间隔，使每个请求的到达时间保持不变。
+//!
+//! 指定 window 时，第 i 个输入的地址整体平移 i * window，
+//! 使每个输入独占一段 LBA 窗口，可以在一个设备上模拟多个租户。
+
+use std::{cmp::Ordering, collections::BinaryHeap, fmt, io::BufRead};
+
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    error::HMSimError,
+    output::Report,
+    trace::{open_output, same_file, RetimingWriter, TraceReader, TraceRecord, TraceWriter},
+    utils::{hmsim_block_to_string, string_to_hmsim_block},
+};
+
+/// 合并方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMode {
+    /// 按输入的顺序首尾相接
+    #[default]
+    Concat,
+    /// 按到达时间交错合并
+    Interleave,
+}
+
+impl fmt::Display for MergeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeMode::Concat => f.write_str("concat"),
+            MergeMode::Interleave => f.write_str("interleave"),
+        }
+    }
+}
+
+/// merge 的命令行参数
+#[derive(Debug, Clone, clap::Args)]
+pub struct MergeArgs {
+    /// 输入的 trace 文件，按给出的顺序合并，例如 -f a.trace b.trace
+    #[arg(short, long, required = true, num_args = 1..)]
+    pub file: Vec<String>,
+
+    /// 合并方式 [concat: 首尾相接, interleave: 按到达时间(之前所有请求的 nextinter 之和)交错]
+    #[arg(short, long, value_enum, ignore_case = true, default_value_t = MergeMode::Concat)]
+    pub mode: MergeMode,
+
+    /// 每个输入独占的 LBA 窗口大小，第 i 个输入(从 0 开始)的地址加上 i * window，请求超出窗口时报错
+    #[arg(short, long)]
+    pub window: Option<String>,
+
+    /// 输出的 trace 文件名，`-` 表示输出到 stdout(此时统计结果输出到 stderr)
+    #[arg(short, long, default_value = "merged.trace")]
+    pub output: String,
+}
+
+impl MergeArgs {
+    pub fn resolve(&self) -> Result<Merge, HMSimError> {
+        Ok(Merge {
+            files: self.file.clone(),
+            mode: self.mode,
+            window: self.window.as_deref().map(|window| string_to_hmsim_block(window).map(|block| block.block)).transpose()?,
+            output: self.output.clone(),
+            force: false,
+        })
+    }
+}
+
+/// merge 子命令的参数，window 的单位为扇区(512B)
+#[derive(Debug, Clone)]
+pub struct Merge {
+    pub files: Vec<String>,
+    pub mode: MergeMode,
+    /// 每个输入独占的 LBA 窗口大小，为 None 时不平移地址
+    pub window: Option<u64>,
+    /// 输出的 trace 文件，`-` 表示 stdout
+    pub output: String,
+    /// 输出文件已经存在时是否覆盖
+    pub force: bool,
+}
+
+/// merge 子命令输出的统计信息
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeStats {
+    pub output: String,
+    pub mode: MergeMode,
+    /// 合并后的请求数量，以及模拟时长(单位: 秒，即所有请求的时间间隔之和)
+    pub requests: u64,
+    pub duration: f64,
+    /// 每个输入的统计信息
+    pub inputs: Vec<MergeInput>,
+}
+
+/// 一个输入的统计信息，时间单位为毫秒，地址单位为扇区
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MergeInput {
+    pub file: String,
+    pub requests: u64,
+    /// 该输入在合并后的地址偏移，即 LBA 窗口的起始地址
+    pub base: u64,
+    /// 该输入最后一个请求的到达时间
+    pub last_arrival: f64,
+}
+
+impl Report for MergeStats {
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "output: {}  mode: {}\nrequests: {:<10}  duration: {:.3}s",
+            self.output, self.mode, self.requests, self.duration
+        );
+        for input in &self.inputs {
+            text += &format!(
+                "\n{}: requests: {}  base: {}  last_arrival: {:.3}ms",
+                input.file,
+                input.requests,
+                hmsim_block_to_string(input.base),
+                input.last_arrival
+            );
+        }
+        text
+    }
+}
+
+/// 一个输入中下一个待合并的请求
+struct Pending {
+    arrival: f64,
+    input: usize,
+    record: TraceRecord,
+}
+
+/// BinaryHeap 是最大堆，这里反转比较结果，到达时间最早的先出堆，时间相同时先出编号小的输入
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.arrival.total_cmp(&self.arrival).then_with(|| other.input.cmp(&self.input))
+    }
+}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+/// 一个输入文件，读取时记录到达时间并平移地址
+struct Input<R: BufRead> {
+    reader: TraceReader<R>,
+    stats: MergeInput,
+    // 下一个请求的到达时间
+    clock: f64,
+    window: Option<u64>,
+}
+
+impl<R: BufRead> Input<R> {
+    /// 读取下一个请求，返回 (到达时间, 平移地址后的请求)
+    fn next(&mut self) -> Result<Option<(f64, TraceRecord)>, HMSimError> {
+        let Some(mut record) = self.reader.next().transpose()? else {
+            return Ok(None);
+        };
+        if let Some(window) = self.window {
+            if record.end_sectors() > window {
+                return Err(HMSimError::CommandError(format!(
+                    "{} 第 {} 行的请求 [{}, {}) 超出了 window({} 扇区)，请增大 window",
+                    self.stats.file,
+                    self.reader.line_no(),
+                    record.offset_sectors,
+                    record.end_sectors(),
+                    window
+                )));
+            }
+            record.offset_sectors += self.stats.base;
+        }
+
+        let arrival = self.clock;
+        self.clock += record.next_inter;
+        self.stats.requests += 1;
+        self.stats.last_arrival = arrival;
+        Ok(Some((arrival, record)))
+    }
+}
+
+/// 对外暴露的函数，合并所有输入并返回统计信息
+pub fn merge(merge: Merge) -> Result<MergeStats, HMSimError> {
+    if merge.files.is_empty() {
+        return Err(HMSimError::CommandError(String::from("至少需要一个输入文件")));
+    }
+    if merge.window == Some(0) {
+        return Err(HMSimError::CommandError(String::from("window 不能为 0")));
+    }
+    if merge.files.iter().any(|file| same_file(file, &merge.output)) {
+        return Err(HMSimError::CommandError(format!("输出文件 {} 不能同时是输入文件", merge.output)));
+    }
+
+    // 先打开所有输入，文件不存在时不会创建输出文件
+    let mut inputs = vec![];
+    for (i, file) in merge.files.iter().enumerate() {
+        let base = match merge.window {
+            Some(window) => window.checked_mul(i as u64).ok_or_else(|| {
+                HMSimError::CommandError(format!("window({} 扇区) 太大，第 {} 个输入的地址超出范围", window, i + 1))
+            })?,
+            None => 0,
+        };
+        inputs.push(Input {
+            reader: TraceReader::open(file)?,
+            stats: MergeInput { file: file.clone(), base, ..MergeInput::default() },
+            clock: 0.0,
+            window: merge.window,
+        });
+    }
+
+    let writer = open_output(&merge.output, merge.force)?;
+    let (requests, clock) = match merge.mode {
+        MergeMode::Concat => {
+            let mut writer = writer;
+            let written = concat(&mut inputs, &mut writer)?;
+            writer.flush().map_err(|e| e.with_path(&merge.output))?;
+            written
+        },
+        MergeMode::Interleave => {
+            let mut writer = RetimingWriter::new(writer);
+            interleave(&mut inputs, &mut writer)?;
+            writer.flush().map_err(|e| e.with_path(&merge.output))?;
+            (writer.requests(), writer.duration())
+        },
+    };
+
+    info!("merge running done.");
+    Ok(MergeStats {
+        output: merge.output,
+        mode: merge.mode,
+        requests,
+        duration: clock / 1000.0,
+        inputs: inputs.into_iter().map(|input| input.stats).collect(),
+    })
+}
+
+/// 按顺序写出所有输入的请求，返回 (请求数量, 时间间隔之和)
+fn concat<R: BufRead, W: std::io::Write>(
+    inputs: &mut [Input<R>],
+    writer: &mut TraceWriter<W>,
+) -> Result<(u64, f64), HMSimError> {
+    let mut requests = 0;
+    let mut clock = 0.0;
+    for input in inputs {
+        while let Some((_, record)) = input.next()? {
+            requests += 1;
+            clock += record.next_inter;
+            writer.write_record(&record)?;
+        }
+    }
+    Ok((requests, clock))
+}
+
+/// 按到达时间交错写出所有输入的请求
+///
+/// 每个请求的时间间隔改为与合并后下一个请求的到达时间之差，最后一个请求保留原来的时间间隔
+fn interleave<R: BufRead, W: std::io::Write>(
+    inputs: &mut [Input<R>],
+    writer: &mut RetimingWriter<W>,
+) -> Result<(), HMSimError> {
+    let mut heap = BinaryHeap::new();
+    for (i, input) in inputs.iter_mut().enumerate() {
+        if let Some((arrival, record)) = input.next()? {
+            heap.push(Pending { arrival, input: i, record });
+        }
+    }
+
+    while let Some(pending) = heap.pop() {
+        if let Some((arrival, record)) = inputs[pending.input].next()? {
+            heap.push(Pending { arrival, input: pending.input, record });
+        }
+        writer.write_record(pending.arrival, pending.record)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(content: &'static str, base: u64, window: Option<u64>) -> Input<&'static [u8]> {
+        Input {
+            reader: TraceReader::new(content.as_bytes()),
+            stats: MergeInput { base, ..MergeInput::default() },
+            clock: 0.0,
+            window,
+        }
+    }
+
+    #[test]
+    fn test_interleave() {
+        let a = "W Hit 0 8 0.000000 10.000000\nW Hit 8 8 0.000000 10.000000\nW Hit 16 8 0.000000 0.000000\n";
+        let b = "R Hit 0 8 0.000000 5.000000\nR Hit 8 8 0.000000 0.000000\n";
+        let mut inputs = [input(a, 0, Some(100)), input(b, 100, Some(100))];
+
+        let mut writer = RetimingWriter::new(TraceWriter::new(Vec::new()));
+        interleave(&mut inputs, &mut writer).unwrap();
+        writer.flush().unwrap();
+        assert_eq!((writer.requests(), writer.duration()), (5, 20.0));
+
+        // 到达时间: a 为 0、10、20，b 为 0、5，时间相同时先输出编号小的输入
+        let records: Vec<TraceRecord> = TraceReader::new(&writer.into_inner().unwrap()[..]).collect::<Result<_, _>>().unwrap();
+        let merged: Vec<_> = records.iter().map(|r| (r.offset_sectors, r.next_inter)).collect();
+        assert_eq!(merged, [(0, 0.0), (100, 5.0), (108, 5.0), (8, 10.0), (16, 0.0)]);
+        assert_eq!(inputs[1].stats.last_arrival, 5.0);
+
+        let mut outside = [input("W Hit 96 8 0.000000 0.000000\n", 0, Some(100))];
+        match concat(&mut outside, &mut TraceWriter::new(Vec::new())) {
+            Err(HMSimError::CommandError(msg)) => assert!(msg.contains("第 1 行"), "{}", msg),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -1,35 +1,104 @@
-use log::info;
+use std::io;
 
-use crate::error::HMSimError;
+use crate::{
+    error::HMSimError,
+    output::{self, OutputFormat, Report},
+};
 
-use self::generate_tape_trace::TapeTrace;
+use self::{filter::Filter, generate_tape_trace::TapeTrace, generate_trace::DiskTrace, merge::Merge, origin_to_sim::OriginToSim, sample::{Sample, SampleMode}, split::Split, timewarp::Timewarp, transform::Transform, warmup_trace::WarmupTrace};
 
 pub mod origin_to_sim;
 pub mod trace_foot_size;
-// pub mod generate_trace;
+pub mod generate_trace;
 pub mod generate_tape_trace;
+pub mod warmup_trace;
+pub mod merge;
+pub mod filter;
+pub mod transform;
+pub mod timewarp;
+pub mod sample;
+pub mod split;
 
-pub struct Pine;
+/// 子命令的入口，`output_format` 决定分析结果的输出格式
+pub struct Pine {
+    pub output_format: OutputFormat,
+}
 
 impl Pine {
-    
-    pub fn generate_trace(&self) -> Result<(), HMSimError> {
-        Ok(())
+
+    pub fn generate_trace(&self, disk_trace: DiskTrace) -> Result<(), HMSimError> {
+        let to_stdout = disk_trace.output == "-";
+        let stats = generate_trace::generate_trace(disk_trace)?;
+        self.emit(&stats, to_stdout)
     }
 
+    pub fn warmup_trace(&self, warmup_trace: WarmupTrace) -> Result<(), HMSimError> {
+        let to_stdout = warmup_trace.output == "-";
+        let stats = warmup_trace::warmup_trace(warmup_trace)?;
+        self.emit(&stats, to_stdout)
+    }
 
-    pub fn trace_foot_size(&self, file: &str) -> Result<(), HMSimError> {
-        let (footprint, volume) = trace_foot_size::trace_foot_size(file).unwrap();
-        info!("tracefile: {}\nfootprint: {:>7}\nvolume: {:>10}", file, footprint, volume);
-        Ok(())
+
+    /// 未指定 sample_rate 和 sample_mode 时从 trace 的抽样信息中读取，都没有时不做估计
+    pub fn trace_foot_size(&self, file: &str, sample_rate: Option<f64>, sample_mode: Option<SampleMode>) -> Result<(), HMSimError> {
+        let report = trace_foot_size::foot_size_report(file, sample_rate, sample_mode)?;
+        output::emit(&report, self.output_format)
     }
 
 
-    pub fn origin_to_sim(&self, file: &str, timestamp: bool) -> Result<(), HMSimError> {
-        origin_to_sim::origin_to_sim(file, timestamp)
+    pub fn origin_to_sim(&self, origin: OriginToSim) -> Result<(), HMSimError> {
+        let stats = origin_to_sim::origin_to_sim(&origin)?;
+        self.emit(&stats, false)
     }
 
     pub fn generate_tape_trace(&self, tape_trace_struct: TapeTrace) -> Result<(), HMSimError> {
-        generate_tape_trace::generate_tape_trace(tape_trace_struct)
+        let to_stdout = tape_trace_struct.output == "-";
+        let stats = generate_tape_trace::generate_tape_trace(tape_trace_struct)?;
+        self.emit(&stats, to_stdout)
+    }
+
+    pub fn merge(&self, merge: Merge) -> Result<(), HMSimError> {
+        let to_stdout = merge.output == "-";
+        let stats = merge::merge(merge)?;
+        self.emit(&stats, to_stdout)
+    }
+
+    pub fn filter(&self, filter: Filter) -> Result<(), HMSimError> {
+        let stats = filter::filter(&filter)?;
+        self.emit(&stats, filter.output == "-")
+    }
+
+    pub fn transform(&self, transform: Transform) -> Result<(), HMSimError> {
+        let stats = transform::transform(&transform)?;
+        self.emit(&stats, transform.output == "-")
+    }
+
+    pub fn timewarp(&self, timewarp: Timewarp) -> Result<(), HMSimError> {
+        let stats = timewarp::timewarp(&timewarp)?;
+        self.emit(&stats, timewarp.output == "-")
+    }
+
+    pub fn sample(&self, sample: Sample) -> Result<(), HMSimError> {
+        let stats = sample::sample(&sample)?;
+        self.emit(&stats, sample.output == "-")
+    }
+
+    pub fn split(&self, split: Split) -> Result<(), HMSimError> {
+        let stats = split::split(&split)?;
+        self.emit(&stats, false)
+    }
+
+    /// 输出完整的 generate-tape-trace 参数，文本格式为 TOML
+    pub fn dump_config(&self, tape_trace_struct: &TapeTrace) -> Result<(), HMSimError> {
+        output::emit(tape_trace_struct, self.output_format)
+    }
+
+    /// 输出子命令的结果，若 trace 本身输出到了 stdout，则结果输出到 stderr
+    fn emit<R: Report>(&self, report: &R, trace_to_stdout: bool) -> Result<(), HMSimError> {
+        if trace_to_stdout {
+            output::write_report(&mut io::stderr(), report, self.output_format)
+        } else {
+            output::emit(report, self.output_format)
+        }
     }
 }
@@ -13,102 +13,395 @@
 // Col 3: 偏移量(offset，单位：扇区)
 // Col 4: 长度(length，单位：块，扇区，即 512B)
 // Col 5: 服务时间(servtime，即完成该次请求的总时间)
-// Col 6: 时间戳(源码中的字段名为 nextinter)
+// Col 6: 与下一个请求的到达时间之差(单位为 ms，源码中的字段名为 nextinter)，最后一个请求为 0
+
+// 实际的 MSR/SNIA 文件并不完全符合上述格式，默认容忍以下情况：
+// 文件开头的表头行、空行、大小写不同的读写类型(read/WRITE)、行尾多余的空列。
+// --strict 时不容忍这些情况；--skip-bad 时跳过无法解析的行，并按原因统计跳过的行数。
 
 use std::{
-    fs::{remove_file, File, OpenOptions},
+    collections::BTreeMap,
+    fs::{self, File},
     io::{BufRead, BufReader, Write},
     path::Path,
 };
 
-use log::info;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{
+    error::{BoxError, HMSimError},
+    output::Report,
+    trace::{open_output, Op, RetimingWriter, TraceRecord},
+};
+
+/// 原始 trace 每一列的列名，用于报告解析错误
+const COLUMNS: [&str; 7] = ["timestamp", "hostname", "devname", "rw", "offset", "length", "responsetime"];
+
+/// 列数不足或多出非空列时，跳过原因中使用的名称
+const COLUMN_COUNT: &str = "columns";
+
+/// --skip-bad 时在日志中逐行给出原因的最大行数，之后只计数
+const MAX_WARNINGS: u64 = 10;
 
-use crate::error::HMSimError;
+/// origin-to-sim 的命令行参数
+#[derive(Debug, Clone, clap::Args)]
+pub struct OriginToSimArgs {
+    /// 原始 trace 文件名
+    #[arg(short, long)]
+    pub file: String,
 
-pub fn origin_to_sim(filename: &str, timestamp: bool) -> Result<(), HMSimError> {
-    let file = File::open(filename)?;
+    /// 是否保留时间戳
+    #[arg(short, long)]
+    pub timestamp: bool,
+
+    /// 严格按照 7 列、Read/Write 的格式解析，不容忍表头行、空行、大小写不同的读写类型和多余的列
+    #[arg(long, conflicts_with = "skip_bad")]
+    pub strict: bool,
+
+    /// 跳过无法解析的行，并统计跳过的行数及原因，默认遇到这样的行时报错
+    #[arg(long = "skip-bad")]
+    pub skip_bad: bool,
+
+    /// 只转换主机名(hostname 列)为该值的请求
+    #[arg(long)]
+    pub hostname: Option<String>,
+
+    /// 只转换设备名(devname 列)为该值的请求
+    #[arg(long)]
+    pub devname: Option<String>,
+
+    /// 每个设备(hostname 和 devname 相同)的请求输出到各自的文件 <文件名>-<hostname>-<devname>.trace
+    #[arg(long = "split-device")]
+    pub split_device: bool,
+}
+
+impl OriginToSimArgs {
+    pub fn resolve(&self) -> OriginToSim {
+        OriginToSim {
+            file: self.file.clone(),
+            timestamp: self.timestamp,
+            parse: if self.strict {
+                ParseMode::Strict
+            } else if self.skip_bad {
+                ParseMode::SkipBad
+            } else {
+                ParseMode::Tolerant
+            },
+            hostname: self.hostname.clone(),
+            devname: self.devname.clone(),
+            split_device: self.split_device,
+            force: false,
+        }
+    }
+}
+
+/// 解析方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseMode {
+    /// 严格按照原始 trace 格式解析
+    Strict,
+    /// 容忍表头行、空行、大小写和行尾的空列，遇到无法解析的行时报错
+    #[default]
+    Tolerant,
+    /// 在 Tolerant 的基础上跳过无法解析的行
+    SkipBad,
+}
+
+/// origin-to-sim 子命令的参数
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OriginToSim {
+    pub file: String,
+    /// 是否保留时间戳
+    pub timestamp: bool,
+    pub parse: ParseMode,
+    /// 只转换 hostname、devname 为该值的请求，为 None 时不做筛选
+    pub hostname: Option<String>,
+    pub devname: Option<String>,
+    /// 是否按设备输出到不同的文件
+    pub split_device: bool,
+    /// 输出文件已经存在时是否覆盖
+    pub force: bool,
+}
+
+impl OriginToSim {
+    pub fn new(file: &str, timestamp: bool) -> OriginToSim {
+        OriginToSim { file: file.to_string(), timestamp, ..OriginToSim::default() }
+    }
+}
+
+/// origin-to-sim 子命令输出的统计信息
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OriginToSimStats {
+    pub file: String,
+    /// 读取的行数，以及其中的表头行和空行数量
+    pub lines: u64,
+    pub headers: u64,
+    pub blank: u64,
+    /// 转换的请求数量，以及因 hostname/devname 不符被过滤的请求数量
+    pub converted: u64,
+    pub filtered: u64,
+    /// 无法解析而被跳过的行数，以及按原因(出错的列名，列数不对时为 columns)统计的行数
+    pub skipped: u64,
+    pub skipped_reasons: BTreeMap<String, u64>,
+    /// 每个输出文件的请求数量
+    pub outputs: Vec<OriginOutput>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OriginOutput {
+    pub file: String,
+    pub requests: u64,
+}
+
+impl Report for OriginToSimStats {
+    fn to_text(&self) -> String {
+        let reasons: Vec<String> = self.skipped_reasons.iter().map(|(reason, n)| format!("{}: {}", reason, n)).collect();
+        let mut text = format!(
+            "file: {}\n\
+            lines: {:<10}  headers: {:<10}  blank: {}\n\
+            converted: {:<10}  filtered: {:<10}  skipped: {} ({})",
+            self.file,
+            self.lines,
+            self.headers,
+            self.blank,
+            self.converted,
+            self.filtered,
+            self.skipped,
+            if reasons.is_empty() { String::from("-") } else { reasons.join(", ") },
+        );
+        for output in &self.outputs {
+            text += &format!("\n{}: requests: {}", output.file, output.requests);
+        }
+        text
+    }
+}
+
+/// 一行原始 trace 解析后的结果，时间戳的单位为 100 ns
+struct Row<'a> {
+    timestamp: &'a str,
+    hostname: &'a str,
+    devname: &'a str,
+    record: TraceRecord,
+}
+
+/// 一行无法解析的原因，column 为出错的列，列数不对时为缺少的第一列(或多出的第一列)
+struct RowError {
+    column: usize,
+    /// 是否为列数不对
+    count: bool,
+    source: Option<BoxError>,
+}
+
+impl RowError {
+    fn new(column: usize, source: Option<BoxError>) -> RowError {
+        RowError { column, count: false, source }
+    }
+
+    fn count(len: usize) -> RowError {
+        RowError {
+            column: len,
+            count: true,
+            source: Some(format!("应有 {} 列，实际为 {} 列", COLUMNS.len(), len).into()),
+        }
+    }
+
+    /// 跳过原因中使用的名称
+    fn reason(&self) -> &'static str {
+        match self.count {
+            true => COLUMN_COUNT,
+            false => COLUMNS[self.column],
+        }
+    }
+}
+
+/// 解析一行原始 trace，strict 为 false 时容忍大小写不同的读写类型和行尾的空列
+fn parse_row<'a>(columns: &[&'a str], strict: bool) -> Result<Row<'a>, RowError> {
+    let extra = columns.get(COLUMNS.len()..).unwrap_or_default();
+    if columns.len() < COLUMNS.len() || (!extra.is_empty() && (strict || extra.iter().any(|column| !column.is_empty()))) {
+        return Err(RowError::count(columns.len()));
+    }
+
+    // 模拟器 trace 第一个参数: 读写
+    let rw = columns[3];
+    let op = if rw == "Read" || (!strict && rw.eq_ignore_ascii_case("read")) {
+        Op::Read
+    } else if rw == "Write" || (!strict && rw.eq_ignore_ascii_case("write")) {
+        Op::Write
+    } else {
+        return Err(RowError::new(3, None));
+    };
+
+    // 模拟器 trace 第三、四个参数: 偏移量和长度，第二个参数 Hit 和第五个参数服务时间使用默认值
+    let offset = columns[4].parse::<u64>().map_err(|e| RowError::new(4, Some(Box::new(e))))? / 512;
+    let length = columns[5].parse::<u64>().map_err(|e| RowError::new(5, Some(Box::new(e))))? / 512;
+
+    Ok(Row {
+        timestamp: columns[0],
+        hostname: columns[1],
+        devname: columns[2],
+        record: TraceRecord::new(op, offset, length),
+    })
+}
+
+/// 一个输出文件，时间间隔为与同一文件中下一个请求的时间戳之差
+struct Output {
+    writer: RetimingWriter<Box<dyn Write>>,
+    path: String,
+    // 第一个请求的时间戳，到达时间从它开始计算，避免大的时间戳相减损失精度
+    first_timestamp: Option<f64>,
+}
+
+impl Output {
+    fn create(path: String, force: bool) -> Result<Output, HMSimError> {
+        Ok(Output { writer: RetimingWriter::new(open_output(&path, force)?), path, first_timestamp: None })
+    }
+}
+
+pub fn origin_to_sim(origin: &OriginToSim) -> Result<OriginToSimStats, HMSimError> {
+    let filename = origin.file.as_str();
+    let file = File::open(filename).map_err(|e| HMSimError::file(filename, e))?;
 
     // 将文件后缀替换成 .trace
     let path = Path::new(filename);
 
     // parent 获取文件父目录，file_stem 获取不包含扩展名的文件名
-    let new_filename = format!("{}.trace", path.file_stem().unwrap().to_string_lossy().to_string());
-
-    remove_file(&new_filename)?;
-    let mut output_file = OpenOptions::new()
-        .append(true)
-        .create(true)
-        // .write(true)
-        // .truncate(true)
-        .open(new_filename)
-        .unwrap();
+    let stem = match path.file_stem() {
+        Some(stem) => stem.to_string_lossy().to_string(),
+        None => return Err(HMSimError::CommandError(format!("{} 不是合法的文件名", filename))),
+    };
 
     // 使用 BufReader 包装文件，以便按行读取
     let reader = BufReader::new(file);
 
-    let rw = vec!["R", "W"];
-    let mut nr = 1;
-    let mut pre_timestamp;
-    let mut next_timestamp = 0.0;
-
-    // 遍历每一行并将其存储为 String
-    for line in reader.lines() {
-        let mut new_vec = vec![];
+    // 按设备名排列的输出文件，不按设备输出时只有一个，键为空
+    let mut outputs: BTreeMap<(String, String), Output> = BTreeMap::new();
+    let mut stats = OriginToSimStats { file: filename.to_string(), ..OriginToSimStats::default() };
 
-        // 每一行按照逗号(,)分隔，每一列含义见文件头注释
-        let line = line?;
-        let line: Vec<&str> = line.split(',').map(|item| item.trim()).collect();
+    // 转换失败时删除已经创建的输出文件，否则重新运行时需要指定 --force
+    if let Err(e) = convert(origin, reader, &stem, &mut outputs, &mut stats) {
+        for output in outputs.into_values() {
+            drop(output.writer);
+            let _ = fs::remove_file(output.path);
+        }
+        return Err(e);
+    }
+    stats.outputs = outputs
+        .into_values()
+        .map(|output| OriginOutput { file: output.path, requests: output.writer.requests() })
+        .collect();
 
-        let rw_flag = if line[3].eq("Read") {
-            0
-        } else if line[3].eq("Write") {
-            1
-        } else {
-            return Err(HMSimError::FileError);
-        };
+    if stats.skipped > MAX_WARNINGS {
+        warn!("共跳过 {} 行，只列出了前 {} 行", stats.skipped, MAX_WARNINGS);
+    }
+    info!("origin_to_sim running done.");
+    Ok(stats)
+}
 
-        // 模拟器 trace 第一个参数: 读写
-        new_vec.push(rw[rw_flag]);
+/// 逐行转换 reader 中的原始 trace，写入 outputs 中的输出文件并刷新
+fn convert(
+    origin: &OriginToSim,
+    reader: impl BufRead,
+    stem: &str,
+    outputs: &mut BTreeMap<(String, String), Output>,
+    stats: &mut OriginToSimStats,
+) -> Result<(), HMSimError> {
+    let filename = origin.file.as_str();
+    let strict = origin.parse == ParseMode::Strict;
 
-        // 模拟器 trace 第二个参数: Hit
-        new_vec.push("Hit");
+    // 遍历每一行并将其存储为 String
+    for (index, line) in reader.lines().enumerate() {
+        let line_no = index as u64 + 1;
+        stats.lines += 1;
 
-        // 模拟器 trace 第三个参数: 偏移量
-        let offset = (line[4].parse::<u64>().unwrap() / 512).to_string();
-        new_vec.push(offset.as_str());
+        // 每一行按照逗号(,)分隔，每一列含义见文件头注释
+        let line = line.map_err(|e| HMSimError::file(filename, e))?;
+        if !strict && line.trim().is_empty() {
+            stats.blank += 1;
+            continue;
+        }
+        let line: Vec<&str> = line.split(',').map(|item| item.trim()).collect();
 
-        // 模拟器 trace 第四个参数: 长度
-        let length = (line[5].parse::<u64>().unwrap() / 512).to_string();
-        new_vec.push(length.as_str());
+        // 第一个请求之前时间戳不是数字、读写类型也不合法的行视为表头
+        let is_op = |rw: &str| rw.eq_ignore_ascii_case("read") || rw.eq_ignore_ascii_case("write");
+        if !strict
+            && stats.converted + stats.filtered + stats.skipped == 0
+            && line[0].parse::<f64>().is_err()
+            && !line.get(3).is_some_and(|rw| is_op(rw))
+        {
+            stats.headers += 1;
+            continue;
+        }
 
-        // 模拟器 trace 第五个参数: 服务时间
-        new_vec.push("0.000000");
+        let error = |e: RowError| HMSimError::LineError {
+            path: filename.to_string(),
+            line: line_no,
+            column: COLUMNS.get(e.column).copied().unwrap_or(COLUMN_COUNT),
+            token: line.get(e.column).unwrap_or(&"").to_string(),
+            source: e.source,
+        };
 
-        // 模拟器 trace 第六个参数: 时间戳
-        let inter;
+        let parsed = parse_row(&line, strict).and_then(|row| {
+            // 模拟器 trace 第六个参数: 时间戳
+            let timestamp = match origin.timestamp {
+                true => Some(row.timestamp.parse::<f64>().map_err(|e| RowError::new(0, Some(Box::new(e))))?),
+                false => None,
+            };
+            Ok((row, timestamp))
+        });
+        let (row, timestamp) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) if origin.parse == ParseMode::SkipBad => {
+                stats.skipped += 1;
+                *stats.skipped_reasons.entry(e.reason().to_string()).or_default() += 1;
+                if stats.skipped <= MAX_WARNINGS {
+                    warn!("跳过 {}", error(e));
+                }
+                continue;
+            },
+            Err(e) => return Err(error(e)),
+        };
 
-        if timestamp {
-            if nr == 1 {
-                pre_timestamp = line[0].parse::<f64>().unwrap();
-                next_timestamp = line[0].parse::<f64>().unwrap();
-                nr = 0;
-            } else {
-                pre_timestamp = next_timestamp;
-                next_timestamp = line[0].parse::<f64>().unwrap();
-            }
+        if origin.hostname.as_deref().is_some_and(|hostname| hostname != row.hostname)
+            || origin.devname.as_deref().is_some_and(|devname| devname != row.devname)
+        {
+            stats.filtered += 1;
+            continue;
+        }
 
-            inter = ((next_timestamp - pre_timestamp) / 10000 as f64).to_string();
+        let key = if origin.split_device {
+            (row.hostname.to_string(), row.devname.to_string())
         } else {
-            inter = String::from("0.000000");
+            (String::new(), String::new())
+        };
+        if !outputs.contains_key(&key) {
+            let path = if origin.split_device {
+                format!("{}-{}-{}.trace", stem, row.hostname, row.devname)
+            } else {
+                format!("{}.trace", stem)
+            };
+            // 将转换后的结果写入新文件，文件已经存在时需要指定 force
+            outputs.insert(key.clone(), Output::create(path, origin.force)?);
         }
-        new_vec.push(inter.as_str());
+        let output = outputs.get_mut(&key).expect("输出文件已创建");
 
-        // 将转换后的结果写入新文件，如果文件存在则重新创建
+        // 到达时间的单位为 ms，不保留时间戳时所有请求的时间间隔为 0
+        let arrival = match timestamp {
+            Some(timestamp) => (timestamp - *output.first_timestamp.get_or_insert(timestamp)) / 10000_f64,
+            None => 0.0,
+        };
+        output.writer.write_record(arrival, row.record).map_err(|e| e.with_path(&output.path))?;
+        stats.converted += 1;
+    }
 
-        output_file.write_all(new_vec.join(" ").as_bytes()).unwrap();
-        output_file.write_all("\n".as_bytes()).unwrap();
+    // 没有任何请求时仍然生成一个空的输出文件
+    if outputs.is_empty() && !origin.split_device {
+        outputs.insert((String::new(), String::new()), Output::create(format!("{}.trace", stem), origin.force)?);
+    }
+    for output in outputs.values_mut() {
+        output.writer.flush().map_err(|e| e.with_path(&output.path))?;
     }
-    info!("origin_to_sim running done.");
     Ok(())
 }
 
@@ -121,8 +414,25 @@ mod tests {
         let path = Path::new("test.csv");
 
         // let new_filename = format!("{}/{}.trace", path.parent().unwrap().to_string_lossy().to_string(), path.file_stem().unwrap().to_string_lossy().to_string());
-        let new_filename = format!("{}.trace", path.file_stem().unwrap().to_string_lossy().to_string());
+        let new_filename = format!("{}.trace", path.file_stem().unwrap().to_string_lossy());
 
         println!("{}", new_filename);
     }
+
+    #[test]
+    fn test_parse_row() {
+        let row = |line: &'static str, strict: bool| {
+            let columns: Vec<&str> = line.split(',').map(|item| item.trim()).collect();
+            parse_row(&columns, strict).map(|row| (row.devname, row.record.op, row.record.offset_sectors)).map_err(|e| e.reason())
+        };
+
+        assert_eq!(row("128166372005310872,ts,0,Write,4096,4096,63790", true), Ok(("0", Op::Write, 8)));
+        // 大小写不同的读写类型和行尾的空列
+        assert_eq!(row("128166372005310872,ts,1,read,4096,4096,63790,,", false), Ok(("1", Op::Read, 8)));
+        assert_eq!(row("128166372005310872,ts,1,read,4096,4096,63790", true), Err("rw"));
+        assert_eq!(row("128166372005310872,ts,1,Read,4096,4096,63790,,", true), Err("columns"));
+        assert_eq!(row("128166372005310872,ts,1,Read,4096,4096,63790,x", false), Err("columns"));
+        assert_eq!(row("128166372005310872,ts,1,Read,4096", false), Err("columns"));
+        assert_eq!(row("128166372005310872,ts,1,Read,-1,4096,63790", false), Err("offset"));
+    }
 }
@@ -0,0 +1,382 @@
+//! sample 子命令：按比例从 HMSim trace 中抽样，得到规模更小的 trace
+//!
+//! - uniform：每个请求以 rate 的概率独立地被保留；
+//! - time：把时间划分为长度为 period 的周期，只保留每个周期开头 rate * period 内到达的请求；
+//! - spatial：参照 SHARDS，对请求起始地址所在的块(block)做哈希，哈希值落在前 rate 比例内的块上的请求全部保留，
+//!   同一个块的所有访问要么都被保留、要么都被丢弃，因此重复访问、落盘量等局部性统计与原 trace 成比例。
+//!
+//! 保留下来的请求之间的时间间隔重新计算，使它们的到达时间与原 trace 中相同。
+//! 输出到文件时，抽样参数记录在 `<output>.sample.json` 中，trace-foot-size 据此把统计结果按 1 / rate 放大。
+
+use std::{fmt, fs, io::{self, Write}};
+
+use log::{info, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::HMSimError,
+    output::{ratio, Report},
+    trace::{open_output, RetimingWriter, TraceReader, TraceRecord},
+    utils::{hmsim_block_to_string, seeded_rng, string_to_hmsim_block},
+    TraceRng,
+};
+
+/// SHARDS 中哈希值的模数 P，块的哈希值对 P 取模后小于 rate * P 时保留
+const HASH_MODULUS: u64 = 1 << 24;
+
+/// 抽样方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleMode {
+    /// 按请求均匀抽样
+    #[default]
+    Uniform,
+    /// 按时间窗口抽样
+    Time,
+    /// 按地址哈希抽样(SHARDS)
+    Spatial,
+}
+
+impl fmt::Display for SampleMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleMode::Uniform => f.write_str("uniform"),
+            SampleMode::Time => f.write_str("time"),
+            SampleMode::Spatial => f.write_str("spatial"),
+        }
+    }
+}
+
+/// sample 的命令行参数
+#[derive(Debug, Clone, clap::Args)]
+pub struct SampleArgs {
+    /// 输入的 trace 文件名
+    #[arg(short, long)]
+    pub file: String,
+
+    /// 输出的 trace 文件名，`-` 表示输出到 stdout(此时统计结果输出到 stderr，不记录抽样参数)
+    #[arg(short, long, default_value = "sampled.trace")]
+    pub output: String,
+
+    /// 抽样方式 [uniform: 按请求均匀抽样, time: 按时间窗口抽样, spatial: 按地址哈希抽样]
+    #[arg(short, long, value_enum, ignore_case = true, default_value_t = SampleMode::Uniform)]
+    pub mode: SampleMode,
+
+    /// 抽样比例，范围为 (0, 1]，例如 0.01
+    #[arg(short, long)]
+    pub rate: f64,
+
+    /// time 抽样的周期(单位: 毫秒)，每个周期只保留开头 rate * period 内到达的请求
+    #[arg(long, value_name = "ms", default_value_t = 1000.0)]
+    pub period: f64,
+
+    /// spatial 抽样的块大小，按请求起始地址所在的块抽样，需要加上单位
+    #[arg(long, default_value = "4K")]
+    pub block: String,
+
+    /// 随机数种子，uniform 用于生成随机数，spatial 用于哈希，不指定时随机生成
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+impl SampleArgs {
+    /// 解析所有参数，块大小转化为扇区
+    pub fn resolve(&self) -> Result<Sample, HMSimError> {
+        let sample = Sample {
+            file: self.file.clone(),
+            output: self.output.clone(),
+            force: false,
+            mode: self.mode,
+            rate: self.rate,
+            period: self.period,
+            block: string_to_hmsim_block(&self.block)?.block,
+            seed: self.seed,
+        };
+        sample.validate()?;
+        Ok(sample)
+    }
+}
+
+/// 抽样参数，block 的单位为扇区，period 的单位为毫秒
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    /// 输入和输出的 trace 文件名，输出文件已经存在时是否覆盖，不记录在抽样信息中
+    #[serde(skip)]
+    pub file: String,
+    #[serde(skip)]
+    pub output: String,
+    #[serde(skip)]
+    pub force: bool,
+    pub mode: SampleMode,
+    pub rate: f64,
+    pub period: f64,
+    pub block: u64,
+    pub seed: Option<u64>,
+}
+
+impl Default for Sample {
+    fn default() -> Self {
+        Sample {
+            file: String::new(),
+            output: String::new(),
+            force: false,
+            mode: SampleMode::Uniform,
+            rate: 1.0,
+            period: 1000.0,
+            block: 8,
+            seed: None,
+        }
+    }
+}
+
+impl Sample {
+    pub fn validate(&self) -> Result<(), HMSimError> {
+        if !(self.rate > 0.0 && self.rate <= 1.0) {
+            return Err(HMSimError::CommandError(format!("rate 的范围为 (0, 1]，实际为 {}", self.rate)));
+        }
+        if !(self.period.is_finite() && self.period > 0.0) {
+            return Err(HMSimError::CommandError(format!("period 应为正数，实际为 {}", self.period)));
+        }
+        if self.block == 0 {
+            return Err(HMSimError::CommandError(String::from("block 不能小于一个扇区(512B)")));
+        }
+        Ok(())
+    }
+}
+
+/// 记录在 `<output>.sample.json` 中的抽样信息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SampleMeta {
+    /// 原 trace 文件名
+    pub source: String,
+    /// 抽样参数，seed 为实际使用的种子
+    pub sample: Sample,
+    pub scanned: u64,
+    pub kept: u64,
+    /// 抽样得到的 trace 文件的字节数，用于发现 trace 被重新生成或修改后留下的过期抽样信息
+    pub bytes: u64,
+}
+
+/// trace 对应的抽样信息文件名
+pub fn meta_path(trace: &str) -> String {
+    format!("{}.sample.json", trace)
+}
+
+/// 读取 trace 的抽样信息，trace 不是抽样得到的(没有抽样信息文件)或抽样信息已经过期时返回 None
+pub fn load_meta(trace: &str) -> Result<Option<SampleMeta>, HMSimError> {
+    let path = meta_path(trace);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(HMSimError::file(path, e)),
+    };
+    let meta: SampleMeta = serde_json::from_str(&content)
+        .map_err(|e| HMSimError::CommandError(format!("无法解析抽样信息 {}: {}", path, e)))?;
+    let bytes = fs::metadata(trace).map_err(|e| HMSimError::file(trace, e))?.len();
+    if bytes != meta.bytes {
+        warn!("{} 与 trace 不一致(记录的大小为 {}B，实际为 {}B)，忽略该抽样信息", path, meta.bytes, bytes);
+        return Ok(None);
+    }
+    Ok(Some(meta))
+}
+
+/// sample 子命令输出的统计信息，数据量单位为扇区(512B)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SampleStats {
+    pub file: String,
+    pub output: String,
+    pub mode: SampleMode,
+    pub rate: f64,
+    /// 实际使用的种子，time 抽样时为空
+    pub seed: Option<u64>,
+    /// 读取和保留的请求数量及数据量
+    pub scanned: u64,
+    pub kept: u64,
+    pub scanned_data: u64,
+    pub kept_data: u64,
+    /// 保留的请求数量占读取的请求数量的比例
+    pub effective_rate: Option<f64>,
+}
+
+impl Report for SampleStats {
+    fn to_text(&self) -> String {
+        format!(
+            "file: {}  output: {}\n\
+            mode: {}  rate: {}  seed: {}\n\
+            scanned: {:<10}  kept: {:<10}  effective_rate: {}\n\
+            scanned_data: {:<10}MB  kept_data: {:<10}MB",
+            self.file,
+            self.output,
+            self.mode,
+            self.rate,
+            self.seed.map_or(String::from("-"), |seed| seed.to_string()),
+            self.scanned,
+            self.kept,
+            self.effective_rate.map_or(String::from("-"), |rate| format!("{:.6}", rate)),
+            self.scanned_data as f32 / 2048f32,
+            self.kept_data as f32 / 2048f32,
+        )
+    }
+}
+
+/// 判断请求是否被保留
+enum Sampler {
+    Uniform { rng: Box<TraceRng>, rate: f64 },
+    Time { period: f64, window: f64 },
+    Spatial { salt: u64, block: u64, threshold: u64 },
+}
+
+impl Sampler {
+    /// 由抽样参数构造，返回 (抽样器, 实际使用的种子)
+    fn new(sample: &Sample) -> (Sampler, Option<u64>) {
+        match sample.mode {
+            SampleMode::Uniform => {
+                let (rng, seed) = seeded_rng(sample.seed);
+                (Sampler::Uniform { rng: Box::new(rng), rate: sample.rate }, Some(seed))
+            },
+            SampleMode::Time => (Sampler::Time { period: sample.period, window: sample.period * sample.rate }, None),
+            SampleMode::Spatial => {
+                let (_, seed) = seeded_rng(sample.seed);
+                let threshold = (sample.rate * HASH_MODULUS as f64).round() as u64;
+                (Sampler::Spatial { salt: seed, block: sample.block, threshold }, Some(seed))
+            },
+        }
+    }
+
+    /// 到达时间为 arrival 的请求是否被保留
+    fn keep(&mut self, arrival: f64, record: &TraceRecord) -> bool {
+        match self {
+            Sampler::Uniform { rng, rate } => rng.gen::<f64>() < *rate,
+            Sampler::Time { period, window } => arrival.rem_euclid(*period) < *window,
+            Sampler::Spatial { salt, block, threshold } => {
+                hash(record.offset_sectors / *block, *salt) % HASH_MODULUS < *threshold
+            },
+        }
+    }
+}
+
+/// 块号的哈希(splitmix64)，与平台和运行次数无关，保证同一个块的判断结果相同
+fn hash(block: u64, salt: u64) -> u64 {
+    let mut z = block ^ salt.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// 对外暴露的函数，从 file 中抽样写入 output，返回统计信息
+pub fn sample(sample: &Sample) -> Result<SampleStats, HMSimError> {
+    sample.validate()?;
+    let (file, output) = (&sample.file, &sample.output);
+    if file == output {
+        return Err(HMSimError::CommandError(format!("输出文件 {} 不能同时是输入文件", output)));
+    }
+    let reader = TraceReader::open(file)?;
+    let mut writer = RetimingWriter::new(open_output(output, sample.force)?);
+    let (mut sampler, seed) = Sampler::new(sample);
+    let mut stats = sample_records(reader, &mut writer, &mut sampler)?;
+    writer.flush().map_err(|e| e.with_path(output))?;
+    drop(writer);
+
+    stats.file = file.clone();
+    stats.output = output.clone();
+    stats.mode = sample.mode;
+    stats.rate = sample.rate;
+    stats.seed = seed;
+
+    if output != "-" {
+        let meta = SampleMeta {
+            source: file.clone(),
+            sample: Sample { seed, ..sample.clone() },
+            scanned: stats.scanned,
+            kept: stats.kept,
+            bytes: fs::metadata(output).map_err(|e| HMSimError::file(output, e))?.len(),
+        };
+        let json = serde_json::to_string_pretty(&meta).map_err(io::Error::from)?;
+        fs::write(meta_path(output), json).map_err(|e| HMSimError::file(meta_path(output), e))?;
+    }
+
+    info!(
+        "sample running done, {} of {} requests kept (block: {}).",
+        stats.kept,
+        stats.scanned,
+        hmsim_block_to_string(sample.block)
+    );
+    Ok(stats)
+}
+
+/// 抽样并写出，保留下来的请求的时间间隔改为与下一个保留的请求的到达时间之差，最后一个请求保留原来的时间间隔
+fn sample_records<I, W>(records: I, writer: &mut RetimingWriter<W>, sampler: &mut Sampler) -> Result<SampleStats, HMSimError>
+where
+    I: Iterator<Item = Result<TraceRecord, HMSimError>>,
+    W: Write,
+{
+    let mut stats = SampleStats::default();
+    let mut clock = 0.0;
+
+    for record in records {
+        let record = record?;
+        let arrival = clock;
+        clock += record.next_inter;
+        stats.scanned += 1;
+        stats.scanned_data += record.len_sectors;
+
+        if !sampler.keep(arrival, &record) {
+            continue;
+        }
+        stats.kept += 1;
+        stats.kept_data += record.len_sectors;
+        writer.write_record(arrival, record)?;
+    }
+
+    stats.effective_rate = ratio(stats.kept, stats.scanned);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::TraceWriter;
+
+    fn run(sample: &Sample, input: &str) -> (SampleStats, Vec<TraceRecord>) {
+        let (mut sampler, _) = Sampler::new(sample);
+        let mut writer = RetimingWriter::new(TraceWriter::new(Vec::new()));
+        let stats = sample_records(TraceReader::new(input.as_bytes()), &mut writer, &mut sampler).unwrap();
+        let output = writer.into_inner().unwrap();
+        (stats, TraceReader::new(&output[..]).collect::<Result<_, _>>().unwrap())
+    }
+
+    #[test]
+    fn test_sample_records() {
+        // 到达时间为 0、1、...、9，time 抽样只保留每 4ms 的前 2ms
+        let input: String = (0..10).map(|i| format!("W Hit {} 8 0.000000 1.000000\n", i * 8)).collect();
+        let time = Sample { mode: SampleMode::Time, rate: 0.5, period: 4.0, ..Sample::default() };
+        let (stats, records) = run(&time, &input);
+        let kept: Vec<_> = records.iter().map(|r| (r.offset_sectors / 8, r.next_inter)).collect();
+        assert_eq!(kept, [(0, 1.0), (1, 3.0), (4, 1.0), (5, 3.0), (8, 1.0), (9, 1.0)]);
+        assert_eq!((stats.scanned, stats.kept, stats.kept_data), (10, 6, 48));
+
+        let all = Sample { rate: 1.0, seed: Some(1), ..Sample::default() };
+        assert_eq!(run(&all, &input).0.kept, 10);
+    }
+
+    #[test]
+    fn test_spatial() {
+        // 每个块访问 4 次，同一个块的访问要么都保留，要么都丢弃
+        let input: String = (0..4)
+            .flat_map(|_| (0..2000).map(|i| format!("R Hit {} 8 0.000000 0.000000\n", i * 8)))
+            .collect();
+        let spatial = Sample { mode: SampleMode::Spatial, rate: 0.1, seed: Some(7), ..Sample::default() };
+        let (stats, records) = run(&spatial, &input);
+        assert_eq!(stats.kept % 4, 0);
+        let mut blocks: Vec<u64> = records.iter().map(|r| r.offset_sectors).collect();
+        blocks.sort_unstable();
+        blocks.dedup();
+        assert_eq!(blocks.len() as u64 * 4, stats.kept);
+        assert!((100..300).contains(&blocks.len()), "{}", blocks.len());
+
+        // 种子相同时结果相同
+        assert_eq!(run(&spatial, &input).1, records);
+        assert!(Sample { rate: 0.0, ..Sample::default() }.validate().is_err());
+    }
+}
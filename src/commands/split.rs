@@ -0,0 +1,272 @@
+//! split 子命令：把一个 HMSim trace 切分为多个编号的分片，便于并行模拟
+//!
+//! 按请求数量(requests)、累计数据量(volume)或模拟时间(time)切分，三者最多指定一个：
+//! 前两种在当前分片达到指定值后开始新的分片，请求不会被拆开；
+//! time 按到达时间划分窗口，第 i 个分片包含到达时间在 [i * time, (i + 1) * time) 内的请求，没有请求的窗口不生成文件。
+//!
+//! 指定 by-op 时读请求和写请求分别输出到各自的文件，并各自按上述条件切分。
+//! 每个文件内的请求的到达时间差与原 trace 中相同，文件中最后一个请求保留原来的时间间隔。
+
+use std::io::Write;
+
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    error::HMSimError,
+    output::Report,
+    trace::{open_output, Op, RetimingWriter, TraceReader},
+    utils::{hmsim_block_to_string, string_to_hmsim_block},
+};
+
+/// split 的命令行参数
+#[derive(Debug, Clone, clap::Args)]
+pub struct SplitArgs {
+    /// 输入的 trace 文件名
+    #[arg(short, long)]
+    pub file: String,
+
+    /// 输出文件名的前缀，分片为 <prefix>-000.trace、<prefix>-001.trace 等，默认为输入文件名去掉 .trace
+    #[arg(short, long)]
+    pub prefix: Option<String>,
+
+    /// 每个分片的请求数量
+    #[arg(long, conflicts_with_all = ["volume", "time"])]
+    pub requests: Option<u64>,
+
+    /// 每个分片的数据量，达到该值后开始新的分片，需要加上单位，例如 10G
+    #[arg(long, conflicts_with = "time")]
+    pub volume: Option<String>,
+
+    /// 每个分片的模拟时长(单位: 毫秒)，按到达时间切分，例如 3600000 为一小时
+    #[arg(long, value_name = "ms")]
+    pub time: Option<f64>,
+
+    /// 读请求和写请求分别输出到 <prefix>-read-*.trace 和 <prefix>-write-*.trace
+    #[arg(long = "by-op")]
+    pub by_op: bool,
+}
+
+impl SplitArgs {
+    /// 解析所有参数，数据量转化为扇区
+    pub fn resolve(&self) -> Result<Split, HMSimError> {
+        let by = match (self.requests, &self.volume, self.time) {
+            (Some(requests), None, None) => Some(SplitBy::Requests(requests)),
+            (None, Some(volume), None) => Some(SplitBy::Volume(string_to_hmsim_block(volume)?.block)),
+            (None, None, Some(time)) => Some(SplitBy::Time(time)),
+            (None, None, None) => None,
+            _ => return Err(HMSimError::CommandError(String::from("requests、volume 和 time 最多只能指定一个"))),
+        };
+        let prefix = match &self.prefix {
+            Some(prefix) => prefix.clone(),
+            None => self.file.strip_suffix(".trace").unwrap_or(&self.file).to_string(),
+        };
+        let split = Split { file: self.file.clone(), prefix, by, by_op: self.by_op, force: false };
+        split.validate()?;
+        Ok(split)
+    }
+}
+
+/// 切分条件，数据量的单位为扇区，时间的单位为毫秒
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitBy {
+    Requests(u64),
+    Volume(u64),
+    Time(f64),
+}
+
+/// split 子命令的参数，by 为 None 时不切分(只能与 by_op 一起使用)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Split {
+    pub file: String,
+    pub prefix: String,
+    pub by: Option<SplitBy>,
+    pub by_op: bool,
+    /// 输出文件已经存在时是否覆盖
+    pub force: bool,
+}
+
+impl Split {
+    pub fn validate(&self) -> Result<(), HMSimError> {
+        match self.by {
+            None if !self.by_op => {
+                Err(HMSimError::CommandError(String::from("需要指定 requests、volume、time 或 by-op 中的至少一个")))
+            },
+            Some(SplitBy::Requests(0)) => Err(HMSimError::CommandError(String::from("requests 不能为 0"))),
+            Some(SplitBy::Volume(0)) => Err(HMSimError::CommandError(String::from("volume 不能小于一个扇区(512B)"))),
+            Some(SplitBy::Time(time)) if !(time.is_finite() && time > 0.0) => {
+                Err(HMSimError::CommandError(format!("time 应为正数，实际为 {}", time)))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// 请求所在的分片编号，`chunk` 为所在输出的当前分片及其已有的请求数量和数据量
+    fn chunk_of(&self, chunk: Option<&ChunkState>, arrival: f64) -> u64 {
+        match (self.by, chunk) {
+            (Some(SplitBy::Time(time)), _) => (arrival / time).floor() as u64,
+            (_, None) => 0,
+            (Some(SplitBy::Requests(requests)), Some(chunk)) if chunk.requests >= requests => chunk.index + 1,
+            (Some(SplitBy::Volume(volume)), Some(chunk)) if chunk.data >= volume => chunk.index + 1,
+            (_, Some(chunk)) => chunk.index,
+        }
+    }
+
+    /// 分片的文件名，label 为 read 或 write
+    fn output_name(&self, label: Option<&str>, index: u64) -> String {
+        match (label, self.by) {
+            (Some(label), None) => format!("{}-{}.trace", self.prefix, label),
+            (Some(label), Some(_)) => format!("{}-{}-{:03}.trace", self.prefix, label, index),
+            (None, _) => format!("{}-{:03}.trace", self.prefix, index),
+        }
+    }
+}
+
+/// 一个分片当前的编号、请求数量和数据量
+#[derive(Debug, Clone, Copy, Default)]
+struct ChunkState {
+    index: u64,
+    requests: u64,
+    data: u64,
+}
+
+/// split 子命令输出的统计信息
+#[derive(Debug, Clone, Serialize)]
+pub struct SplitStats {
+    pub file: String,
+    /// 读取的请求数量，以及每个分片的统计信息(按文件创建的顺序)
+    pub requests: u64,
+    pub outputs: Vec<SplitOutput>,
+}
+
+/// 一个分片的统计信息，数据量单位为扇区(512B)，时间单位为毫秒
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SplitOutput {
+    pub file: String,
+    pub requests: u64,
+    pub data: u64,
+    /// 第一个和最后一个请求在原 trace 中的到达时间
+    pub first_arrival: f64,
+    pub last_arrival: f64,
+}
+
+impl Report for SplitStats {
+    fn to_text(&self) -> String {
+        let mut text = format!("file: {}\nrequests: {:<10}  outputs: {}", self.file, self.requests, self.outputs.len());
+        for output in &self.outputs {
+            text += &format!(
+                "\n{}: requests: {}  data: {}  arrival: {:.3}..{:.3}ms",
+                output.file,
+                output.requests,
+                hmsim_block_to_string(output.data),
+                output.first_arrival,
+                output.last_arrival
+            );
+        }
+        text
+    }
+}
+
+/// 一路输出(全部请求、读请求或写请求)
+struct Stream {
+    label: Option<&'static str>,
+    chunk: Option<ChunkState>,
+    /// 当前分片的文件名及其统计信息在 outputs 中的下标
+    path: String,
+    output: usize,
+    writer: Option<RetimingWriter<Box<dyn Write>>>,
+}
+
+impl Stream {
+    fn new(label: Option<&'static str>) -> Stream {
+        Stream { label, chunk: None, path: String::new(), output: 0, writer: None }
+    }
+
+    /// 关闭当前的分片，分片中最后一个请求保留原来的时间间隔
+    fn close(&mut self) -> Result<(), HMSimError> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush().map_err(|e| e.with_path(&self.path))?;
+        }
+        Ok(())
+    }
+}
+
+/// 对外暴露的函数，切分 file 并返回每个分片的统计信息
+pub fn split(split: &Split) -> Result<SplitStats, HMSimError> {
+    split.validate()?;
+    let reader = TraceReader::open(&split.file)?;
+
+    let mut streams = if split.by_op {
+        vec![Stream::new(Some("read")), Stream::new(Some("write"))]
+    } else {
+        vec![Stream::new(None)]
+    };
+    let mut outputs: Vec<SplitOutput> = vec![];
+    let mut requests = 0;
+    let mut clock = 0.0;
+
+    for record in reader {
+        let record = record?;
+        let arrival = clock;
+        clock += record.next_inter;
+        requests += 1;
+
+        let stream = &mut streams[if split.by_op && record.op == Op::Write { 1 } else { 0 }];
+        let index = split.chunk_of(stream.chunk.as_ref(), arrival);
+        if stream.chunk.is_none_or(|chunk| chunk.index != index) {
+            stream.close()?;
+            stream.path = split.output_name(stream.label, index);
+            stream.writer = Some(RetimingWriter::new(open_output(&stream.path, split.force)?));
+            stream.chunk = Some(ChunkState { index, ..ChunkState::default() });
+            stream.output = outputs.len();
+            outputs.push(SplitOutput { file: stream.path.clone(), first_arrival: arrival, ..SplitOutput::default() });
+        }
+
+        let chunk = stream.chunk.as_mut().expect("分片已打开");
+        chunk.requests += 1;
+        chunk.data += record.len_sectors;
+        let output = &mut outputs[stream.output];
+        output.requests += 1;
+        output.data += record.len_sectors;
+        output.last_arrival = arrival;
+        stream.writer.as_mut().expect("分片已打开").write_record(arrival, record)?;
+    }
+
+    for stream in &mut streams {
+        stream.close()?;
+    }
+
+    info!("split running done, {} files written.", outputs.len());
+    Ok(SplitStats { file: split.file.clone(), requests, outputs })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_of() {
+        let split = |by| Split { file: String::new(), prefix: String::from("msr"), by, by_op: false, force: false };
+        let chunk = |index, requests, data| Some(ChunkState { index, requests, data });
+
+        let requests = split(Some(SplitBy::Requests(2)));
+        assert_eq!(requests.chunk_of(None, 5.0), 0);
+        assert_eq!(requests.chunk_of(chunk(0, 1, 8).as_ref(), 5.0), 0);
+        assert_eq!(requests.chunk_of(chunk(0, 2, 8).as_ref(), 5.0), 1);
+
+        // 达到数据量之后才开始新的分片，请求不会被拆开
+        let volume = split(Some(SplitBy::Volume(16)));
+        assert_eq!(volume.chunk_of(chunk(3, 1, 15).as_ref(), 0.0), 3);
+        assert_eq!(volume.chunk_of(chunk(3, 2, 40).as_ref(), 0.0), 4);
+
+        let time = split(Some(SplitBy::Time(1000.0)));
+        assert_eq!(time.chunk_of(None, 2500.0), 2);
+        assert_eq!(time.chunk_of(chunk(0, 100, 100).as_ref(), 999.9), 0);
+
+        assert_eq!(requests.output_name(None, 12), "msr-012.trace");
+        assert_eq!(requests.output_name(Some("read"), 1), "msr-read-001.trace");
+        assert_eq!(split(None).output_name(Some("write"), 0), "msr-write.trace");
+        assert!(split(None).validate().is_err());
+        assert!(split(Some(SplitBy::Time(0.0))).validate().is_err());
+    }
+}
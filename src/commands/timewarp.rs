@@ -0,0 +1,245 @@
+//! timewarp 子命令：改写 HMSim trace 中请求的时间间隔(nextinter)
+//!
+//! 时间间隔是 trace 中唯一的时间信息，改写它可以让同一个生产环境的 trace
+//! 以不同的负载强度驱动模拟器。先把超过 max-gap 的空闲时间截断为 max-gap，
+//! 再把所有时间间隔乘以 factor，或者按 iops 计算出的系数缩放；zero 把所有时间间隔置为 0(闭环回放)。
+
+use std::io::Write;
+
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    error::HMSimError,
+    output::Report,
+    trace::{open_output, TraceReader, TraceRecord, TraceWriter},
+};
+
+/// timewarp 的命令行参数，时间单位与 nextinter 相同，为毫秒
+#[derive(Debug, Clone, clap::Args)]
+pub struct TimewarpArgs {
+    /// 输入的 trace 文件名
+    #[arg(short, long)]
+    pub file: String,
+
+    /// 输出的 trace 文件名，`-` 表示输出到 stdout(此时统计结果输出到 stderr)
+    #[arg(short, long, default_value = "warped.trace")]
+    pub output: String,
+
+    /// 所有时间间隔乘以该系数，小于 1 为加速，大于 1 为减速，例如 0.5 表示负载强度加倍
+    #[arg(long, conflicts_with_all = ["iops", "zero"])]
+    pub factor: Option<f64>,
+
+    /// 按比例缩放所有时间间隔，使平均每秒请求数(请求数量 / 时间间隔之和)为该值
+    #[arg(long, conflicts_with = "zero")]
+    pub iops: Option<f64>,
+
+    /// 超过该值的时间间隔(空闲时间)截断为该值，在 factor 或 iops 缩放之前进行
+    #[arg(long = "max-gap", value_name = "ms")]
+    pub max_gap: Option<f64>,
+
+    /// 所有时间间隔置为 0，用于闭环回放
+    #[arg(long, conflicts_with = "max_gap")]
+    pub zero: bool,
+}
+
+impl TimewarpArgs {
+    pub fn resolve(&self) -> Result<Timewarp, HMSimError> {
+        let timewarp = Timewarp {
+            file: self.file.clone(),
+            output: self.output.clone(),
+            force: false,
+            factor: self.factor,
+            iops: self.iops,
+            max_gap: self.max_gap,
+            zero: self.zero,
+        };
+        timewarp.validate()?;
+        Ok(timewarp)
+    }
+}
+
+/// 时间间隔的改写方式，时间单位为毫秒
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timewarp {
+    /// 输入和输出的 trace 文件名，输出文件已经存在时是否覆盖
+    pub file: String,
+    pub output: String,
+    pub force: bool,
+    /// 所有时间间隔乘以该系数，小于 1 为加速，大于 1 为减速
+    pub factor: Option<f64>,
+    /// 缩放时间间隔，使平均每秒请求数为该值
+    pub iops: Option<f64>,
+    /// 超过该值的时间间隔截断为该值，在缩放之前进行
+    pub max_gap: Option<f64>,
+    /// 所有时间间隔置为 0
+    pub zero: bool,
+}
+
+impl Timewarp {
+    /// 检查参数，factor、iops 和 zero 最多指定一个
+    pub fn validate(&self) -> Result<(), HMSimError> {
+        let modes = [self.factor.is_some(), self.iops.is_some(), self.zero].iter().filter(|&&mode| mode).count();
+        if modes > 1 {
+            return Err(HMSimError::CommandError(String::from("factor、iops 和 zero 最多只能指定一个")));
+        }
+        if modes == 0 && self.max_gap.is_none() {
+            return Err(HMSimError::CommandError(String::from("需要指定 factor、iops、max-gap 或 zero 中的至少一个")));
+        }
+        for (name, value) in [("factor", self.factor), ("iops", self.iops)] {
+            if let Some(value) = value.filter(|value| !(value.is_finite() && *value > 0.0)) {
+                return Err(HMSimError::CommandError(format!("{} 应为正数，实际为 {}", name, value)));
+            }
+        }
+        if let Some(max_gap) = self.max_gap.filter(|max_gap| !(max_gap.is_finite() && *max_gap >= 0.0)) {
+            return Err(HMSimError::CommandError(format!("max-gap 应为非负数，实际为 {}", max_gap)));
+        }
+        Ok(())
+    }
+
+    /// 截断空闲时间之后的时间间隔
+    fn clamp(&self, gap: f64) -> f64 {
+        match self.max_gap {
+            Some(max_gap) => gap.min(max_gap),
+            None => gap,
+        }
+    }
+}
+
+/// timewarp 子命令输出的统计信息，时长单位为秒
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TimewarpStats {
+    pub file: String,
+    pub output: String,
+    pub requests: u64,
+    /// 被 max-gap 截断的时间间隔数量
+    pub clamped: u64,
+    /// 实际使用的缩放系数，zero 时为 0
+    pub factor: f64,
+    /// 改写前后的模拟时长(所有请求的时间间隔之和)，以及平均每秒请求数(时长为 0 时为空)
+    pub input_duration: f64,
+    pub output_duration: f64,
+    pub input_iops: Option<f64>,
+    pub output_iops: Option<f64>,
+}
+
+impl Report for TimewarpStats {
+    fn to_text(&self) -> String {
+        let iops = |iops: Option<f64>| iops.map_or(String::from("-"), |iops| format!("{:.3}", iops));
+        format!(
+            "file: {}  output: {}\n\
+            requests: {:<10}  clamped: {:<10}  factor: {}\n\
+            duration: {:.3}s -> {:.3}s  iops: {} -> {}",
+            self.file,
+            self.output,
+            self.requests,
+            self.clamped,
+            self.factor,
+            self.input_duration,
+            self.output_duration,
+            iops(self.input_iops),
+            iops(self.output_iops),
+        )
+    }
+}
+
+/// 对外暴露的函数，改写 file 中请求的时间间隔写入 output，返回统计信息
+///
+/// 指定 iops 时需要先读一遍 file 统计截断后的总时长
+pub fn timewarp(timewarp: &Timewarp) -> Result<TimewarpStats, HMSimError> {
+    timewarp.validate()?;
+    if timewarp.file == timewarp.output {
+        return Err(HMSimError::CommandError(format!("输出文件 {} 不能同时是输入文件", timewarp.output)));
+    }
+
+    let factor = match timewarp.iops {
+        Some(iops) => {
+            let (mut requests, mut clamped_gaps) = (0u64, 0.0);
+            for record in TraceReader::open(&timewarp.file)? {
+                requests += 1;
+                clamped_gaps += timewarp.clamp(record?.next_inter);
+            }
+            if clamped_gaps <= 0.0 {
+                return Err(HMSimError::CommandError(format!("{} 中的时间间隔之和为 0，无法按 iops 缩放", timewarp.file)));
+            }
+            // 目标时长为 requests / iops 秒，时间间隔的单位为毫秒
+            requests as f64 / iops * 1000.0 / clamped_gaps
+        },
+        None if timewarp.zero => 0.0,
+        None => timewarp.factor.unwrap_or(1.0),
+    };
+
+    let reader = TraceReader::open(&timewarp.file)?;
+    let mut writer = open_output(&timewarp.output, timewarp.force)?;
+    let mut stats = warp_records(reader, &mut writer, timewarp, factor)?;
+    writer.flush().map_err(|e| e.with_path(&timewarp.output))?;
+
+    info!("timewarp running done.");
+    stats.file = timewarp.file.clone();
+    stats.output = timewarp.output.clone();
+    Ok(stats)
+}
+
+/// 逐个改写时间间隔并写出
+fn warp_records<I, W>(records: I, writer: &mut TraceWriter<W>, timewarp: &Timewarp, factor: f64) -> Result<TimewarpStats, HMSimError>
+where
+    I: Iterator<Item = Result<TraceRecord, HMSimError>>,
+    W: Write,
+{
+    let mut stats = TimewarpStats { factor, ..TimewarpStats::default() };
+    let (mut input_clock, mut output_clock) = (0.0, 0.0);
+    for record in records {
+        let mut record = record?;
+        input_clock += record.next_inter;
+
+        let gap = timewarp.clamp(record.next_inter);
+        if gap < record.next_inter {
+            stats.clamped += 1;
+        }
+        record.next_inter = gap * factor;
+        output_clock += record.next_inter;
+
+        stats.requests += 1;
+        writer.write_record(&record)?;
+    }
+
+    stats.input_duration = input_clock / 1000.0;
+    stats.output_duration = output_clock / 1000.0;
+    let iops = |clock: f64| if clock > 0.0 { Some(stats.requests as f64 / clock * 1000.0) } else { None };
+    stats.input_iops = iops(input_clock);
+    stats.output_iops = iops(output_clock);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warp_records() {
+        let input = "W Hit 0 8 0.000000 1.000000\nW Hit 8 8 0.000000 100.000000\nR Hit 16 8 0.000000 3.000000\n";
+        let run = |timewarp: &Timewarp, factor: f64| {
+            let mut writer = TraceWriter::new(Vec::new());
+            let stats = warp_records(TraceReader::new(input.as_bytes()), &mut writer, timewarp, factor).unwrap();
+            let output = writer.into_inner().unwrap();
+            let gaps: Vec<f64> = TraceReader::new(&output[..]).map(|r| r.unwrap().next_inter).collect();
+            (stats, gaps)
+        };
+
+        let (stats, gaps) = run(&Timewarp { factor: Some(2.0), ..Timewarp::default() }, 2.0);
+        assert_eq!(gaps, [2.0, 200.0, 6.0]);
+        assert_eq!((stats.input_duration, stats.output_duration), (0.104, 0.208));
+
+        // 先截断再缩放
+        let clamp = Timewarp { max_gap: Some(10.0), factor: Some(0.5), ..Timewarp::default() };
+        let (stats, gaps) = run(&clamp, 0.5);
+        assert_eq!((gaps, stats.clamped), (vec![0.5, 5.0, 1.5], 1));
+
+        let (stats, gaps) = run(&Timewarp { zero: true, ..Timewarp::default() }, 0.0);
+        assert_eq!((gaps, stats.output_iops), (vec![0.0, 0.0, 0.0], None));
+
+        assert!(Timewarp::default().validate().is_err());
+        assert!(Timewarp { factor: Some(2.0), zero: true, ..Timewarp::default() }.validate().is_err());
+        assert!(Timewarp { iops: Some(-1.0), ..Timewarp::default() }.validate().is_err());
+    }
+}
@@ -1,198 +1,381 @@
-/// 该程序作用是计算 disksim 格式的 trace 实际占用的硬盘空间
-///
-/// 这和 trace 表示的数据量大小不同。
-///
-/// 前者会包含重复的请求，故不会重复计算
-///
-/// trace 实际占用的空间 footprint 只计算写请求。
+//! 该程序作用是计算 disksim 格式的 trace 实际占用的硬盘空间
+//!
+//! 这和 trace 表示的数据量大小不同。
+//!
+//! 前者会包含重复的请求，故不会重复计算
+//!
+//! trace 实际占用的空间 footprint 只计算写请求。
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{
+    commands::sample::{self, SampleMode},
+    error::HMSimError,
+    output::{ratio, Report},
+    trace::{Op, TraceReader},
+};
+
+/// 稀疏的区间集合，记录已经被访问过的扇区
 ///
+/// 以 start -> end(不包含) 的形式保存互不重叠、互不相邻的区间，
+/// 内存占用只与访问过的区间数量有关，支持任意 64 位的 LBA，
+/// 每个请求按区间整体处理，而不是逐个扇区处理。
+#[derive(Debug, Default)]
+pub struct ExtentMap {
+    extents: BTreeMap<u64, u64>,
+    sectors: u64,
+}
+
+impl ExtentMap {
+    pub fn new() -> ExtentMap {
+        ExtentMap::default()
+    }
+
+    /// 插入区间 [start, start + len)，返回其中新覆盖的扇区数
+    pub fn insert(&mut self, start: u64, len: u64) -> u64 {
+        if len == 0 {
+            return 0;
+        }
+        let mut new_start = start;
+        let mut new_end = start.saturating_add(len);
+
+        // 合并起始地址不大于 start 且与之重叠或相邻的区间
+        if let Some((&prev_start, &prev_end)) = self.extents.range(..=start).next_back() {
+            if prev_end >= start {
+                if prev_end >= new_end {
+                    return 0;
+                }
+                new_start = prev_start;
+            }
+        }
+
+        // 合并起始地址落在 [new_start, new_end] 内的区间
+        let merged: Vec<(u64, u64)> = self
+            .extents
+            .range(new_start..=new_end)
+            .map(|(&s, &e)| (s, e))
+            .collect();
+
+        let mut old_covered = 0;
+        for (s, e) in merged {
+            self.extents.remove(&s);
+            old_covered += e - s;
+            new_end = new_end.max(e);
+        }
+
+        self.extents.insert(new_start, new_end);
 
+        let added = (new_end - new_start) - old_covered;
+        self.sectors += added;
+        added
+    }
 
-use std::{fs::File, io::BufRead, io::BufReader};
+    /// 区间 [start, start + len) 中已经被覆盖的扇区数
+    pub fn covered(&self, start: u64, len: u64) -> u64 {
+        let end = start.saturating_add(len);
+        let mut covered = 0;
 
-use log::info;
+        if let Some((_, &prev_end)) = self.extents.range(..start).next_back() {
+            covered += prev_end.min(end).saturating_sub(start);
+        }
+        for (&s, &e) in self.extents.range(start..end) {
+            covered += e.min(end) - s;
+        }
+        covered
+    }
 
-use crate::error::HMSimError;
+    /// 所有区间覆盖的扇区总数
+    pub fn sectors(&self) -> u64 {
+        self.sectors
+    }
 
-// 位图数据结构
-struct BitMapRead {
-    map: Vec<u64>,
+    /// 区间数量
+    pub fn extent_count(&self) -> usize {
+        self.extents.len()
+    }
 }
 
-struct BitMapWrite {
-    map: Vec<u64>,
+/// trace 的统计结果，大小单位均为扇区(512B)
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FootSize {
+    /// 读、写请求数量
+    pub read_count: u64,
+    pub write_count: u64,
+
+    /// 读、写数据量(包含重复访问的扇区)
+    pub read_volume: u64,
+    pub write_volume: u64,
+
+    /// 读过的不重复扇区数
+    pub read_footprint: u64,
+    /// 写过的不重复扇区数，即落盘量
+    pub write_footprint: u64,
+    /// 读或写过的不重复扇区数
+    pub union_footprint: u64,
+
+    /// 访问过的最小和最大扇区地址，trace 为空时均为 0
+    pub min_lba: u64,
+    pub max_lba: u64,
+
+    /// 全部落在之前已经写过的区域内的读请求数量
+    pub read_after_write: u64,
+    /// 访问了从未写过的扇区的读请求数量，以及这部分扇区数
+    pub read_unwritten: u64,
+    pub read_unwritten_sectors: u64,
 }
 
-// BitMapRead 和 BitMapWrite 需要实现这个 trait
-trait BitOperation {
-    
-    // 获取底层位图数据
-    fn get_bitmap(&mut self) -> &mut Vec<u64>;
-
-    // 设置某一位(置为 1)
-    fn set_bit(&mut self, index: u64) -> bool {
-        let block = index / 64;
-        let bit = index % 64;
-        self.get_bitmap()[block as usize] |= 0x01 << bit;
-        true
-    }
-
-    // 重置某一位(置为 0)
-    fn reset_bit(&mut self, index: u64) -> bool {
-        let block = index / 64;
-        let bit = index % 64;
-        self.get_bitmap()[block as usize] &= 0x00 << bit;
-        true
-    }
-
-    // 判断某一位是否被设置
-    fn test_bit(&mut self, index: u64) -> bool {
-        let block = index / 64;
-        let bit = index % 64;
-        if (self.get_bitmap()[block as usize] & (0x01 << bit)) != 0 {
-            true
-        } else {
-            false
+impl FootSize {
+    /// 总请求数量
+    pub fn count(&self) -> u64 {
+        self.read_count + self.write_count
+    }
+
+    /// 总数据量
+    pub fn volume(&self) -> u64 {
+        self.read_volume + self.write_volume
+    }
+
+    /// 由抽样比例为 rate 的 trace 估计原 trace，统计结果乘以 1 / rate(四舍五入)
+    ///
+    /// 请求数量和数据量与抽样方式无关；落盘量等局部性统计只有按地址抽样(spatial)时才与原 trace 成比例，
+    /// 按请求或按时间抽样会丢掉同一地址的部分访问，这些字段无法估计，为 None
+    pub fn estimate(&self, rate: f64, mode: SampleMode) -> FootSizeEstimate {
+        let scale = |value: u64| (value as f64 / rate).round() as u64;
+        let locality = |value: u64| if mode == SampleMode::Spatial { Some(scale(value)) } else { None };
+        FootSizeEstimate {
+            read_count: scale(self.read_count),
+            write_count: scale(self.write_count),
+            read_volume: scale(self.read_volume),
+            write_volume: scale(self.write_volume),
+            read_footprint: locality(self.read_footprint),
+            write_footprint: locality(self.write_footprint),
+            union_footprint: locality(self.union_footprint),
+            read_after_write: locality(self.read_after_write),
+            read_unwritten: locality(self.read_unwritten),
+            read_unwritten_sectors: locality(self.read_unwritten_sectors),
         }
     }
 }
 
-impl BitOperation for BitMapRead {
-    fn get_bitmap(&mut self) -> &mut Vec<u64> {
-        &mut self.map
+/// 由抽样 trace 估计的原 trace 的统计结果，字段含义与 `FootSize` 相同，无法估计的字段为 None
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FootSizeEstimate {
+    pub read_count: u64,
+    pub write_count: u64,
+    pub read_volume: u64,
+    pub write_volume: u64,
+    pub read_footprint: Option<u64>,
+    pub write_footprint: Option<u64>,
+    pub union_footprint: Option<u64>,
+    pub read_after_write: Option<u64>,
+    pub read_unwritten: Option<u64>,
+    pub read_unwritten_sectors: Option<u64>,
+}
+
+impl FootSizeEstimate {
+    /// 总数据量
+    pub fn volume(&self) -> u64 {
+        self.read_volume + self.write_volume
     }
 }
 
-impl BitOperation for BitMapWrite {
-    fn get_bitmap(&mut self) -> &mut Vec<u64> {
-        &mut self.map
+/// trace-foot-size 子命令输出的结果，大小单位均为扇区(512B)
+///
+/// 比例的分母为 0 时为 null
+#[derive(Debug, Clone, Serialize)]
+pub struct FootSizeReport {
+    pub file: String,
+    #[serde(flatten)]
+    pub stats: FootSize,
+    /// 总请求数量和总数据量
+    pub count: u64,
+    pub volume: u64,
+    /// 写请求数量与读请求数量之比
+    pub op_rate_w_r: Option<f64>,
+    /// 写数据量与读数据量之比
+    pub data_rate_w_r: Option<f64>,
+    /// 落盘量与写数据量之比，越小说明覆盖写越多
+    pub write_footprint_ratio: Option<f64>,
+    /// trace 由 sample 子命令抽样得到时的抽样比例和抽样方式，以及按 1 / sample_rate 放大后对原 trace 的估计
+    pub sample_rate: Option<f64>,
+    pub sample_mode: Option<SampleMode>,
+    pub estimated: Option<FootSizeEstimate>,
+}
+
+impl FootSizeReport {
+    pub fn new(file: &str, stats: FootSize) -> FootSizeReport {
+        FootSizeReport {
+            file: file.to_string(),
+            count: stats.count(),
+            volume: stats.volume(),
+            op_rate_w_r: ratio(stats.write_count, stats.read_count),
+            data_rate_w_r: ratio(stats.write_volume, stats.read_volume),
+            write_footprint_ratio: ratio(stats.write_footprint, stats.write_volume),
+            sample_rate: None,
+            sample_mode: None,
+            estimated: None,
+            stats,
+        }
+    }
+
+    /// 记录抽样比例和抽样方式，并估计原 trace 的统计结果
+    pub fn with_sample(mut self, rate: f64, mode: SampleMode) -> FootSizeReport {
+        self.sample_rate = Some(rate);
+        self.sample_mode = Some(mode);
+        self.estimated = Some(self.stats.estimate(rate, mode));
+        self
     }
 }
 
-// impl BitMapRead {
-//     fn new() -> Self {
-//         BitMapRead {
-//             map: vec![0; u32::MAX as usize],
-//         }
-//     }
-// }
-
-impl BitMapWrite {
-    fn new() -> Self {
-        BitMapWrite {
-            map: vec![0; u32::MAX as usize],
+impl Report for FootSizeReport {
+    fn to_text(&self) -> String {
+        let stats = &self.stats;
+        let mut text = format!(
+            "tracefile: {}\nfootprint: {:>10}\nvolume: {:>13}\n\
+            read_footprint: {:>10}    union_footprint: {:>10}\n\
+            read_volume: {:>13}    write_volume: {:>13}\n\
+            read_op: {:>17}    write_op: {:>17}\n\
+            min_lba: {:>17}    max_lba: {:>18}\n\
+            read_after_write: {:>8}    read_unwritten: {:>11} ({})",
+            self.file,
+            human_size(stats.write_footprint),
+            human_size(self.volume),
+            human_size(stats.read_footprint),
+            human_size(stats.union_footprint),
+            human_size(stats.read_volume),
+            human_size(stats.write_volume),
+            stats.read_count,
+            stats.write_count,
+            stats.min_lba,
+            stats.max_lba,
+            stats.read_after_write,
+            stats.read_unwritten,
+            human_size(stats.read_unwritten_sectors),
+        );
+        if let (Some(rate), Some(mode), Some(estimated)) = (self.sample_rate, self.sample_mode, &self.estimated) {
+            text += &format!(
+                "\nsample_rate: {} ({})  estimated footprint: {}  volume: {}  read_op: {}  write_op: {}",
+                rate,
+                mode,
+                estimated.write_footprint.map_or(String::from("-"), human_size),
+                human_size(estimated.volume()),
+                estimated.read_count,
+                estimated.write_count,
+            );
         }
+        text
+    }
+}
+
+/// trace-foot-size 子命令的结果
+///
+/// 未指定 sample_rate 和 sample_mode 时从 sample 子命令记录的抽样信息中读取，没有抽样比例时不做估计，
+/// 只指定了比例时按 uniform 抽样估计
+pub fn foot_size_report(
+    file: &str,
+    sample_rate: Option<f64>,
+    sample_mode: Option<SampleMode>,
+) -> Result<FootSizeReport, HMSimError> {
+    let meta = sample::load_meta(file)?;
+    let sample_rate = sample_rate.or(meta.as_ref().map(|meta| meta.sample.rate));
+    let sample_mode = sample_mode.or(meta.as_ref().map(|meta| meta.sample.mode)).unwrap_or_default();
+    if let Some(rate) = sample_rate.filter(|rate| !(*rate > 0.0 && *rate <= 1.0)) {
+        return Err(HMSimError::CommandError(format!("sample-rate 的范围为 (0, 1]，实际为 {}", rate)));
     }
+
+    let report = FootSizeReport::new(file, trace_foot_size(file)?);
+    Ok(match sample_rate {
+        Some(rate) => report.with_sample(rate, sample_mode),
+        None => report,
+    })
 }
 
-/// 计算 trace 的数据量及落盘量
-pub fn trace_foot_size(filename: &str) -> Result<(String, String), HMSimError> {
-    // 位图
-    // let mut bitmap_read = BitMapRead::new();
-    let mut bitmap_write = BitMapWrite::new();
+/// 计算 trace 的数据量、落盘量及读写统计信息
+pub fn trace_foot_size(filename: &str) -> Result<FootSize, HMSimError> {
+    let reader = TraceReader::open(filename)?;
 
-    let buf = BufReader::new(File::open(filename).unwrap());
+    // 已读过、已写过、读或写过的区间
+    let mut read = ExtentMap::new();
+    let mut written = ExtentMap::new();
+    let mut touched = ExtentMap::new();
 
     let mut max_index: u64 = 0;
     let mut min_index: u64 = u64::MAX;
 
-    let mut volume: u64 = 0;
-    let mut footprint: u64 = 0;
+    let mut stats = FootSize::default();
 
     // 从 trace 中解析读写、长度以及偏移量字段
-    for (index, line) in buf.lines().enumerate() {
-        // if index % 10000 == 0 {
-        //     info!("{}", index);
-        // }
-        let line = line?;
-
-        // 存储每一行 trace
-        let data: Vec<&str> = line.split(' ').collect();
-
-        update_min_max(&mut min_index, &mut max_index, data[2]);
-
-        let mut real: u64 = 0;
-        let offset: u64 = data[2].parse::<u64>().unwrap();
-        let len: u64 = data[3].parse::<u64>().unwrap();
-
-        if data[0].eq("W") {
-            for i in offset..offset + len {
-                // 如果该位没有设置，real++，然后将该位设置为 1
-                if bitmap_write.test_bit(i) == false {
-                    real += 1;
-                    bitmap_write.set_bit(i);
+    for record in reader {
+        let record = record?;
+
+        if record.len_sectors > 0 {
+            update_min_max(&mut min_index, &mut max_index, record.offset_sectors, record.end_sectors() - 1);
+        }
+
+        match record.op {
+            Op::Read => {
+                stats.read_count += 1;
+                stats.read_volume += record.len_sectors;
+
+                // 读请求是否访问了之前没有写过的扇区
+                let unwritten = record.len_sectors - written.covered(record.offset_sectors, record.len_sectors);
+                if unwritten == 0 {
+                    stats.read_after_write += 1;
+                } else {
+                    stats.read_unwritten += 1;
+                    stats.read_unwritten_sectors += unwritten;
                 }
+
+                read.insert(record.offset_sectors, record.len_sectors);
+            },
+            Op::Write => {
+                stats.write_count += 1;
+                stats.write_volume += record.len_sectors;
+
+                // 只有之前没有写过的扇区才计入落盘量
+                written.insert(record.offset_sectors, record.len_sectors);
             }
-            footprint += real;
         }
+        touched.insert(record.offset_sectors, record.len_sectors);
+    }
 
-        volume += len;
-
+    stats.read_footprint = read.sectors();
+    stats.write_footprint = written.sectors();
+    stats.union_footprint = touched.sectors();
+    if min_index <= max_index {
+        stats.min_lba = min_index;
+        stats.max_lba = max_index;
     }
-    let (footprint, volume) = convert_to_str(footprint, volume);
-    Ok((footprint, volume))
+
+    Ok(stats)
 }
 
-fn update_min_max(min_index: &mut u64, max_index: &mut u64, offset: &str) {
-    let offset = offset.parse::<u64>().unwrap();
-    if offset < *min_index {
-        *min_index = offset;
+fn update_min_max(min_index: &mut u64, max_index: &mut u64, first: u64, last: u64) {
+    if first < *min_index {
+        *min_index = first;
     }
 
-    if offset > *max_index {
-        *max_index = offset;
+    if last > *max_index {
+        *max_index = last;
     }
 }
 
-/// 判断容量是否超限，如果超限则扩容
-// fn overflow<F>(mut bitmap: F, max_index: &mut u64, length: &str) -> bool
-// where
-//     F: BitOperation,
-// {
-//     let length = length.parse::<u64>().unwrap();
-//     let count = (*max_index + length) / 64 + 1;
-//     if count >= bitmap.get_bitmap().capacity() as u64 {
-//         bitmap.get_bitmap().resize(count as usize, 0);
-//     }
-//     true
-// }
-
-/// 将容量转化为以 KB、MB、GB、TB 易读的形式
-fn convert_to_str(footprint: u64, volume: u64) -> (String, String) {
-    let suffix = vec!["KB", "MB", "GB", "TB", "PB"];
+/// 将以扇区为单位的容量转化为以 KB、MB、GB、TB 易读的形式，保留两位小数
+pub fn human_size(sectors: u64) -> String {
+    let suffix = ["KB", "MB", "GB", "TB", "PB", "EB"];
 
     // 单位是块(512B)
-    let mut footprint = footprint as f64 / 2 as f64;
-    let mut volume = volume as f64 / 2 as f64;
-
-    let footprint_str;
-    let volume_str;
-
+    let mut size = sectors as f64 / 2_f64;
     let mut cnt = 0;
 
-    loop {
-        if footprint < 512 as f64 {
-            // 保留两位小数
-            footprint_str = format!("{:.2}{}", footprint, suffix[cnt]);
-            break;
-        }
-        footprint /= 1024 as f64;
-        cnt += 1;
-    }
-
-    cnt = 0;
-
-    loop {
-        if volume < 512 as f64 {
-            // 保留两位小数
-            volume_str = format!("{:.2}{}", volume, suffix[cnt]);
-            break;
-        }
-        volume /= 1024 as f64;
+    while size >= 512_f64 && cnt < suffix.len() - 1 {
+        size /= 1024_f64;
         cnt += 1;
     }
 
-    (footprint_str, volume_str)
+    format!("{:.2}{}", size, suffix[cnt])
 }
 
 #[cfg(test)]
@@ -201,14 +384,55 @@ mod tests {
 
     #[test]
     fn test() {
-        let res = trace_foot_size("ts_0_validate.trace").unwrap();
+        let res = trace_foot_size(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test.trace")).unwrap();
         println!("{:?}", res);
+        assert_eq!((human_size(res.write_footprint), human_size(res.volume())), ("92.00KB".to_string(), "104.00KB".to_string()));
+        assert_eq!((res.read_count, res.write_count), (1, 14));
+        assert_eq!((res.read_volume, res.write_volume), (8, 200));
+        assert_eq!((res.read_footprint, res.union_footprint), (8, 192));
+        assert_eq!((res.min_lba, res.max_lba), (732504, 9953807));
+        assert_eq!((res.read_after_write, res.read_unwritten, res.read_unwritten_sectors), (0, 1, 8));
+
+        let report = FootSizeReport::new("test.trace", res.clone()).with_sample(0.5, SampleMode::Spatial);
+        let estimated = report.estimated.unwrap();
+        assert_eq!((estimated.write_footprint, estimated.write_count), (Some(368), 28));
+
+        // 按请求抽样时落盘量无法估计
+        let estimated = res.estimate(0.5, SampleMode::Uniform);
+        assert_eq!((estimated.write_footprint, estimated.read_after_write, estimated.write_volume), (None, None, 400));
+    }
+
+    #[test]
+    fn test_extent_map() {
+        let mut map = ExtentMap::new();
+        assert_eq!(map.insert(10, 10), 10);
+        assert_eq!(map.insert(30, 10), 10);
+        // 与两个区间都重叠，只有 [20, 30) 是新的
+        assert_eq!(map.insert(15, 20), 10);
+        assert_eq!(map.extent_count(), 1);
+        assert_eq!(map.sectors(), 30);
+        // 相邻的区间会被合并
+        assert_eq!(map.insert(40, 5), 5);
+        assert_eq!(map.extent_count(), 1);
+        assert_eq!(map.insert(12, 3), 0);
+
+        assert_eq!(map.covered(0, 100), 35);
+        assert_eq!(map.covered(5, 10), 5);
+        assert_eq!(map.covered(44, 10), 1);
+
+        // 超过 2^38 扇区的地址
+        assert_eq!(map.insert(u64::MAX - 8, 8), 8);
+        assert_eq!(map.insert(u64::MAX - 16, 100), 8);
+        assert_eq!(map.sectors(), 51);
     }
 
     #[test]
-    fn test_convert_to_str() {
+    fn test_human_size() {
         let footprint = 1424000;
         let volume = 208777;
-        println!("{:?}", convert_to_str(footprint, volume));
+        println!("{:?}", (human_size(footprint), human_size(volume)));
+        assert_eq!(human_size(footprint), "0.68GB");
+        assert_eq!(human_size(volume), "101.94MB");
+        assert_eq!(human_size(u64::MAX), "8192.00EB");
     }
 }
@@ -0,0 +1,300 @@
+//! transform 子命令：改写 HMSim trace 中请求的地址
+//!
+//! 把 MSR 等 trace 搬到容量不同的模拟磁带或 SMR 盘上时，需要改写请求的地址。
+//! 每个请求依次经过平移(shift)、缩放(scale-lba)、对齐(align)、按容量回绕(modulo-capacity)
+//! 和拆分(split-max)，未指定的步骤跳过。
+//!
+//! 一个请求被拆成多个请求时，这些请求连续到达(时间间隔为 0)，最后一个请求保留原来的时间间隔，
+//! 因此原 trace 中每个请求的到达时间都保持不变。
+
+use std::io::{BufRead, Write};
+
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    error::HMSimError,
+    output::Report,
+    trace::{open_output, TraceReader, TraceRecord, TraceWriter},
+    utils::{hmsim_block_to_string, string_to_hmsim_block},
+};
+
+/// transform 的命令行参数，大小均需要加上单位(忽略大小写)，不加单位默认为 B
+#[derive(Debug, Clone, clap::Args)]
+pub struct TransformArgs {
+    /// 输入的 trace 文件名
+    #[arg(short, long)]
+    pub file: String,
+
+    /// 输出的 trace 文件名，`-` 表示输出到 stdout(此时统计结果输出到 stderr)
+    #[arg(short, long, default_value = "transformed.trace")]
+    pub output: String,
+
+    /// 所有请求的地址加上该值，可以为负数，例如 --shift=-10G
+    #[arg(long, allow_hyphen_values = true)]
+    pub shift: Option<String>,
+
+    /// 所有请求的地址乘以该系数(向下取整)，请求大小不变，例如 0.5
+    #[arg(long = "scale-lba", value_name = "factor")]
+    pub scale_lba: Option<f64>,
+
+    /// 设备容量，地址对容量取模，跨过容量末尾的请求拆成两个，第二个从地址 0 开始
+    #[arg(long = "modulo-capacity", value_name = "capacity")]
+    pub modulo_capacity: Option<String>,
+
+    /// 对齐边界，请求的起始地址向下对齐、结束地址向上对齐
+    #[arg(long)]
+    pub align: Option<String>,
+
+    /// 单个请求的最大大小，更大的请求拆成若干个连续的请求
+    #[arg(long = "split-max", value_name = "size")]
+    pub split_max: Option<String>,
+}
+
+impl TransformArgs {
+    /// 解析所有参数，大小转化为扇区
+    pub fn resolve(&self) -> Result<Transform, HMSimError> {
+        let sectors = |size: &str| string_to_hmsim_block(size).map(|block| block.block);
+        let transform = Transform {
+            file: self.file.clone(),
+            output: self.output.clone(),
+            force: false,
+            shift: match &self.shift {
+                Some(shift) => match shift.trim().strip_prefix('-') {
+                    Some(negative) => -(sectors(negative)? as i128),
+                    None => sectors(shift)? as i128,
+                },
+                None => 0,
+            },
+            scale: self.scale_lba,
+            capacity: self.modulo_capacity.as_deref().map(sectors).transpose()?,
+            align: self.align.as_deref().map(sectors).transpose()?,
+            split_max: self.split_max.as_deref().map(sectors).transpose()?,
+        };
+        transform.validate()?;
+        Ok(transform)
+    }
+}
+
+/// 地址变换，地址和大小的单位为扇区，为 None 的步骤跳过
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transform {
+    /// 输入和输出的 trace 文件名，输出文件已经存在时是否覆盖
+    pub file: String,
+    pub output: String,
+    pub force: bool,
+    pub shift: i128,
+    pub scale: Option<f64>,
+    pub capacity: Option<u64>,
+    pub align: Option<u64>,
+    pub split_max: Option<u64>,
+}
+
+impl Transform {
+    /// 检查参数，对齐之后的请求在回绕和拆分之后仍然是对齐的
+    pub fn validate(&self) -> Result<(), HMSimError> {
+        if let Some(scale) = self.scale {
+            if !(scale.is_finite() && scale > 0.0) {
+                return Err(HMSimError::CommandError(format!("scale-lba 应为正数，实际为 {}", scale)));
+            }
+        }
+        for (name, value) in [("modulo-capacity", self.capacity), ("align", self.align), ("split-max", self.split_max)] {
+            if value == Some(0) {
+                return Err(HMSimError::CommandError(format!("{} 不能小于一个扇区(512B)", name)));
+            }
+        }
+        if let Some(align) = self.align {
+            for (name, value) in [("modulo-capacity", self.capacity), ("split-max", self.split_max)] {
+                if let Some(value) = value.filter(|value| !value.is_multiple_of(align)) {
+                    return Err(HMSimError::CommandError(format!(
+                        "{}({}) 应为 align({}) 的整数倍",
+                        name,
+                        hmsim_block_to_string(value),
+                        hmsim_block_to_string(align)
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 变换一个请求，结果追加到 out 中，`line` 只用于报告错误
+    fn apply(&self, record: &TraceRecord, line: u64, out: &mut Vec<TraceRecord>) -> Result<(), HMSimError> {
+        let mut offset = record.offset_sectors as i128 + self.shift;
+        if let Some(scale) = self.scale {
+            offset = (offset as f64 * scale).floor() as i128;
+        }
+        if let Some(capacity) = self.capacity {
+            offset = offset.rem_euclid(capacity as i128);
+        }
+        let mut offset = u64::try_from(offset).map_err(|_| {
+            HMSimError::CommandError(format!(
+                "第 {} 行的请求变换后的地址 {} 超出范围，可以指定 modulo-capacity 回绕",
+                line, offset
+            ))
+        })?;
+
+        let mut len = record.len_sectors;
+        if let Some(align) = self.align {
+            let end = offset.saturating_add(len).div_ceil(align) * align;
+            offset = offset / align * align;
+            len = end - offset;
+        }
+
+        // 按容量回绕，跨过容量末尾的部分从地址 0 开始
+        let mut pieces = [(offset, len), (0, 0)];
+        if let Some(capacity) = self.capacity {
+            if len > capacity {
+                return Err(HMSimError::CommandError(format!(
+                    "第 {} 行的请求大小({} 扇区)大于 modulo-capacity({} 扇区)",
+                    line, len, capacity
+                )));
+            }
+            if offset + len > capacity {
+                pieces = [(offset, capacity - offset), (0, offset + len - capacity)];
+            }
+        }
+
+        let first = out.len();
+        for (offset, len) in pieces.into_iter().filter(|&(_, len)| len > 0) {
+            let max = self.split_max.unwrap_or(u64::MAX);
+            let mut done = 0;
+            loop {
+                let piece = (len - done).min(max);
+                out.push(TraceRecord {
+                    offset_sectors: offset + done,
+                    len_sectors: piece,
+                    next_inter: 0.0,
+                    ..record.clone()
+                });
+                done += piece;
+                if done >= len {
+                    break;
+                }
+            }
+        }
+        // 大小为 0 的请求原样保留地址
+        if out.len() == first {
+            out.push(TraceRecord { offset_sectors: offset, len_sectors: 0, ..record.clone() });
+        }
+        out.last_mut().expect("至少有一个请求").next_inter = record.next_inter;
+        Ok(())
+    }
+}
+
+/// transform 子命令输出的统计信息，地址单位为扇区(512B)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TransformStats {
+    pub file: String,
+    pub output: String,
+    /// 输入和输出的请求数量
+    pub input_requests: u64,
+    pub output_requests: u64,
+    /// 被拆成多个请求的输入请求数量
+    pub split: u64,
+    /// 变换后的最小和最大扇区地址，没有请求时为空
+    pub min_lba: Option<u64>,
+    pub max_lba: Option<u64>,
+}
+
+impl Report for TransformStats {
+    fn to_text(&self) -> String {
+        format!(
+            "file: {}  output: {}\n\
+            input_requests: {:<10}  output_requests: {:<10}  split: {}\n\
+            min_lba: {}  max_lba: {}",
+            self.file,
+            self.output,
+            self.input_requests,
+            self.output_requests,
+            self.split,
+            self.min_lba.map_or(String::from("-"), |lba| lba.to_string()),
+            self.max_lba.map_or(String::from("-"), |lba| lba.to_string()),
+        )
+    }
+}
+
+/// 对外暴露的函数，变换 file 中的请求写入 output，返回统计信息
+pub fn transform(transform: &Transform) -> Result<TransformStats, HMSimError> {
+    transform.validate()?;
+    if transform.file == transform.output {
+        return Err(HMSimError::CommandError(format!("输出文件 {} 不能同时是输入文件", transform.output)));
+    }
+    let mut reader = TraceReader::open(&transform.file)?;
+    let mut writer = open_output(&transform.output, transform.force)?;
+    let mut stats = transform_records(&mut reader, &mut writer, transform).map_err(|e| match e {
+        HMSimError::CommandError(msg) => HMSimError::CommandError(format!("{} {}", transform.file, msg)),
+        e => e,
+    })?;
+    writer.flush().map_err(|e| e.with_path(&transform.output))?;
+
+    info!("transform running done.");
+    stats.file = transform.file.clone();
+    stats.output = transform.output.clone();
+    Ok(stats)
+}
+
+/// 逐个变换请求并写出
+fn transform_records<R: BufRead, W: Write>(
+    reader: &mut TraceReader<R>,
+    writer: &mut TraceWriter<W>,
+    transform: &Transform,
+) -> Result<TransformStats, HMSimError> {
+    let mut stats = TransformStats::default();
+    let mut out = vec![];
+    while let Some(record) = reader.next().transpose()? {
+        out.clear();
+        transform.apply(&record, reader.line_no(), &mut out)?;
+
+        stats.input_requests += 1;
+        stats.output_requests += out.len() as u64;
+        if out.len() > 1 {
+            stats.split += 1;
+        }
+        for record in &out {
+            stats.min_lba = Some(stats.min_lba.map_or(record.offset_sectors, |lba| lba.min(record.offset_sectors)));
+            let last = record.end_sectors().saturating_sub(1).max(record.offset_sectors);
+            stats.max_lba = Some(stats.max_lba.map_or(last, |lba| lba.max(last)));
+            writer.write_record(record)?;
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trace::Op;
+
+    use super::*;
+
+    fn apply(transform: &Transform, offset: u64, len: u64) -> Vec<(u64, u64, f64)> {
+        let mut record = TraceRecord::new(Op::Write, offset, len);
+        record.next_inter = 3.0;
+        let mut out = vec![];
+        transform.apply(&record, 1, &mut out).unwrap();
+        out.iter().map(|r| (r.offset_sectors, r.len_sectors, r.next_inter)).collect()
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(apply(&Transform::default(), 100, 8), [(100, 8, 3.0)]);
+
+        let shift = Transform { shift: -100, scale: Some(0.5), ..Transform::default() };
+        assert_eq!(apply(&shift, 300, 8), [(100, 8, 3.0)]);
+        let mut out = vec![];
+        assert!(shift.apply(&TraceRecord::new(Op::Read, 10, 8), 7, &mut out).is_err());
+
+        // 对齐后回绕，跨过容量末尾的请求拆成两个
+        let wrap = Transform { capacity: Some(64), align: Some(8), ..Transform::default() };
+        assert_eq!(apply(&wrap, 70, 4), [(0, 16, 3.0)]);
+        assert_eq!(apply(&wrap, 60, 9), [(56, 8, 0.0), (0, 8, 3.0)]);
+
+        // 拆分后只有最后一个请求保留时间间隔
+        let split = Transform { split_max: Some(16), ..Transform::default() };
+        assert_eq!(apply(&split, 0, 40), [(0, 16, 0.0), (16, 16, 0.0), (32, 8, 3.0)]);
+        assert_eq!(apply(&split, 5, 0), [(5, 0, 3.0)]);
+
+        let invalid = Transform { align: Some(8), split_max: Some(12), ..Transform::default() };
+        assert!(invalid.validate().is_err());
+    }
+}
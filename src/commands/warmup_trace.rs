@@ -0,0 +1,380 @@
+//! warmup-trace 子命令：生成用于测试缓存的大容量 trace
+//!
+//! trace 由两部分组成：第一部分是预热请求，写入 warmup_size 的数据用来填充缓存；
+//! 第二部分是真正的随机读写请求，用来计算平均时延和命中率。
+//!
+//! 第二部分只与随机请求的参数有关，生成后保存为 `rand-<size>.trace`，并在旁边的
+//! `rand-<size>.trace.meta.json` 中记录生成参数，之后参数相同时直接复用该文件。
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::generate_trace::{generate_trace, DiskTrace, DiskTraceStats, Pattern},
+    error::HMSimError,
+    output::Report,
+    utils::{hmsim_block_to_string, rate_to_num, seeded_rng, size_range_to_start_end, string_to_hmsim_block},
+};
+
+/// warmup-trace 的命令行参数，大小均需要加上单位(忽略大小写)，不加单位默认为 B
+#[derive(Debug, Clone, clap::Args)]
+pub struct WarmupTraceArgs {
+    /// 预热请求的数据量(必须指定)，应与缓存大小相同，例如 100G
+    #[arg(name = "warmup_size", short, long)]
+    pub warmup_size: String,
+
+    /// 预热请求的访问模式，seq 从地址 0 开始顺序写，rand 在设备容量内随机写 [默认值: seq]
+    #[arg(name = "warmup_pattern", long, value_enum, ignore_case = true)]
+    pub warmup_pattern: Option<Pattern>,
+
+    /// 每个预热请求的大小 [默认值: 1M]
+    #[arg(name = "warmup_len", long)]
+    pub warmup_len: Option<String>,
+
+    /// 随机请求的数据量(必须指定)，例如 1T
+    #[arg(short, long)]
+    pub size: String,
+
+    /// 设备容量，所有请求都落在 [0, capacity) 内 [默认值: 100G]
+    #[arg(long)]
+    pub capacity: Option<String>,
+
+    /// 随机请求大小的范围 [默认值: 4K-1M]
+    #[arg(name = "length_range", long, value_name = "min-max")]
+    pub length_range: Option<String>,
+
+    /// 请求偏移和长度的对齐单位 [默认值: 4K]
+    #[arg(long)]
+    pub align: Option<String>,
+
+    /// 随机请求的读写比例(读:写) [默认值: 1:1]
+    #[arg(long, value_name = "read:write")]
+    pub rwrate: Option<String>,
+
+    /// 随机数种子，指定相同的种子和参数可以生成完全相同的 trace(不指定则随机生成，并在结果中输出)
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// 输出的 trace 文件名，`-` 表示输出到 stdout(此时统计结果输出到 stderr) [默认值: <size>-<warmup_size>.trace，例如 1TB-100GB.trace]
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// 随机请求部分的缓存文件名 [默认值: rand-<size>.trace，例如 rand-1TB.trace]
+    #[arg(name = "rand_output", long)]
+    pub rand_output: Option<String>,
+}
+
+impl WarmupTraceArgs {
+    /// 用出现的参数覆盖默认参数，大小全部转化为扇区
+    pub fn resolve(&self) -> Result<WarmupTrace, HMSimError> {
+        let mut trace = WarmupTrace::new();
+
+        trace.warmup_size = string_to_hmsim_block(&self.warmup_size)?.block;
+        if let Some(warmup_pattern) = self.warmup_pattern {
+            trace.warmup_pattern = warmup_pattern;
+        }
+        if let Some(warmup_len) = &self.warmup_len {
+            trace.warmup_len = string_to_hmsim_block(warmup_len)?.block;
+        }
+
+        trace.rand.size = string_to_hmsim_block(&self.size)?.block;
+        if let Some(capacity) = &self.capacity {
+            trace.rand.capacity = string_to_hmsim_block(capacity)?.block;
+        }
+        if let Some(length_range) = &self.length_range {
+            let range = size_range_to_start_end(length_range)?;
+            trace.rand.length_start = range.size_begin.block;
+            trace.rand.length_end = range.size_end.block;
+        }
+        if let Some(align) = &self.align {
+            trace.rand.align = string_to_hmsim_block(align)?.block;
+        }
+        if let Some(rwrate) = &self.rwrate {
+            (trace.rand.read_rate, trace.rand.write_rate) = rate_to_num(rwrate)?;
+        }
+        trace.seed = self.seed;
+
+        // 文件名中的大小与旧版本相同，例如 1TB-100GB.trace 和 rand-1TB.trace
+        let size = format!("{}B", hmsim_block_to_string(trace.rand.size));
+        trace.output = match &self.output {
+            Some(output) => output.clone(),
+            None => format!("{}-{}B.trace", size, hmsim_block_to_string(trace.warmup_size)),
+        };
+        trace.rand_output = match &self.rand_output {
+            Some(rand_output) => rand_output.clone(),
+            None => format!("rand-{}.trace", size),
+        };
+        Ok(trace)
+    }
+}
+
+/// 通过子命令参数转化成的 WarmupTrace 结构体，大小单位全部转化为扇区(512B)
+#[derive(Debug, Clone)]
+pub struct WarmupTrace {
+    /// 预热请求的数据量、访问模式和每个请求的大小
+    pub warmup_size: u64,
+    pub warmup_pattern: Pattern,
+    pub warmup_len: u64,
+
+    /// 随机请求部分的参数，相同时复用缓存的随机请求
+    pub rand: RandParams,
+
+    /// 随机数种子，为 None 时随机生成一个，此时可以复用以任意种子生成的随机请求
+    pub seed: Option<u64>,
+
+    /// 输出的 trace 文件，`-` 表示 stdout
+    pub output: String,
+    /// 随机请求部分的缓存文件
+    pub rand_output: String,
+    /// 输出文件已经存在时是否覆盖
+    pub force: bool,
+}
+
+/// 随机请求部分的参数，记录在缓存文件的元数据中
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RandParams {
+    pub size: u64,
+    pub capacity: u64,
+    pub length_start: u64,
+    pub length_end: u64,
+    pub align: u64,
+    pub read_rate: f32,
+    pub write_rate: f32,
+}
+
+/// 缓存的随机请求的元数据，保存为 `<rand_output>.meta.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RandMeta {
+    pub params: RandParams,
+    pub stats: DiskTraceStats,
+}
+
+impl Default for WarmupTrace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WarmupTrace {
+    pub fn new() -> WarmupTrace {
+        WarmupTrace {
+            warmup_size: 0,
+            warmup_pattern: Pattern::Seq,
+            // 1MB
+            warmup_len: 2048,
+            rand: RandParams {
+                size: 0,
+                // 100GB
+                capacity: 100 * 2 * 1024 * 1024,
+                // 4KB-1MB
+                length_start: 8,
+                length_end: 2048,
+                align: 8,
+                read_rate: 1.0,
+                write_rate: 1.0,
+            },
+            seed: None,
+            output: String::from("warmup.trace"),
+            rand_output: String::from("rand.trace"),
+            force: false,
+        }
+    }
+
+    /// 预热请求部分，只有写请求，数据量用完即停止
+    pub fn warmup_trace(&self, seed: u64) -> DiskTrace {
+        let mut trace = DiskTrace::new();
+        trace.range_end = self.rand.capacity;
+        trace.addr_start = Some(0);
+        trace.total_size = self.warmup_size;
+        trace.length_start = self.warmup_len;
+        trace.length_end = self.warmup_len;
+        trace.pattern = self.warmup_pattern;
+        trace.align = self.rand.align;
+        trace.read_rate = 0.0;
+        trace.write_rate = 1.0;
+        trace.seed = Some(seed);
+        trace.output = self.output.clone();
+        trace.force = self.force;
+        trace
+    }
+
+    /// 随机请求部分，生成后覆盖缓存文件
+    pub fn rand_trace(&self, seed: u64) -> DiskTrace {
+        let mut trace = DiskTrace::new();
+        trace.range_end = self.rand.capacity;
+        trace.total_size = self.rand.size;
+        trace.length_start = self.rand.length_start;
+        trace.length_end = self.rand.length_end;
+        trace.pattern = Pattern::Rand;
+        trace.align = self.rand.align;
+        trace.read_rate = self.rand.read_rate;
+        trace.write_rate = self.rand.write_rate;
+        trace.seed = Some(seed);
+        trace.output = self.rand_output.clone();
+        trace.force = true;
+        trace
+    }
+
+    /// 生成前检查所有参数，随机请求和预热请求的参数由 generate-trace 再检查一次
+    pub fn validate(&self) -> Result<(), HMSimError> {
+        if self.warmup_size == 0 {
+            return Err(HMSimError::CommandError(String::from("warmup_size 不能为 0")));
+        }
+        if self.rand.size == 0 {
+            return Err(HMSimError::CommandError(String::from("size 不能为 0")));
+        }
+        if self.warmup_pattern == Pattern::Stride {
+            return Err(HMSimError::CommandError(String::from("warmup_pattern 只支持 seq 和 rand")));
+        }
+        // 顺序预热超出设备容量时会回到地址 0，重复写的数据不能再填充缓存
+        if self.warmup_pattern == Pattern::Seq && self.warmup_size > self.rand.capacity {
+            return Err(HMSimError::CommandError(format!(
+                "warmup_size({}) 不能大于 capacity({})",
+                hmsim_block_to_string(self.warmup_size),
+                hmsim_block_to_string(self.rand.capacity)
+            )));
+        }
+        if self.output != "-" && self.output == self.rand_output {
+            return Err(HMSimError::CommandError(format!("output 和 rand_output 不能是同一个文件: {}", self.output)));
+        }
+        self.warmup_trace(0).validate()?;
+        self.rand_trace(0).validate()
+    }
+
+    /// 缓存文件的元数据文件名
+    pub fn meta_path(&self) -> String {
+        format!("{}.meta.json", self.rand_output)
+    }
+
+    /// 读取缓存的随机请求的元数据，参数(以及指定的种子)与当前参数相同时返回，否则返回 None
+    fn cached_rand(&self) -> Option<RandMeta> {
+        fs::metadata(&self.rand_output).ok()?;
+        let content = fs::read_to_string(self.meta_path()).ok()?;
+        let meta: RandMeta = serde_json::from_str(&content).ok()?;
+        if meta.params == self.rand && self.seed.is_none_or(|seed| seed == meta.stats.seed) {
+            Some(meta)
+        } else {
+            None
+        }
+    }
+}
+
+/// warmup-trace 子命令输出的统计信息，数据量单位为扇区(512B)
+#[derive(Debug, Clone, Serialize)]
+pub struct WarmupTraceStats {
+    pub output: String,
+    pub rand_output: String,
+    /// 随机请求部分是否复用了缓存文件
+    pub cached: bool,
+    pub warmup: DiskTraceStats,
+    pub rand: DiskTraceStats,
+}
+
+impl Report for WarmupTraceStats {
+    fn to_text(&self) -> String {
+        format!(
+            "output: {}  rand_output: {}{}\n\
+            [warmup]\n{}\n\
+            [rand]\n{}",
+            self.output,
+            self.rand_output,
+            if self.cached { " (cached)" } else { "" },
+            self.warmup.to_text(),
+            self.rand.to_text(),
+        )
+    }
+}
+
+/// 对外暴露的函数
+///
+/// 先准备随机请求部分(参数相同时复用缓存文件)，再生成预热请求，最后把随机请求追加到预热请求之后
+pub fn warmup_trace(trace: WarmupTrace) -> Result<WarmupTraceStats, HMSimError> {
+    trace.validate()?;
+    // 在生成随机请求之前检查，避免生成之后才发现无法写入输出文件
+    if trace.output != "-" && !trace.force && fs::metadata(&trace.output).is_ok() {
+        return Err(HMSimError::CommandError(format!("输出文件 {} 已经存在，如需覆盖请指定 --force", trace.output)));
+    }
+    let (_, seed) = seeded_rng(trace.seed);
+
+    let (rand, cached) = match trace.cached_rand() {
+        Some(meta) => {
+            info!("reuse {} generated with seed {}", trace.rand_output, meta.stats.seed);
+            (meta.stats, true)
+        },
+        None => {
+            info!("generate {} with seed {}", trace.rand_output, seed);
+            // 先删除旧的元数据，生成中断时不会误用不完整的缓存文件
+            match fs::remove_file(trace.meta_path()) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(HMSimError::file(trace.meta_path(), e)),
+                _ => {},
+            }
+            let stats = generate_trace(trace.rand_trace(seed))?;
+            let meta = RandMeta { params: trace.rand.clone(), stats: stats.clone() };
+            let json = serde_json::to_string_pretty(&meta).map_err(io::Error::from)?;
+            fs::write(trace.meta_path(), json).map_err(|e| HMSimError::file(trace.meta_path(), e))?;
+            (stats, false)
+        },
+    };
+
+    let warmup = generate_trace(trace.warmup_trace(rand.seed))?;
+    append_trace(&trace.rand_output, &trace.output)?;
+
+    info!("warmup_trace running done.");
+    Ok(WarmupTraceStats {
+        output: trace.output,
+        rand_output: trace.rand_output,
+        cached,
+        warmup,
+        rand,
+    })
+}
+
+/// 将随机请求的 trace 追加到输出文件之后，`-` 表示 stdout
+fn append_trace(rand_output: &str, output: &str) -> Result<(), HMSimError> {
+    let mut rand_file = File::open(rand_output).map_err(|e| HMSimError::file(rand_output, e))?;
+    if output == "-" {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        io::copy(&mut rand_file, &mut out).map_err(|e| HMSimError::file(rand_output, e))?;
+        out.flush()?;
+    } else {
+        let mut out = OpenOptions::new().append(true).open(output).map_err(|e| HMSimError::file(output, e))?;
+        io::copy(&mut rand_file, &mut out).map_err(|e| HMSimError::file(output, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_names() {
+        let args = WarmupTraceArgs {
+            warmup_size: String::from("100G"),
+            warmup_pattern: None,
+            warmup_len: None,
+            size: String::from("1T"),
+            capacity: Some(String::from("2T")),
+            length_range: None,
+            align: None,
+            rwrate: None,
+            seed: None,
+            output: None,
+            rand_output: None,
+        };
+        let trace = args.resolve().unwrap();
+        assert_eq!((trace.output.as_str(), trace.rand_output.as_str()), ("1TB-100GB.trace", "rand-1TB.trace"));
+        assert_eq!((trace.warmup_size, trace.warmup_len), (100 * 2 * 1024 * 1024, 2048));
+        assert!(trace.validate().is_ok());
+
+        let mut too_large = trace.clone();
+        too_large.warmup_size = too_large.rand.capacity + 1;
+        assert!(matches!(too_large.validate(), Err(HMSimError::CommandError(msg)) if msg.contains("capacity")));
+    }
+}
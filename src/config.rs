@@ -0,0 +1,493 @@
+//! generate-tape-trace 的参数及场景配置文件
+//!
+//! `TapeTraceConfig` 既是 generate-tape-trace 的命令行参数，也是 `--config` 指定的 TOML 配置文件，
+//! 键名与命令行参数相同，取值的写法也与命令行相同。未出现的参数保持默认值，
+//! 命令行中出现的参数覆盖配置文件中的同名参数，包括各个阶段中的参数。
+//!
+//! 每个 `[[phase]]` 是多阶段负载中的一个阶段，只需写出与顶层参数不同的部分，例如：
+//!
+//! ```toml
+//! blk_size = "256K"
+//! woff = "230G"
+//! rsize = "1-8"
+//! seed = 2024
+//!
+//! [[phase]]
+//! name = "fill"
+//! size = "200G"
+//! rw = "w"
+//! wsize = "64-64"
+//!
+//! [[phase]]
+//! name = "mixed"
+//! duration = 3600
+//! iops = 500
+//! rwrate = "7:3"
+//! ro = "rand"
+//!
+//! [[phase]]
+//! name = "burst"
+//! count = 10000
+//! rw = "r"
+//! ```
+
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::generate_tape_trace::{AccessOrder, BatchMode, OpMix, RateBasis, TapeTrace},
+    error::HMSimError,
+    utils::{
+        apply_rwrate, hmsim_block_to_string, range_to_num, req_dist_analyze, rwrate_analyze,
+        seek_dist_analyze, string_to_hmsim_block, time_dist_analyze,
+    },
+    Dist,
+};
+
+/// generate-tape-trace 的一组参数，未出现的参数保持不变
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, clap::Args)]
+#[serde(deny_unknown_fields)]
+pub struct TapeTraceConfig {
+    /// 阶段名，只用于多阶段负载
+    #[arg(skip)]
+    pub name: Option<String>,
+
+    /// 读写操作的总大小，与 count、duration 一起作为结束条件，可以同时指定，任意一个用完即停止(使用多阶段负载时作为各阶段的默认值)
+    #[arg(long, value_name = "size")]
+    pub size: Option<String>,
+
+    /// 生成的请求数量
+    #[arg(long)]
+    pub count: Option<u64>,
+
+    /// 模拟时长(单位: 秒)，即请求的时间间隔之和，需要指定 time_dist 或 iops
+    #[arg(long, value_name = "seconds")]
+    pub duration: Option<f64>,
+
+    /// 每秒请求数，请求的时间间隔服从均值为 1000 / iops 毫秒的指数分布，不能与 time_dist 同时指定
+    #[arg(long)]
+    pub iops: Option<f64>,
+
+    /// 读写比例(读:写)，例如 1:3，支持小数，某一项为 0 表示只生成另一种请求(未指定 rw 时由该比例推断 rw)，empirical:<trace 文件> 表示与该 trace 的读写请求数量之比相同
+    #[arg(long, value_name = "read:write")]
+    pub rwrate: Option<String>,
+
+    /// 读写比例的计算方式 [op: 按请求数量(默认), data: 按数据量]
+    #[arg(name = "rwrate_by", long, value_enum)]
+    pub rwrate_by: Option<RateBasis>,
+
+    /// 生成的请求粒度，即块大小(必须指定)
+    #[arg(name = "blk_size", long)]
+    pub blk_size: Option<String>,
+
+    /// 指定生成的请求类型
+    #[arg(long, value_enum, ignore_case = true)]
+    pub rw: Option<OpMix>,
+
+    /// 指定读操作的顺序性(该参数当 rw 包含 r 有效) [默认值: rand]
+    #[arg(long, value_name = "read_order", value_enum, ignore_case = true)]
+    pub ro: Option<AccessOrder>,
+
+    /// 指定写操作的顺序性(该参数当 rw 包含 w 有效)，seq 为追加写，其它为在 wregion 内覆盖写 [默认值: seq]
+    #[arg(long, value_name = "write_order", value_enum, ignore_case = true)]
+    pub wo: Option<AccessOrder>,
+
+    /// 若只有读操作，指定已经写的数据地址(如果 ro 不为 seq, 该值必须指定且不为 0；wo 不为 seq 时也是默认的覆盖写区域) [默认值: 0]
+    #[arg(long, value_name = "write_offset")]
+    pub woff: Option<String>,
+
+    /// 单个写请求大小范围，若没有写请求则设置为 0-0
+    #[arg(long, value_name = "wsize")]
+    pub wsize: Option<String>,
+
+    /// 单个读请求大小范围，若没有读请求则设置为 0-0
+    #[arg(long, value_name = "rsize")]
+    pub rsize: Option<String>,
+
+    /// 单个请求大小范围(该参数当 wsize 和 rsize 均为 None 时有效)
+    #[arg(long)]
+    pub rwsize: Option<String>,
+
+    /// 设置读写操作的 batch [默认值: none]
+    #[arg(long, value_enum, ignore_case = true)]
+    pub batch: Option<BatchMode>,
+
+    /// 每个 write batch 的大小范围(单位为 blk_size，该参数当 batch 包含 'w' 时有效)
+    #[arg(name = "batch_IOw_num", long)]
+    #[serde(rename = "batch_IOw_num")]
+    pub batch_iow_num: Option<String>,
+
+    /// 每个 read batch 的大小范围(单位为 blk_size，该参数当 batch 包含 'r' 时有效)
+    #[arg(name = "batch_IOr_num", long)]
+    #[serde(rename = "batch_IOr_num")]
+    pub batch_ior_num: Option<String>,
+
+    /// 生成的时间间隔满足的数学分布[支持的参数：exp:lambda，uni:min:max，poi:lambda，norm:mean:std_dev，lognorm:mu:sigma，pareto:scale:shape，weibull:scale:shape，gamma:shape:scale，zipf:n:s，const:value，empirical:<trace 文件>(使用该 trace 的经验分布)，none(不使用分布)]
+    #[arg(name = "time_dist", long)]
+    pub time_dist: Option<String>,
+
+    /// 生成的请求大小(单位为扇区)满足的数学分布，同时作用于读写请求[支持的参数与 time_dist 相同]
+    #[arg(name = "req_dist", long)]
+    pub req_dist: Option<String>,
+
+    /// 随机读请求的寻道距离(与上一个请求结束地址之差，单位为扇区)满足的分布，可以为负数，超出已写区域时回绕[支持的参数与 time_dist 相同]
+    #[arg(name = "seek_dist", long)]
+    pub seek_dist: Option<String>,
+
+    /// ro/wo=zipf 时 Zipf 分布的参数 theta，越大访问越集中，0 表示均匀访问 [默认值: 0.99]
+    #[arg(name = "zipf_theta", long)]
+    pub zipf_theta: Option<f64>,
+
+    /// ro/wo=hotcold 时热数据占地址空间的比例 [默认值: 0.2]
+    #[arg(name = "hot_frac", long)]
+    pub hot_frac: Option<f64>,
+
+    /// ro/wo=hotcold 时访问热数据的概率 [默认值: 0.8]
+    #[arg(name = "hot_prob", long)]
+    pub hot_prob: Option<f64>,
+
+    /// zipf 和 hotcold 划分地址空间的粒度 [默认与 blk_size 相同]
+    #[arg(long, value_name = "extent_size")]
+    pub extent: Option<String>,
+
+    /// wo 为 rand、zipf 或 hotcold 时覆盖写的区域大小，写请求的地址在 [0, wregion) 内 [默认与 woff 相同]
+    #[arg(long, value_name = "write_region")]
+    pub wregion: Option<String>,
+
+    /// 随机数种子，指定相同的种子和参数可以生成完全相同的 trace(不指定则随机生成，并在结果中输出)
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// 输出的 trace 文件名，`-` 表示输出到 stdout(此时统计结果输出到 stderr) [默认值: tape.trace]
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// 多阶段负载，只能出现在配置文件的顶层
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub phase: Vec<TapeTraceConfig>,
+}
+
+impl TapeTraceConfig {
+    /// 读取 TOML 格式的配置文件
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<TapeTraceConfig, HMSimError> {
+        let content = fs::read_to_string(&path).map_err(|e| HMSimError::file(&path, e))?;
+        toml::from_str(&content).map_err(|e| HMSimError::ConfigError {
+            path: path.as_ref().display().to_string(),
+            source: Box::new(e),
+        })
+    }
+
+    /// 以默认参数为基础，依次用配置文件(self)和命令行参数(overrides)覆盖，再生成每个阶段的完整参数，
+    /// 命令行参数同样覆盖每个阶段中的同名参数
+    pub fn resolve(&self, overrides: &TapeTraceConfig) -> Result<TapeTrace, HMSimError> {
+        let mut trace = overrides.apply(&self.apply(&TapeTrace::new())?)?;
+
+        for (i, config) in self.phase.iter().enumerate() {
+            if config.seed.is_some() || config.output.is_some() || !config.phase.is_empty() {
+                return Err(HMSimError::CommandError(format!("第 {} 个 [[phase]] 中不能指定 seed、output 或 phase", i + 1)));
+            }
+            let mut phase = overrides.apply(&config.apply_phase(&trace)?)?;
+            if phase.name.is_empty() {
+                phase.name = format!("phase{}", i);
+            }
+            trace.phases.push(phase);
+        }
+        Ok(trace)
+    }
+
+    /// 把配置作为一个阶段，以顶层参数 base 为基础生成该阶段的参数
+    ///
+    /// 与 `apply` 的区别是阶段中指定了任意一个结束条件时不再使用顶层的 size、count 和 duration
+    pub fn apply_phase(&self, base: &TapeTrace) -> Result<TapeTrace, HMSimError> {
+        let mut base = base.clone();
+        if self.size.is_some() || self.count.is_some() || self.duration.is_some() {
+            base.total_size = 0;
+            base.count = 0;
+            base.duration = 0.0;
+        }
+        self.apply(&base)
+    }
+
+    /// 用配置中出现的参数覆盖 base，不包括各个阶段
+    pub fn apply(&self, base: &TapeTrace) -> Result<TapeTrace, HMSimError> {
+        let mut trace = base.clone();
+        trace.phases.clear();
+
+        if let Some(name) = &self.name {
+            trace.name = name.clone();
+        }
+
+        // 结束条件各自覆盖，来自不同层的 size、count、duration 同时生效
+        if let Some(size) = &self.size {
+            trace.total_size = string_to_hmsim_block(size)?.block;
+        }
+        if let Some(count) = self.count {
+            trace.count = count;
+        }
+        if let Some(duration) = self.duration {
+            trace.duration = duration;
+        }
+
+        if let Some(blk_size) = &self.blk_size {
+            trace.block_size = string_to_hmsim_block(blk_size)?.block;
+        }
+
+        // 只指定 rw 时不再继承读写比例，只指定 rwrate 时由比例推断 rw
+        if let Some(rw) = self.rw {
+            trace.rw = Some(rw);
+            trace.read_rate = 0.0;
+            trace.write_rate = 0.0;
+        }
+        if let Some(rwrate) = &self.rwrate {
+            if self.rw.is_none() {
+                trace.rw = None;
+            }
+            apply_rwrate(&mut trace, rwrate_analyze(rwrate)?)?;
+        }
+        if let Some(rwrate_by) = self.rwrate_by {
+            trace.rwrate_by = rwrate_by;
+        }
+
+        if let Some(ro) = self.ro {
+            trace.read_order = ro;
+        }
+        if let Some(wo) = self.wo {
+            trace.write_order = wo;
+        }
+        if let Some(woff) = &self.woff {
+            trace.write_offset = string_to_hmsim_block(woff)?.block;
+        }
+
+        if let Some(wsize) = &self.wsize {
+            let (start, end) = range_to_num(wsize)?;
+            trace.write_size_start = start;
+            trace.write_size_end = end;
+            trace.write_size_range = end - start;
+        }
+        if let Some(rsize) = &self.rsize {
+            let (start, end) = range_to_num(rsize)?;
+            trace.read_size_start = start;
+            trace.read_size_end = end;
+            trace.read_size_range = end - start;
+        }
+        if let Some(rwsize) = &self.rwsize {
+            let (start, end) = range_to_num(rwsize)?;
+            trace.rwsize_start = start;
+            trace.rwsize_end = end;
+            trace.rwsize_range = end - start;
+        }
+
+        if let Some(batch) = self.batch {
+            trace.batch = batch;
+        }
+        if let Some(batch_iow_num) = &self.batch_iow_num {
+            let (begin, end) = range_to_num(batch_iow_num)?;
+            trace.batch_iow_num_begin = begin;
+            trace.batch_iow_num_end = end;
+            trace.batch_iow_num_range = end - begin;
+        }
+        if let Some(batch_ior_num) = &self.batch_ior_num {
+            let (begin, end) = range_to_num(batch_ior_num)?;
+            trace.batch_ior_num_begin = begin;
+            trace.batch_ior_num_end = end;
+            trace.batch_ior_num_range = end - begin;
+        }
+
+        match (self.iops, &self.time_dist) {
+            (Some(_), Some(_)) => {
+                return Err(HMSimError::CommandError(String::from("iops 和 time_dist 不能同时指定")));
+            },
+            (Some(iops), None) => {
+                if !(iops.is_finite() && iops > 0.0) {
+                    return Err(HMSimError::CommandError(format!("iops 应为正数，实际为 {}", iops)));
+                }
+                // 时间间隔的单位为毫秒
+                trace.time_interval_dist = Dist::Exponential(iops / 1000.0);
+            },
+            (None, Some(time_dist)) => trace.time_interval_dist = time_dist_analyze(time_dist)?,
+            (None, None) => {},
+        }
+        if let Some(req_dist) = &self.req_dist {
+            trace.req_length_dist = req_dist_analyze(req_dist)?;
+        }
+        if let Some(seek_dist) = &self.seek_dist {
+            trace.seek_dist = seek_dist_analyze(seek_dist)?;
+        }
+
+        if let Some(zipf_theta) = self.zipf_theta {
+            trace.zipf_theta = zipf_theta;
+        }
+        if let Some(hot_frac) = self.hot_frac {
+            trace.hot_fraction = hot_frac;
+        }
+        if let Some(hot_prob) = self.hot_prob {
+            trace.hot_probability = hot_prob;
+        }
+        if let Some(extent) = &self.extent {
+            trace.extent_size = string_to_hmsim_block(extent)?.block;
+        }
+        if let Some(wregion) = &self.wregion {
+            trace.write_region = string_to_hmsim_block(wregion)?.block;
+        }
+
+        if let Some(seed) = self.seed {
+            trace.seed = Some(seed);
+        }
+        if let Some(output) = &self.output {
+            trace.output = output.clone();
+        }
+
+        Ok(trace)
+    }
+}
+
+/// 完整的参数，每一项都写出来，可以由 `resolve` 还原为相同的 TapeTrace
+impl From<TapeTrace> for TapeTraceConfig {
+    fn from(trace: TapeTrace) -> TapeTraceConfig {
+        let range = |start: u64, end: u64| Some(format!("{}-{}", start, end));
+        // Dist::None 写作 none，其余分布与命令行的写法相同
+        let dist = |dist: &Dist| Some(dist.to_string());
+
+        TapeTraceConfig {
+            name: Some(trace.name.clone()).filter(|name| !name.is_empty()),
+            size: Some(hmsim_block_to_string(trace.total_size)),
+            count: Some(trace.count),
+            duration: Some(trace.duration),
+            iops: None,
+            rwrate: if trace.read_rate > 0.0 || trace.write_rate > 0.0 {
+                Some(format!("{}:{}", trace.read_rate, trace.write_rate))
+            } else {
+                None
+            },
+            rwrate_by: Some(trace.rwrate_by),
+            blk_size: Some(hmsim_block_to_string(trace.block_size)),
+            rw: trace.rw,
+            ro: Some(trace.read_order),
+            wo: Some(trace.write_order),
+            woff: Some(hmsim_block_to_string(trace.write_offset)),
+            wsize: range(trace.write_size_start, trace.write_size_end),
+            rsize: range(trace.read_size_start, trace.read_size_end),
+            rwsize: range(trace.rwsize_start, trace.rwsize_end),
+            batch: Some(trace.batch),
+            batch_iow_num: range(trace.batch_iow_num_begin, trace.batch_iow_num_end),
+            batch_ior_num: range(trace.batch_ior_num_begin, trace.batch_ior_num_end),
+            time_dist: dist(&trace.time_interval_dist),
+            req_dist: dist(&trace.req_length_dist),
+            seek_dist: dist(&trace.seek_dist),
+            zipf_theta: Some(trace.zipf_theta),
+            hot_frac: Some(trace.hot_fraction),
+            hot_prob: Some(trace.hot_probability),
+            extent: Some(hmsim_block_to_string(trace.extent_size)),
+            wregion: Some(hmsim_block_to_string(trace.write_region)),
+            seed: trace.seed,
+            output: Some(trace.output.clone()),
+            // 阶段中不能出现 seed 和 output
+            phase: trace
+                .phases
+                .into_iter()
+                .map(|phase| TapeTraceConfig { seed: None, output: None, ..phase.into() })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<TapeTraceConfig> for TapeTrace {
+    type Error = HMSimError;
+
+    fn try_from(config: TapeTraceConfig) -> Result<TapeTrace, HMSimError> {
+        config.resolve(&TapeTraceConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        let config: TapeTraceConfig = toml::from_str(
+            r#"
+            size = "2M"
+            rw = "rw"
+
+            [[phase]]
+            name = "fill"
+            size = "1M"
+            rw = "w"
+
+            [[phase]]
+            duration = 60
+            iops = 500
+            rwrate = "7:3"
+
+            [[phase]]
+            count = 100
+            rw = "r"
+            "#,
+        )
+        .unwrap();
+
+        let trace = config.resolve(&TapeTraceConfig::default()).unwrap();
+        let phases = &trace.phases;
+
+        assert_eq!((phases[0].name.as_str(), phases[0].total_size, phases[0].rw), ("fill", 2048, Some(OpMix::W)));
+        assert_eq!((phases[1].name.as_str(), phases[1].total_size, phases[1].duration), ("phase1", 0, 60.0));
+        assert_eq!((phases[1].rw, phases[1].read_rate, phases[1].write_rate), (Some(OpMix::Rw), 7.0, 3.0));
+        assert_eq!(phases[1].time_interval_dist, Dist::Exponential(0.5));
+        assert_eq!((phases[2].count, phases[2].rw, phases[2].read_rate), (100, Some(OpMix::R), 0.0));
+
+        assert!(toml::from_str::<TapeTraceConfig>("[[phase]]\nsizes = \"1M\"").is_err());
+        assert!(toml::from_str::<TapeTraceConfig>("batch = \"rwx\"").is_err());
+        let nested: TapeTraceConfig = toml::from_str("[[phase]]\nsize = \"1M\"\nseed = 1").unwrap();
+        assert!(nested.resolve(&TapeTraceConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_override_and_dump() {
+        let file: TapeTraceConfig = toml::from_str(
+            r#"
+            size = "1G"
+            blk_size = "256K"
+            rwrate = "1:3"
+            woff = "230G"
+            rsize = "1-8"
+            time_dist = "exp:0.03"
+            seed = 7
+
+            [[phase]]
+            count = 10
+            rw = "r"
+            wsize = "4-8"
+            "#,
+        )
+        .unwrap();
+
+        // 命令行参数覆盖配置文件中的参数
+        let cli = TapeTraceConfig { rw: Some(OpMix::R), seed: Some(8), ..Default::default() };
+        let trace = file.resolve(&cli).unwrap();
+        assert_eq!((trace.rw, trace.read_rate, trace.write_rate), (Some(OpMix::R), 0.0, 0.0));
+        assert_eq!((trace.seed, trace.block_size, trace.write_offset), (Some(8), 512, 230 * 2 * 1024 * 1024));
+        assert_eq!(trace.phases[0].seed, Some(8));
+
+        // 命令行参数也覆盖阶段中的同名参数
+        let cli = TapeTraceConfig { wsize: Some(String::from("16-16")), ..Default::default() };
+        let phase = &file.resolve(&cli).unwrap().phases[0];
+        assert_eq!((phase.write_size_start, phase.write_size_end, phase.rw), (16, 16, Some(OpMix::R)));
+
+        // 命令行中的 count 与文件中的 size 同时作为结束条件
+        let cli = TapeTraceConfig { count: Some(100), ..Default::default() };
+        let trace = file.resolve(&cli).unwrap();
+        assert_eq!((trace.total_size, trace.count), (2 * 1024 * 1024, 100));
+        assert_eq!((trace.phases[0].total_size, trace.phases[0].count), (0, 100));
+        assert!(toml::to_string(&trace).unwrap().starts_with("size = \"1G\"\ncount = 100\n"));
+
+        // 打印出的完整参数可以还原为相同的 TapeTrace
+        let dumped = toml::to_string(&trace).unwrap();
+        assert!(dumped.contains("size = \"1G\""), "{}", dumped);
+        assert!(dumped.contains("time_dist = \"exp:0.03\""), "{}", dumped);
+        let restored: TapeTrace = toml::from_str(&dumped).unwrap();
+        assert_eq!(toml::to_string(&restored).unwrap(), dumped);
+    }
+}
@@ -0,0 +1,462 @@
+//! 生成 trace 时使用的数学分布
+//!
+//! 同一个分布既可以用于请求大小(单位为扇区)，也可以用于请求的时间间隔，
+//! 命令行中的写法为 `名称:参数1:参数2`，例如 `exp:0.03`、`uni:8:64`。
+//!
+//! 经验分布 `Empirical` 从已有的 trace 中统计得到，用于生成与真实 trace 相似的 trace。
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use rand::Rng;
+use rand_distr::Distribution;
+
+use crate::{
+    error::HMSimError,
+    trace::{Op, TraceReader},
+    TraceRng,
+};
+
+/// 数学分布的枚举
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dist {
+    Exponential(f64),       // 指数分布 exp:lambda
+    Uniform(f64, f64),      // 均匀分布 uni:min:max
+    Poisson(f64),           // 泊松分布 poi:lambda
+    Normal(f64, f64),       // 正态分布 norm:mean:std_dev
+    LogNormal(f64, f64),    // 对数正态分布 lognorm:mu:sigma
+    Pareto(f64, f64),       // 帕累托分布 pareto:scale:shape
+    Weibull(f64, f64),      // 韦布尔分布 weibull:scale:shape
+    Gamma(f64, f64),        // 伽马分布 gamma:shape:scale
+    Zipf(u64, f64),         // Zipf 分布 zipf:n:s，取值为 1 到 n 的整数
+    Constant(f64),          // 固定值 const:value
+    Empirical(Arc<Empirical>),  // 经验分布 empirical:<trace 文件>
+    None
+}
+
+/// 每种分布在命令行中的写法及参数的取值范围
+pub const DIST_SYNTAX: [(&str, &str); 12] = [
+    ("exp", "exp:lambda (lambda > 0)"),
+    ("uni", "uni:min:max (0 <= min <= max)"),
+    ("poi", "poi:lambda (lambda > 0)"),
+    ("norm", "norm:mean:std_dev (std_dev >= 0)"),
+    ("lognorm", "lognorm:mu:sigma (sigma >= 0)"),
+    ("pareto", "pareto:scale:shape (scale > 0, shape > 0)"),
+    ("weibull", "weibull:scale:shape (scale > 0, shape > 0)"),
+    ("gamma", "gamma:shape:scale (shape > 0, scale > 0)"),
+    ("zipf", "zipf:n:s (n 为正整数, s >= 0)"),
+    ("const", "const:value (value >= 0)"),
+    ("empirical", "empirical:<trace 文件>"),
+    ("none", "none (不使用分布)"),
+];
+
+impl Dist {
+    /// 由分布名和参数构造分布，名称未知、参数个数不对或参数不合法时返回 None
+    ///
+    /// 经验分布需要读取 trace 文件，不通过该函数构造
+    pub fn from_parts(name: &str, params: &[f64]) -> Option<Dist> {
+        let dist = match (name, params) {
+            ("exp", &[lambda]) => Dist::Exponential(lambda),
+            ("uni", &[min, max]) => Dist::Uniform(min, max),
+            ("poi", &[lambda]) => Dist::Poisson(lambda),
+            ("norm", &[mean, std_dev]) => Dist::Normal(mean, std_dev),
+            ("lognorm", &[mu, sigma]) => Dist::LogNormal(mu, sigma),
+            ("pareto", &[scale, shape]) => Dist::Pareto(scale, shape),
+            ("weibull", &[scale, shape]) => Dist::Weibull(scale, shape),
+            ("gamma", &[shape, scale]) => Dist::Gamma(shape, scale),
+            ("zipf", &[n, s]) if n >= 1.0 && n.fract() == 0.0 && n <= u64::MAX as f64 => Dist::Zipf(n as u64, s),
+            ("const", &[value]) => Dist::Constant(value),
+            ("none", &[]) => Dist::None,
+            _ => return None,
+        };
+        if dist.is_valid() {
+            Some(dist)
+        } else {
+            None
+        }
+    }
+
+    /// 参数是否在合法范围内
+    pub fn is_valid(&self) -> bool {
+        let finite = |v: &[f64]| v.iter().all(|x| x.is_finite());
+        match *self {
+            Dist::Exponential(lambda) | Dist::Poisson(lambda) => finite(&[lambda]) && lambda > 0.0,
+            Dist::Uniform(min, max) => finite(&[min, max]) && 0.0 <= min && min <= max,
+            Dist::Normal(mean, std_dev) => finite(&[mean, std_dev]) && std_dev >= 0.0,
+            Dist::LogNormal(mu, sigma) => finite(&[mu, sigma]) && sigma >= 0.0,
+            Dist::Pareto(a, b) | Dist::Weibull(a, b) | Dist::Gamma(a, b) => finite(&[a, b]) && a > 0.0 && b > 0.0,
+            Dist::Zipf(n, s) => n >= 1 && finite(&[s]) && s >= 0.0,
+            Dist::Constant(value) => finite(&[value]) && value >= 0.0,
+            Dist::Empirical(ref empirical) => !empirical.is_empty(),
+            Dist::None => true,
+        }
+    }
+
+    /// 从分布中取一个样本，`Dist::None` 返回 0
+    ///
+    /// 参数必须已经通过 `is_valid` 的检查，正态分布可能返回负数，由调用者处理
+    pub fn sample(&self, rng: &mut TraceRng) -> f64 {
+        const VALID: &str = "分布参数应已通过校验";
+        match *self {
+            Dist::Exponential(lambda) => rand_distr::Exp::new(lambda).expect(VALID).sample(rng),
+            Dist::Uniform(min, max) => rng.gen_range(min..=max),
+            Dist::Poisson(lambda) => rand_distr::Poisson::new(lambda).expect(VALID).sample(rng),
+            Dist::Normal(mean, std_dev) => rand_distr::Normal::new(mean, std_dev).expect(VALID).sample(rng),
+            Dist::LogNormal(mu, sigma) => rand_distr::LogNormal::new(mu, sigma).expect(VALID).sample(rng),
+            Dist::Pareto(scale, shape) => rand_distr::Pareto::new(scale, shape).expect(VALID).sample(rng),
+            Dist::Weibull(scale, shape) => rand_distr::Weibull::new(scale, shape).expect(VALID).sample(rng),
+            Dist::Gamma(shape, scale) => rand_distr::Gamma::new(shape, scale).expect(VALID).sample(rng),
+            Dist::Zipf(n, s) => rand_distr::Zipf::new(n, s).expect(VALID).sample(rng),
+            Dist::Constant(value) => value,
+            Dist::Empirical(ref empirical) => empirical.sample(rng),
+            Dist::None => 0.0,
+        }
+    }
+
+    /// 分布的期望，期望不存在(如 shape <= 1 的帕累托分布)、难以计算或为 `Dist::None` 时返回 None
+    pub fn mean(&self) -> Option<f64> {
+        match *self {
+            Dist::Exponential(lambda) => Some(1.0 / lambda),
+            Dist::Uniform(min, max) => Some((min + max) / 2.0),
+            Dist::Poisson(lambda) => Some(lambda),
+            Dist::Normal(mean, _) => Some(mean),
+            Dist::LogNormal(mu, sigma) => Some((mu + sigma * sigma / 2.0).exp()),
+            Dist::Pareto(scale, shape) if shape > 1.0 => Some(shape * scale / (shape - 1.0)),
+            Dist::Pareto(..) => None,
+            Dist::Weibull(scale, shape) => Some(scale * gamma_fn(1.0 + 1.0 / shape)),
+            Dist::Gamma(shape, scale) => Some(shape * scale),
+            // 期望为 H(n, s - 1) / H(n, s)，n 太大时不计算
+            Dist::Zipf(n, s) if n <= 1_000_000 => {
+                let (num, den) = (1..=n).fold((0.0, 0.0), |(num, den), k| {
+                    let k = k as f64;
+                    (num + k.powf(1.0 - s), den + k.powf(-s))
+                });
+                Some(num / den)
+            },
+            Dist::Zipf(..) => None,
+            Dist::Constant(value) => Some(value),
+            Dist::Empirical(ref empirical) => empirical.mean(),
+            Dist::None => None,
+        }
+    }
+}
+
+/// 经验分布，保存直方图中每个桶的取值及累计样本数，按样本的频率抽样
+#[derive(Clone, Default, PartialEq)]
+pub struct Empirical {
+    // 升序排列的每个桶的取值(桶内样本的平均值)
+    values: Vec<f64>,
+    // cumulative[i] 为不大于 values[i] 的样本数
+    cumulative: Vec<u64>,
+    // 统计样本的 trace 文件，用于把分布还原为 `empirical:<trace 文件>`
+    source: String,
+}
+
+/// 直方图中保留的尾数位数，同一个桶内的取值相对误差不超过 2^-10(约 0.1%)，不超过 2048 的整数各自一个桶
+const HISTOGRAM_MANTISSA_BITS: u32 = 10;
+
+/// 流式统计经验分布的直方图，按取值的符号、指数和高位尾数分桶
+///
+/// 桶的数量只与取值的范围有关，与样本数量无关，统计很大的 trace 时内存占用也是有限的
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    // 桶编号 -> (样本数, 样本之和)
+    buckets: BTreeMap<i64, (u64, f64)>,
+}
+
+impl Histogram {
+    /// 加入一个样本，非有限值(NaN、无穷)会被忽略
+    pub fn add(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        // 正数的桶编号随取值递增，负数取相反数，保证桶编号与取值的顺序一致
+        let key = (value.abs().to_bits() >> (f64::MANTISSA_DIGITS - 1 - HISTOGRAM_MANTISSA_BITS)) as i64;
+        let key = if value < 0.0 { -key - 1 } else { key };
+        let bucket = self.buckets.entry(key).or_insert((0, 0.0));
+        bucket.0 += 1;
+        bucket.1 += value;
+    }
+
+    /// 转化为经验分布，每个桶的取值为桶内样本的平均值
+    pub fn into_empirical(self) -> Empirical {
+        let mut empirical = Empirical::default();
+        let mut total = 0;
+        for (count, sum) in self.buckets.into_values() {
+            total += count;
+            empirical.values.push(sum / count as f64);
+            empirical.cumulative.push(total);
+        }
+        empirical
+    }
+}
+
+impl Empirical {
+    /// 由样本构造经验分布，非有限值(NaN、无穷)会被忽略
+    pub fn from_samples(samples: impl IntoIterator<Item = f64>) -> Empirical {
+        let mut histogram = Histogram::default();
+        samples.into_iter().for_each(|v| histogram.add(v));
+        histogram.into_empirical()
+    }
+
+    /// 记录样本来自哪个 trace 文件
+    pub fn with_source(mut self, source: &str) -> Empirical {
+        self.source = source.to_string();
+        self
+    }
+
+    /// 统计样本的 trace 文件，直接由样本构造时为空
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// 样本数量
+    pub fn len(&self) -> u64 {
+        self.cumulative.last().copied().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 按样本的频率抽取一个取值，没有样本时返回 0
+    pub fn sample(&self, rng: &mut TraceRng) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let k = rng.gen_range(0..self.len());
+        let i = self.cumulative.partition_point(|&c| c <= k);
+        self.values[i]
+    }
+
+    /// 样本的平均值
+    pub fn mean(&self) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut prev = 0;
+        let sum = self.values.iter().zip(&self.cumulative).fold(0.0, |sum, (v, &c)| {
+            let count = c - prev;
+            prev = c;
+            sum + v * count as f64
+        });
+        Some(sum / self.len() as f64)
+    }
+
+    /// 第 p(0 到 1) 分位数
+    pub fn quantile(&self, p: f64) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
+        let k = ((p.clamp(0.0, 1.0) * self.len() as f64) as u64).min(self.len() - 1);
+        Some(self.values[self.cumulative.partition_point(|&c| c <= k)])
+    }
+}
+
+impl fmt::Debug for Empirical {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Empirical({} samples, {} values)", self.len(), self.values.len())
+    }
+}
+
+/// 与命令行相同的写法，可以由 `dist_analyze` 解析回来，`Dist::None` 为 none
+impl fmt::Display for Dist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Dist::Exponential(lambda) => write!(f, "exp:{}", lambda),
+            Dist::Uniform(min, max) => write!(f, "uni:{}:{}", min, max),
+            Dist::Poisson(lambda) => write!(f, "poi:{}", lambda),
+            Dist::Normal(mean, std_dev) => write!(f, "norm:{}:{}", mean, std_dev),
+            Dist::LogNormal(mu, sigma) => write!(f, "lognorm:{}:{}", mu, sigma),
+            Dist::Pareto(scale, shape) => write!(f, "pareto:{}:{}", scale, shape),
+            Dist::Weibull(scale, shape) => write!(f, "weibull:{}:{}", scale, shape),
+            Dist::Gamma(shape, scale) => write!(f, "gamma:{}:{}", shape, scale),
+            Dist::Zipf(n, s) => write!(f, "zipf:{}:{}", n, s),
+            Dist::Constant(value) => write!(f, "const:{}", value),
+            Dist::Empirical(ref empirical) => write!(f, "empirical:{}", empirical.source()),
+            Dist::None => write!(f, "none"),
+        }
+    }
+}
+
+/// 从一个 trace 中统计得到的特征，作为生成相似 trace 的经验分布
+#[derive(Debug, Clone)]
+pub struct TraceProfile {
+    /// 请求大小(单位为扇区)
+    pub lengths: Arc<Empirical>,
+    /// 请求的时间间隔(trace 中的 nextinter)
+    pub gaps: Arc<Empirical>,
+    /// 寻道距离，即请求偏移量与上一个请求结束地址的差(单位为扇区，可以为负数)
+    pub seeks: Arc<Empirical>,
+    /// 读、写请求数量
+    pub read_count: u64,
+    pub write_count: u64,
+}
+
+/// 已经统计过的 trace，同一个文件的多个 `empirical:<file>` 只读取一次
+static PROFILES: Mutex<Vec<(String, Arc<TraceProfile>)>> = Mutex::new(Vec::new());
+
+impl TraceProfile {
+    /// 读取 trace 文件，边读边统计各项特征
+    pub fn from_trace<P: AsRef<Path>>(path: P) -> Result<TraceProfile, HMSimError> {
+        let mut lengths = Histogram::default();
+        let mut gaps = Histogram::default();
+        let mut seeks = Histogram::default();
+        let mut read_count = 0;
+        let mut write_count = 0;
+        let mut prev_end = None;
+
+        for record in TraceReader::open(&path)? {
+            let record = record?;
+            match record.op {
+                Op::Read => read_count += 1,
+                Op::Write => write_count += 1,
+            }
+            lengths.add(record.len_sectors as f64);
+            gaps.add(record.next_inter);
+            if let Some(prev_end) = prev_end {
+                seeks.add(record.offset_sectors as f64 - prev_end as f64);
+            }
+            prev_end = Some(record.end_sectors());
+        }
+
+        let source = path.as_ref().display().to_string();
+        if read_count + write_count == 0 {
+            return Err(HMSimError::CommandError(format!("{} 中没有请求，无法统计经验分布", source)));
+        }
+
+        let empirical = |histogram: Histogram| Arc::new(histogram.into_empirical().with_source(&source));
+        Ok(TraceProfile {
+            lengths: empirical(lengths),
+            gaps: empirical(gaps),
+            seeks: empirical(seeks),
+            read_count,
+            write_count,
+        })
+    }
+
+    /// 与 `from_trace` 相同，但同一个文件只统计一次，之后返回共享的结果
+    pub fn cached(path: &str) -> Result<Arc<TraceProfile>, HMSimError> {
+        if let Some((_, profile)) = PROFILES.lock().unwrap().iter().find(|(p, _)| p == path) {
+            return Ok(profile.clone());
+        }
+        let profile = Arc::new(TraceProfile::from_trace(path)?);
+        PROFILES.lock().unwrap().push((path.to_string(), profile.clone()));
+        Ok(profile)
+    }
+}
+
+/// 伽马函数(Lanczos 近似，x > 0.5)，用于计算韦布尔分布的期望
+fn gamma_fn(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEF: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    let x = x - 1.0;
+    let t = x + G + 0.5;
+    let sum = COEF
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold(COEF[0], |sum, (i, c)| sum + c / (x + i as f64));
+    (2.0 * std::f64::consts::PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::seeded_rng;
+
+    #[test]
+    fn test_from_parts() {
+        assert_eq!(Dist::from_parts("uni", &[8.0, 64.0]), Some(Dist::Uniform(8.0, 64.0)));
+        assert_eq!(Dist::from_parts("zipf", &[100.0, 1.2]), Some(Dist::Zipf(100, 1.2)));
+        assert_eq!(Dist::from_parts("exp", &[]), None);
+        assert_eq!(Dist::from_parts("exp", &[0.0]), None);
+        assert_eq!(Dist::from_parts("uni", &[64.0, 8.0]), None);
+        assert_eq!(Dist::from_parts("norm", &[10.0, -1.0]), None);
+        assert_eq!(Dist::from_parts("zipf", &[1.5, 1.0]), None);
+        assert_eq!(Dist::from_parts("const", &[f64::NAN]), None);
+        assert_eq!(Dist::from_parts("beta", &[1.0, 1.0]), None);
+        assert_eq!(Dist::from_parts("none", &[]), Some(Dist::None));
+
+        // Display 的写法与命令行相同
+        assert_eq!(Dist::Exponential(0.000002).to_string(), "exp:0.000002");
+        assert_eq!(Dist::Zipf(100, 1.2).to_string(), "zipf:100:1.2");
+    }
+
+    #[test]
+    fn test_sample_mean() {
+        let dists = [
+            Dist::Exponential(0.5),
+            Dist::Uniform(8.0, 64.0),
+            Dist::Poisson(20.0),
+            Dist::Normal(100.0, 10.0),
+            Dist::LogNormal(1.0, 0.5),
+            Dist::Pareto(4.0, 3.0),
+            Dist::Weibull(10.0, 2.0),
+            Dist::Gamma(2.0, 3.0),
+            Dist::Zipf(100, 1.5),
+            Dist::Constant(16.0),
+        ];
+        let (mut rng, _) = seeded_rng(Some(7));
+        for dist in dists {
+            let n = 100_000;
+            let avg = (0..n).map(|_| dist.sample(&mut rng)).sum::<f64>() / n as f64;
+            let mean = dist.mean().unwrap();
+            assert!((avg - mean).abs() / mean < 0.05, "{:?}: sample mean {}, mean {}", dist, avg, mean);
+        }
+        assert_eq!(Dist::Pareto(1.0, 1.0).mean(), None);
+        assert_eq!(Dist::Empirical(Arc::new(Empirical::default())).mean(), None);
+        assert!((gamma_fn(5.0) - 24.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_empirical() {
+        let empirical = Empirical::from_samples([8.0, 8.0, 8.0, 64.0, f64::NAN]);
+        assert_eq!(empirical.len(), 4);
+        assert_eq!(empirical.mean(), Some(22.0));
+        assert_eq!(empirical.quantile(0.5), Some(8.0));
+        assert_eq!(empirical.quantile(1.0), Some(64.0));
+
+        let (mut rng, _) = seeded_rng(Some(7));
+        let n = 40_000;
+        let large = (0..n).filter(|_| empirical.sample(&mut rng) == 64.0).count();
+        assert!((large as f64 / n as f64 - 0.25).abs() < 0.01);
+
+        // 桶的数量与样本数量无关，相近的取值合并为一个桶，桶的取值为桶内样本的平均值
+        let empirical = Empirical::from_samples((0..1_000_000).map(|i| 1000.0 + (i % 1000) as f64 / 1000.0));
+        assert_eq!(empirical.len(), 1_000_000);
+        assert!(empirical.values.len() <= 2);
+        assert!((empirical.mean().unwrap() - 1000.4995).abs() < 1e-6);
+
+        // 负数、0 和正数按大小排列
+        let empirical = Empirical::from_samples([-4096.0, -1.0, 0.0, 1.0, 4096.0]);
+        assert_eq!(empirical.values, vec![-4096.0, -1.0, 0.0, 1.0, 4096.0]);
+    }
+
+    #[test]
+    fn test_trace_profile() {
+        let profile = TraceProfile::from_trace(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test.trace")).unwrap();
+        assert_eq!((profile.read_count, profile.write_count), (1, 14));
+        assert_eq!(profile.lengths.len(), 15);
+        assert_eq!(profile.seeks.len(), 14);
+        assert!((profile.lengths.mean().unwrap() - 208.0 / 15.0).abs() < 1e-9);
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test.trace");
+        assert!(Arc::ptr_eq(&TraceProfile::cached(path).unwrap(), &TraceProfile::cached(path).unwrap()));
+    }
+}
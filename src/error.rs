@@ -1,33 +1,115 @@
-/// 自定义错误处理
-/// 
+//! 自定义错误处理
 
-use std::fmt::Display;
+use std::{fmt::Display, io, path::Path};
+
+/// 错误来源，可以是 `ParseIntError`、`ParseFloatError` 等任意错误
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 #[derive(thiserror::Error, Debug)]
 pub enum HMSimError {
-    ParseError,
-    FileError,
-    CommandError
+    /// 命令行参数(大小、范围、比例、分布等)无法解析，`expected` 描述正确的格式
+    ParseError {
+        input: String,
+        expected: String,
+    },
+    /// 文件读写错误，保留文件路径和底层的 io::Error
+    FileError {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    /// 参数组合不合法
+    CommandError(String),
+    /// 配置文件无法解析或无法生成
+    ConfigError {
+        path: String,
+        #[source]
+        source: BoxError,
+    },
+    /// 输入文件某一行的某一列无法解析
+    LineError {
+        path: String,
+        line: u64,
+        column: &'static str,
+        token: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+}
+
+impl HMSimError {
+    /// 构造带文件路径的文件读写错误
+    pub fn file<P: AsRef<Path>>(path: P, source: io::Error) -> HMSimError {
+        HMSimError::FileError {
+            path: path.as_ref().display().to_string(),
+            source,
+        }
+    }
+
+    /// 构造参数解析错误
+    pub fn parse(input: &str, expected: &str) -> HMSimError {
+        HMSimError::ParseError {
+            input: input.to_string(),
+            expected: expected.to_string(),
+        }
+    }
+
+    /// 若是行解析错误且尚未记录文件路径，则补充文件路径
+    pub fn with_path<P: AsRef<Path>>(self, file: P) -> HMSimError {
+        match self {
+            HMSimError::LineError { path, line, column, token, source } if path.is_empty() => {
+                HMSimError::LineError {
+                    path: file.as_ref().display().to_string(),
+                    line,
+                    column,
+                    token,
+                    source,
+                }
+            }
+            HMSimError::FileError { path, source } if path.is_empty() => HMSimError::file(file, source),
+            other => other,
+        }
+    }
 }
 
 impl Display for HMSimError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            HMSimError::ParseError => {
-                write!(f, "单位转化错误")
+            HMSimError::ParseError { input, expected } => {
+                write!(f, "参数解析错误: 无法解析 {:?}，期望的格式为 {}", input, expected)
             },
-            HMSimError::FileError => {
-                write!(f, "文件读写错误")
+            HMSimError::FileError { path, source } => {
+                if path.is_empty() {
+                    write!(f, "文件读写错误: {}", source)
+                } else {
+                    write!(f, "文件读写错误 {}: {}", path, source)
+                }
             },
-            HMSimError::CommandError => {
-                write!(f, "参数解析错误")
+            HMSimError::CommandError(reason) => {
+                write!(f, "参数错误: {}", reason)
+            },
+            HMSimError::ConfigError { path, source } => {
+                write!(f, "配置文件 {} 解析错误: {}", path, source)
+            },
+            HMSimError::LineError { path, line, column, token, source } => {
+                if !path.is_empty() {
+                    write!(f, "{}:", path)?;
+                }
+                write!(f, "第 {} 行解析错误: 列 {} 的值 {:?} 不合法", line, column, token)?;
+                if let Some(source) = source {
+                    write!(f, " ({})", source)?;
+                }
+                Ok(())
             }
         }
     }
 }
 
-impl From<std::io::Error> for HMSimError {
-    fn from(_value: std::io::Error) -> Self {
-        HMSimError::FileError
+impl From<io::Error> for HMSimError {
+    fn from(source: io::Error) -> Self {
+        HMSimError::FileError {
+            path: String::new(),
+            source,
+        }
     }
-}
\ No newline at end of file
+}
@@ -2,6 +2,12 @@ pub mod error;
 pub mod log;
 pub mod commands;
 pub mod utils;
+pub mod trace;
+pub mod output;
+pub mod dist;
+pub mod config;
+
+pub use dist::Dist;
 
 /// 模拟器的块结构体，每个块大小是一个扇区(512B)
 
@@ -18,12 +24,24 @@ pub struct SizePair {
     pub size_end: HMSimBlock
 }
 
+impl Default for HMSimBlock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HMSimBlock {
     pub fn new() -> HMSimBlock{
         HMSimBlock { size_in_string: String::new(), byte: 0, block: 0 }
     }
 }
 
+impl Default for SizePair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SizePair {
     pub fn new() -> SizePair {
         SizePair { size_begin: HMSimBlock::new(), size_end: HMSimBlock::new() }
@@ -31,11 +49,5 @@ impl SizePair {
 }
 
 
-/// 数学分布的枚举
-#[derive(Debug, Clone)]
-pub enum Dist {
-    Exponential(f64),   // 指数分布
-    Uniform,            // 均匀分布
-    Poisson(f64),       // 泊松分布
-    None
-}
\ No newline at end of file
+/// 生成 trace 使用的随机数生成器，ChaCha 算法的输出与平台无关，相同种子可以复现相同的 trace
+pub type TraceRng = rand_chacha::ChaCha8Rng;
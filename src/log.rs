@@ -1,6 +1,6 @@
-use std::env;
+//! 日志处理，重载了 log::Log trait
 
-/// 日志处理，重载了 log::Log trait
+use std::env;
 
 use log::Level;
 
@@ -45,7 +45,8 @@ impl log::Log for DiskpineLogger {
             Level::Trace => 90, // 浅灰色
         };
 
-        println!(
+        // 日志输出到 stderr，stdout 只用于输出结果
+        eprintln!(
             "\u{1B}[{}m[{:>5}]: {} - {}\u{1B}[0m",
             color,
             record.level(),
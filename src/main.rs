@@ -1,7 +1,25 @@
-use ::log::{debug, info};
-use clap::{arg, Parser, Subcommand};
+use std::process::ExitCode;
+
+use ::log::{error, info};
+use clap::{Parser, Subcommand};
 use diskpine::{
-    commands::Pine, error::HMSimError, log, utils::{self, dist_analyze, range_to_num, size_range_to_start_end, string_to_hmsim_block}, Dist, HMSimBlock, SizePair
+    commands::{
+        filter::{Filter, FilterArgs},
+        generate_tape_trace::TapeTrace,
+        generate_trace::{DiskTrace, DiskTraceArgs},
+        merge::{Merge, MergeArgs},
+        origin_to_sim::{OriginToSim, OriginToSimArgs},
+        sample::{Sample, SampleArgs, SampleMode},
+        split::{Split, SplitArgs},
+        timewarp::{Timewarp, TimewarpArgs},
+        transform::{Transform, TransformArgs},
+        warmup_trace::{WarmupTrace, WarmupTraceArgs},
+        Pine,
+    },
+    config::TapeTraceConfig,
+    error::HMSimError,
+    log,
+    output::OutputFormat,
 };
 use dotenv::dotenv;
 
@@ -16,129 +34,107 @@ Version: {version}
 {usage-heading} {usage}
 {all-args} {tab}")]
 struct Args {
+    /// 分析结果的输出格式，结果输出到 stdout，日志输出到 stderr
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+
+    /// 输出文件已经存在时覆盖该文件(只对输出 trace 的子命令有效，trace-foot-size 不输出文件)
+    #[arg(long, global = true)]
+    force: bool,
+
     /// 子命令
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 enum Commands {
-    // /// 生成 trace，可以指定随机或顺序，读写比例，请求大小
-    // GenerateTrace {
-    // /// 指定 trace 的起始地址，若不指定该字段，则随机从一个地址开始
-    // #[arg(short, long)]
-    // addr_start: Option<u64>,
-
-    // /// 通过指定数据量确定 trace(单位: B, KB, MB, GB, TB 等)
-    // #[arg(short, long)]
-    // #[clap(value_parser = string_to_hmsim_block)]
-    // size_data: Option<HMSimBlock>,
-
-    // /// 通过指定请求数量确定 trace(单位: 条数)
-    // #[arg(short, long)]
-    // num_request: Option<u64>,
-
-    // /// 指定每个请求的大小，若不指定，则通过指定随机变化的范围随机变化
-    // /// (单位: B, KB, MB, GB, TB 等)
-    // #[arg(short, long)]
-    // #[clap(value_parser = string_to_hmsim_block)]
-    //length_request: Option<HMSimBlock>,
-    // },
+    /// 生成通用的磁盘 trace，可以指定随机、顺序或固定步长，对齐方式，地址范围，读写比例，请求大小和队列深度
+    GenerateTrace {
+        #[command(flatten)]
+        args: DiskTraceArgs,
+    },
+
+    /// 生成测试缓存的 trace：先写入 warmup_size 的数据预热缓存，再接上随机读写请求(参数相同时复用缓存的随机请求)
+    WarmupTrace {
+        #[command(flatten)]
+        args: WarmupTraceArgs,
+    },
+
+    /// 合并多个 trace：按顺序首尾相接，或按到达时间交错合并，可以把每个输入平移到独占的 LBA 窗口
+    Merge {
+        #[command(flatten)]
+        args: MergeArgs,
+    },
+
+    /// 按读写类型、地址范围、请求大小、时间窗口和请求序号筛选 trace，所有条件同时满足的请求才会被保留
+    Filter {
+        #[command(flatten)]
+        args: FilterArgs,
+    },
+
+    /// 改写 trace 中请求的地址：平移、缩放、对齐、按容量回绕，以及拆分过大的请求，请求的到达时间保持不变
+    Transform {
+        #[command(flatten)]
+        args: TransformArgs,
+    },
+
+    /// 改写 trace 的时间间隔：按系数加速或减速、按目标 IOPS 缩放、截断过长的空闲时间，或全部置为 0
+    Timewarp {
+        #[command(flatten)]
+        args: TimewarpArgs,
+    },
+
+    /// 按比例抽样 trace：均匀抽样、按时间窗口抽样或按地址哈希抽样(SHARDS)
+    Sample {
+        #[command(flatten)]
+        args: SampleArgs,
+    },
+
+    /// 按请求数量、数据量或模拟时间把 trace 切分为多个编号的文件，可以把读写请求分开
+    Split {
+        #[command(flatten)]
+        args: SplitArgs,
+    },
+
     /// 计算 trace 数据量及落盘量
     TraceFootSize {
         /// trace 文件名
         #[arg(short, long)]
         file: String,
+
+        /// trace 的抽样比例，按 1 / sample-rate 估计原 trace 的数据量和落盘量；不指定时读取 sample 子命令记录的比例
+        #[arg(long = "sample-rate", value_name = "rate")]
+        sample_rate: Option<f64>,
+
+        /// trace 的抽样方式，只有 spatial 抽样可以估计落盘量；不指定时读取 sample 子命令记录的方式，都没有时为 uniform
+        #[arg(long = "sample-mode", value_enum, ignore_case = true)]
+        sample_mode: Option<SampleMode>,
     },
 
     /// 将微软原始 trace 格式转化为 HMSim 格式的 trace，修改后的文件与其同名
     OriginToSim {
-        /// 原始 trace 文件名
-        #[arg(short, long)]
-        file: String,
-
-        /// 是否保留时间戳
-        #[arg(short, long)]
-        timestamp: bool,
+        #[command(flatten)]
+        args: OriginToSimArgs,
     },
 
     /// 生成适用于 Tape 的 trace
     GenerateTapeTrace {
-        /// 读写操作的总大小
-        // #[arg(value_name = "data_size")]
-        #[arg(name = "size", long)]
-        #[clap(value_parser = string_to_hmsim_block)]
-        total_size: HMSimBlock,
-
-        /// 生成的请求粒度，即块大小
-        #[arg(name = "blk_size", long)]
-        #[clap(value_parser = string_to_hmsim_block)]
-        block_size: HMSimBlock,
-
-        /// 指定生成的请求类型 [支持参数为 r, w, rw]
-        #[arg(long)]
-        rw: Option<String>,
-
-        /// 指定读操作的顺序性(该参数当 rw 包含 r 有效) [可选参数为 rand(默认), seq]
-        #[arg(name = "ro", long, value_name = "read_order")]
-        read_order: Option<String>,
-
-        /// 指定写操作的顺序性(该参数当 rw 包含 w 有效) [可选参数为 rand(默认), seq]
-        #[arg(name = "wo", long, value_name = "write_order")]
-        write_order: Option<String>,
-
-        /// 若只有读操作，指定已经写的数据地址(如果 ro=rand, 该值必须指定且不为 0) [默认值: 0]
-        #[arg(name = "woff", long, value_name = "write_offset")]
-        #[clap(value_parser = string_to_hmsim_block)]
-        write_offset: Option<HMSimBlock>,
+        #[command(flatten)]
+        args: TapeTraceConfig,
 
-        /// 单个写请求大小范围，若没有写请求则设置为 0-0
-        #[arg(name = "wsize", long)]
-        #[clap(value_parser = range_to_num)]
-        write_size: Option<(u64, u64)>,
-
-        /// 单个读请求大小范围，若没有读请求则设置为 0-0
-        #[arg(name = "rsize", long)]
-        #[clap(value_parser = range_to_num)]
-        read_size: Option<(u64, u64)>,
-
-        /// 单个请求大小范围(该参数当 wsize 和 rsize 均为 None 时有效)
-        #[arg(long)]
-        // #[clap(requires_if_all(&["write_size", "read_size"], &["None", "None"]))]
-        #[clap(value_parser = range_to_num)]
-        rwsize: Option<(u64, u64)>,
+        /// 场景配置文件(TOML)，键名与命令行参数相同，命令行中的参数覆盖文件中的参数，每个 [[phase]] 为多阶段负载中的一个阶段
+        #[arg(long, value_name = "scenario.toml")]
+        config: Option<String>,
 
-        /// 设置读写操作的 batch [支持参数为 r, w, rw]
+        /// 只打印合并配置文件和命令行参数后的完整配置(TOML)，不生成 trace
         #[arg(long)]
-        batch: Option<String>,
-
-        /// 每个 write batch 的大小范围(单位为 blk_size，该参数当 batch 包含 'w' 时有效)
-        #[arg(name = "batch_IOw_num", long)]
-        #[clap(value_parser = range_to_num)]
-        // #[clap(requires_if("batch", "Some"))] // 设置该参数依赖于 batch
-        // batch_write_size: Option<SizePair>,
-        batch_iow_num: Option<(u64, u64)>,
-
-        /// 每个 read batch 的大小范围(单位为 blk_size，该参数当 batch 包含 'r' 时有效)
-        #[arg(name = "batch_IOr_num", long)]
-        #[clap(value_parser = range_to_num)]
-        // #[clap(requires_if("batch", "Some"))] // 设置该参数依赖于 batch
-        // batch_read_size: Option<SizePair>,
-        batch_ior_num: Option<(u64, u64)>,
-
-        /// 生成的时间间隔满足的数学分布[支持的参数：exp:lambda(指数分布:lambda)，uni(均匀分布)，poi(泊松分布:lambda)]
-        #[arg(name = "time_dist", long)]
-        #[clap(value_parser = dist_analyze)]
-        time_interval_distribution: Option<Dist>,
-
-        /// 生成的请求大小满足的数学分布[支持的参数：exp:lambda(指数分布:lambda)，uni(均匀分布)，poi(泊松分布:lambda)]
-        #[arg(name = "req_dist", long)]
-        #[clap(value_parser = dist_analyze)]
-        req_length_distribution: Option<Dist>,
+        dump_config: bool,
     },
 }
 
-fn main() -> Result<(), HMSimError> {
+fn main() -> ExitCode {
     // 从 .env 文件中读取 LOG_LEVEL 环境变量
     dotenv().ok();
     log::log_init();
@@ -146,55 +142,62 @@ fn main() -> Result<(), HMSimError> {
 
     let args = Args::parse();
 
+    // 出错时打印错误信息，并以非零状态码退出
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), HMSimError> {
+    let pine = Pine { output_format: args.output_format };
+
     match args.command {
-        // Commands::GenerateTrace {
-        //     addr_start,
-        //     size_data,
-        //     num_request,
-        //     length_request,
-        // } => Pine.generate_trace(),
-        Commands::TraceFootSize { file } => Pine.trace_foot_size(file.as_str()),
-
-        Commands::OriginToSim { file, timestamp } => Pine.origin_to_sim(file.as_str(), timestamp),
-
-        Commands::GenerateTapeTrace {
-            total_size,
-            block_size,
-            rw,
-            read_order,
-            write_order,
-            write_offset,
-            write_size,
-            read_size,
-            rwsize,
-            batch,
-            batch_iow_num,
-            batch_ior_num,
-            time_interval_distribution,
-            req_length_distribution
-        } => {
-            let tape_trace_struct = utils::command_gen_tape_trace_to_tape_trace_struct(
-                total_size,
-                block_size,
-                rw,
-                read_order,
-                write_order,
-                write_offset,
-                write_size,
-                read_size,
-                rwsize,
-                batch,
-                batch_iow_num,
-                batch_ior_num,
-                time_interval_distribution,
-                req_length_distribution
-            );
+        Commands::GenerateTrace { args: trace_args } => {
+            pine.generate_trace(DiskTrace { force: args.force, ..trace_args.resolve()? })
+        },
 
-            // debug!("{:#?}", tape_trace_struct);
-            Pine.generate_tape_trace(tape_trace_struct?)
+        Commands::WarmupTrace { args: trace_args } => {
+            pine.warmup_trace(WarmupTrace { force: args.force, ..trace_args.resolve()? })
         },
 
-        _ => Err(HMSimError::CommandError),
+        Commands::Merge { args: merge_args } => pine.merge(Merge { force: args.force, ..merge_args.resolve()? }),
+
+        Commands::Filter { args: filter_args } => pine.filter(Filter { force: args.force, ..filter_args.resolve()? }),
+
+        Commands::Transform { args: transform_args } => {
+            pine.transform(Transform { force: args.force, ..transform_args.resolve()? })
+        },
+
+        Commands::Timewarp { args: timewarp_args } => {
+            pine.timewarp(Timewarp { force: args.force, ..timewarp_args.resolve()? })
+        },
+
+        Commands::Sample { args: sample_args } => pine.sample(Sample { force: args.force, ..sample_args.resolve()? }),
+
+        Commands::Split { args: split_args } => pine.split(Split { force: args.force, ..split_args.resolve()? }),
+
+        Commands::TraceFootSize { file, sample_rate, sample_mode } => pine.trace_foot_size(file.as_str(), sample_rate, sample_mode),
+
+        Commands::OriginToSim { args: origin_args } => pine.origin_to_sim(OriginToSim { force: args.force, ..origin_args.resolve() }),
+
+        Commands::GenerateTapeTrace { args: trace_args, config, dump_config } => {
+            let file = match config {
+                Some(path) => TapeTraceConfig::load(path)?,
+                None => TapeTraceConfig::default(),
+            };
+            let tape_trace_struct = TapeTrace { force: args.force, ..file.resolve(&trace_args)? };
+
+            // debug!("{:#?}", tape_trace_struct);
+            if dump_config {
+                pine.dump_config(&tape_trace_struct)
+            } else {
+                pine.generate_tape_trace(tape_trace_struct)
+            }
+        },
     }
 }
 
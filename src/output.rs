@@ -0,0 +1,145 @@
+//! 子命令结果的输出格式
+//!
+//! 分析类子命令的结果统一输出到 stdout，日志输出到 stderr，
+//! 通过全局参数 `--output-format` 选择文本、JSON 或 CSV 格式。
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::HMSimError;
+
+/// 结果的输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// 便于阅读的文本格式
+    #[default]
+    Text,
+    /// 一个 JSON 对象
+    Json,
+    /// 表头一行、数据一行的 CSV
+    Csv,
+}
+
+/// 子命令的结果，JSON 和 CSV 格式由字段的序列化结果生成
+pub trait Report: Serialize {
+    /// 文本格式的结果
+    fn to_text(&self) -> String;
+}
+
+/// 将结果按照指定格式输出到 stdout
+pub fn emit<R: Report>(report: &R, format: OutputFormat) -> Result<(), HMSimError> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    write_report(&mut out, report, format)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// 将结果按照指定格式写入 `out`
+pub fn write_report<W: Write, R: Report>(out: &mut W, report: &R, format: OutputFormat) -> Result<(), HMSimError> {
+    match format {
+        OutputFormat::Text => {
+            writeln!(out, "{}", report.to_text())?;
+        },
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(report).map_err(io::Error::from)?;
+            writeln!(out, "{}", json)?;
+        },
+        OutputFormat::Csv => {
+            let value = serde_json::to_value(report).map_err(io::Error::from)?;
+            let mut columns = vec![];
+            flatten("", &value, &mut columns);
+
+            let header: Vec<String> = columns.iter().map(|(k, _)| csv_escape(k)).collect();
+            let row: Vec<String> = columns.iter().map(|(_, v)| csv_escape(v)).collect();
+            writeln!(out, "{}", header.join(","))?;
+            writeln!(out, "{}", row.join(","))?;
+        },
+    }
+    Ok(())
+}
+
+/// 将嵌套的对象展开成 `a.b` 形式的列名，数组按 JSON 字符串输出
+fn flatten(prefix: &str, value: &Value, columns: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let name = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&name, value, columns);
+            }
+        },
+        Value::Null => columns.push((prefix.to_string(), String::new())),
+        Value::String(s) => columns.push((prefix.to_string(), s.clone())),
+        other => columns.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// 计算比例，分母为 0 时返回 None(JSON 中为 null)
+pub fn ratio(numerator: u64, denominator: u64) -> Option<f64> {
+    if denominator == 0 {
+        None
+    } else {
+        Some(numerator as f64 / denominator as f64)
+    }
+}
+
+/// 文本格式中的比例，没有比例(分母为 0)时为 "-"
+pub fn ratio_text(ratio: Option<f64>) -> String {
+    ratio.map_or(String::from("-"), |r| format!("{:.4}", r))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Demo {
+        file: String,
+        count: u64,
+        rate: Option<f64>,
+        nested: Nested,
+    }
+
+    #[derive(Serialize)]
+    struct Nested {
+        a: u64,
+    }
+
+    impl Report for Demo {
+        fn to_text(&self) -> String {
+            format!("file: {}", self.file)
+        }
+    }
+
+    #[test]
+    fn test_write_report() {
+        let demo = Demo { file: "a,b.trace".to_string(), count: 3, rate: None, nested: Nested { a: 1 } };
+
+        let mut out = vec![];
+        write_report(&mut out, &demo, OutputFormat::Csv).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "file,count,rate,nested.a\n\"a,b.trace\",3,,1\n");
+
+        let mut out = vec![];
+        write_report(&mut out, &demo, OutputFormat::Json).unwrap();
+        let value: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value["nested"]["a"], 1);
+
+        let mut out = vec![];
+        write_report(&mut out, &demo, OutputFormat::Text).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "file: a,b.trace\n");
+
+        assert_eq!(ratio_text(ratio(0, 0)), "-");
+        assert_eq!(ratio_text(ratio(3, 0)), "-");
+        assert_eq!(ratio_text(ratio(1, 3)), "0.3333");
+    }
+}
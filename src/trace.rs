@@ -0,0 +1,379 @@
+//! HMSim 格式 trace 的数据模型，以及流式的读取器和写入器
+//!
+//! 所有子命令都基于这里的 `TraceRecord`、`TraceReader` 和 `TraceWriter` 解析和生成 trace，
+//! 只保留一部分请求或交错合并时用 `RetimingWriter` 保持请求的到达时间不变，
+//! 外部工具也可以直接链接本 crate 复用。
+//!
+//! HMSim(DiskSim) 格式的 trace 每行一个请求，以空格分隔的 6 列含义如下：
+//!
+//! 1. 读写(RW)
+//! 2. Hit(暂时固定为 Hit)
+//! 3. 偏移量(offset，单位：扇区)
+//! 4. 长度(length，单位：块，扇区，即 512B)
+//! 5. 服务时间(servtime，即完成该次请求的总时间)
+//! 6. 与下一个请求的到达时间之差(单位为 ms，源码中的字段名为 nextinter)，请求的到达时间为之前所有请求的时间间隔之和
+
+use std::{
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Lines, Write},
+    path::Path,
+};
+
+use crate::error::{BoxError, HMSimError};
+
+/// 每一行 trace 的列名，用于报告解析错误
+pub const COLUMNS: [&str; 6] = ["rw", "hit", "offset", "length", "servtime", "nextinter"];
+
+/// 请求的读写类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    Read,
+    Write,
+}
+
+impl Op {
+    /// trace 中的表示形式，读为 R，写为 W
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Op::Read => "R",
+            Op::Write => "W",
+        }
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// 一条 HMSim 格式的请求
+///
+/// 偏移量和长度的单位均为扇区(512B)，两个时间字段按照 DiskSim 的 `%f` 格式输出(保留 6 位小数)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    pub op: Op,
+    pub hit: bool,
+    pub offset_sectors: u64,
+    pub len_sectors: u64,
+    pub service_time: f64,
+    pub next_inter: f64,
+}
+
+impl TraceRecord {
+    /// 构造一条服务时间和时间间隔均为 0 的请求
+    pub fn new(op: Op, offset_sectors: u64, len_sectors: u64) -> TraceRecord {
+        TraceRecord {
+            op,
+            hit: true,
+            offset_sectors,
+            len_sectors,
+            service_time: 0.0,
+            next_inter: 0.0,
+        }
+    }
+
+    /// 请求结束的扇区(不包含)
+    pub fn end_sectors(&self) -> u64 {
+        self.offset_sectors.saturating_add(self.len_sectors)
+    }
+
+    /// 解析一行 trace，`line_no` 从 1 开始，仅用于报告错误
+    pub fn parse_line(line: &str, line_no: u64) -> Result<TraceRecord, HMSimError> {
+        let data: Vec<&str> = line.split_whitespace().collect();
+
+        if data.len() != COLUMNS.len() {
+            return Err(HMSimError::LineError {
+                path: String::new(),
+                line: line_no,
+                column: "-",
+                token: line.to_string(),
+                source: Some(format!("应有 {} 列，实际为 {} 列", COLUMNS.len(), data.len()).into()),
+            });
+        }
+
+        let error = |column: usize, source: Option<BoxError>| HMSimError::LineError {
+            path: String::new(),
+            line: line_no,
+            column: COLUMNS[column],
+            token: data[column].to_string(),
+            source,
+        };
+
+        let op = match data[0] {
+            "R" => Op::Read,
+            "W" => Op::Write,
+            _ => return Err(error(0, None)),
+        };
+
+        let hit = match data[1] {
+            "Hit" => true,
+            "Miss" => false,
+            _ => return Err(error(1, None)),
+        };
+
+        Ok(TraceRecord {
+            op,
+            hit,
+            offset_sectors: data[2].parse().map_err(|e| error(2, Some(Box::new(e))))?,
+            len_sectors: data[3].parse().map_err(|e| error(3, Some(Box::new(e))))?,
+            service_time: data[4].parse().map_err(|e| error(4, Some(Box::new(e))))?,
+            next_inter: data[5].parse().map_err(|e| error(5, Some(Box::new(e))))?,
+        })
+    }
+}
+
+impl Display for TraceRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {:.6} {:.6}",
+            self.op,
+            if self.hit { "Hit" } else { "Miss" },
+            self.offset_sectors,
+            self.len_sectors,
+            self.service_time,
+            self.next_inter
+        )
+    }
+}
+
+/// 按行流式读取 HMSim trace，迭代返回 `TraceRecord`，空行会被跳过
+///
+/// 解析错误会带上文件路径和行号
+pub struct TraceReader<R: BufRead> {
+    lines: Lines<R>,
+    path: String,
+    line_no: u64,
+}
+
+impl TraceReader<BufReader<File>> {
+    /// 打开一个 trace 文件
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, HMSimError> {
+        let file = File::open(&path).map_err(|e| HMSimError::file(&path, e))?;
+        let mut reader = TraceReader::new(BufReader::new(file));
+        reader.path = path.as_ref().display().to_string();
+        Ok(reader)
+    }
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(reader: R) -> TraceReader<R> {
+        TraceReader {
+            lines: reader.lines(),
+            path: String::new(),
+            line_no: 0,
+        }
+    }
+
+    /// 最近一次读取的行号(从 1 开始)
+    pub fn line_no(&self) -> u64 {
+        self.line_no
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = Result<TraceRecord, HMSimError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(HMSimError::from(e).with_path(&self.path))),
+            };
+            self.line_no += 1;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(TraceRecord::parse_line(&line, self.line_no).map_err(|e| e.with_path(&self.path)));
+        }
+    }
+}
+
+/// 带缓冲的 HMSim trace 写入器，每条请求占一行
+pub struct TraceWriter<W: Write> {
+    inner: BufWriter<W>,
+}
+
+impl TraceWriter<File> {
+    /// 创建(或清空)一个 trace 文件
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, HMSimError> {
+        let file = File::create(&path).map_err(|e| HMSimError::file(&path, e))?;
+        Ok(TraceWriter::new(file))
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W) -> TraceWriter<W> {
+        TraceWriter {
+            inner: BufWriter::new(writer),
+        }
+    }
+
+    /// 写入一条请求
+    pub fn write_record(&mut self, record: &TraceRecord) -> Result<(), HMSimError> {
+        writeln!(self.inner, "{}", record)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), HMSimError> {
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    /// 写出缓冲区中的内容，并取回内部的写入器
+    pub fn into_inner(self) -> Result<W, HMSimError> {
+        self.inner.into_inner().map_err(|e| e.into_error().into())
+    }
+}
+
+/// 打开输出的 trace 文件，`-` 表示 stdout
+///
+/// 文件已经存在时，只有指定 force 才会覆盖，否则报错
+pub fn open_output(output: &str, force: bool) -> Result<TraceWriter<Box<dyn Write>>, HMSimError> {
+    if output == "-" {
+        return Ok(TraceWriter::new(Box::new(io::stdout())));
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+
+    match options.open(output) {
+        Ok(file) => Ok(TraceWriter::new(Box::new(file))),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(HMSimError::CommandError(format!(
+            "输出文件 {} 已经存在，如需覆盖请指定 --force",
+            output
+        ))),
+        Err(e) => Err(HMSimError::file(output, e)),
+    }
+}
+
+/// 两个路径是否指向同一个文件(例如 `./a.trace` 与 `a.trace`)，无法解析路径(文件不存在或为 `-`)时按字符串比较
+pub fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// 按到达时间写出请求的写入器，用于只保留一部分请求(filter、sample、split)或交错合并(merge)
+///
+/// 每个请求的时间间隔改为与下一个写出的请求的到达时间之差，因此写出的请求的到达时间差与原来相同。
+/// 写出一个请求需要知道下一个请求的到达时间，所以总是暂存最后写入的请求，`flush` 时按原来的时间间隔写出
+pub struct RetimingWriter<W: Write> {
+    writer: TraceWriter<W>,
+    // 暂存的请求及其到达时间
+    pending: Option<(f64, TraceRecord)>,
+    // 已经写出的请求数量，以及它们的时间间隔之和
+    requests: u64,
+    duration: f64,
+}
+
+impl<W: Write> RetimingWriter<W> {
+    pub fn new(writer: TraceWriter<W>) -> RetimingWriter<W> {
+        RetimingWriter { writer, pending: None, requests: 0, duration: 0.0 }
+    }
+
+    /// 写入一条到达时间为 arrival 的请求，到达时间不能早于之前写入的请求
+    pub fn write_record(&mut self, arrival: f64, record: TraceRecord) -> Result<(), HMSimError> {
+        if let Some((prev_arrival, mut prev)) = self.pending.replace((arrival, record)) {
+            prev.next_inter = arrival - prev_arrival;
+            self.write_out(&prev)?;
+        }
+        Ok(())
+    }
+
+    /// 写出暂存的最后一个请求(保留原来的时间间隔)并刷新缓冲区，之后写入的请求不再与它衔接
+    pub fn flush(&mut self) -> Result<(), HMSimError> {
+        if let Some((_, last)) = self.pending.take() {
+            self.write_out(&last)?;
+        }
+        self.writer.flush()
+    }
+
+    /// 已经写出的请求数量
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// 已经写出的请求的时间间隔之和
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// 写出所有请求，并取回内部的写入器
+    pub fn into_inner(mut self) -> Result<W, HMSimError> {
+        self.flush()?;
+        self.writer.into_inner()
+    }
+
+    fn write_out(&mut self, record: &TraceRecord) -> Result<(), HMSimError> {
+        self.requests += 1;
+        self.duration += record.next_inter;
+        self.writer.write_record(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let input = "W Hit 6259488 8 0.000000 0.000000\nR Hit 6384456 8 0.000000 1.987200\n";
+
+        let records: Vec<TraceRecord> = TraceReader::new(input.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].op, Op::Read);
+        assert_eq!(records[1].offset_sectors, 6384456);
+
+        let mut writer = TraceWriter::new(Vec::new());
+        for record in &records {
+            writer.write_record(record).unwrap();
+        }
+        writer.flush().unwrap();
+        let output = String::from_utf8(writer.inner.into_inner().unwrap()).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_retiming_writer() {
+        // 到达时间为 0、1、3 的请求，第二个请求的时间间隔改为 2，最后一个请求保留原来的时间间隔
+        let mut writer = RetimingWriter::new(TraceWriter::new(Vec::new()));
+        for (arrival, offset) in [(0.0, 0), (1.0, 8), (3.0, 16)] {
+            let mut record = TraceRecord::new(Op::Read, offset, 8);
+            record.next_inter = 0.5;
+            writer.write_record(arrival, record).unwrap();
+        }
+        assert_eq!(writer.requests(), 2);
+        writer.flush().unwrap();
+        assert_eq!((writer.requests(), writer.duration()), (3, 3.5));
+
+        let output = writer.into_inner().unwrap();
+        let gaps: Vec<f64> = TraceReader::new(&output[..]).map(|r| r.unwrap().next_inter).collect();
+        assert_eq!(gaps, [1.0, 2.0, 0.5]);
+    }
+
+    #[test]
+    fn test_malformed_line() {
+        let input = "W Hit 8 8 0.000000 0\n\nW Hit 8 x 0.000000 0\n";
+        let res: Result<Vec<TraceRecord>, HMSimError> = TraceReader::new(input.as_bytes()).collect();
+        match res {
+            Err(HMSimError::LineError { line, column, token, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(column, "length");
+                assert_eq!(token, "x");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
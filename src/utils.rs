@@ -1,31 +1,55 @@
-use log::{debug, info};
+use std::sync::Arc;
+
+use rand::{Rng, SeedableRng};
 use regex::Regex;
 
-use crate::{commands::generate_tape_trace::TapeTrace, error::HMSimError, Dist, HMSimBlock, SizePair};
+use crate::{commands::generate_tape_trace::{OpMix, TapeTrace}, dist::{Empirical, TraceProfile, DIST_SYNTAX}, error::HMSimError, Dist, HMSimBlock, SizePair, TraceRng};
+
+/// 由种子构造随机数生成器，未指定种子时随机生成一个，返回 (生成器, 实际使用的种子)
+pub fn seeded_rng(seed: Option<u64>) -> (TraceRng, u64) {
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+    (TraceRng::seed_from_u64(seed), seed)
+}
 
 /// 将以 KB, MB 为单位的字符串转化成 HMSimBlock 结构体(即以扇区为单位)
 pub fn string_to_hmsim_block(size: &str) -> Result<HMSimBlock, HMSimError> {
-    Ok(unit_parse(size)?)
+    unit_parse(size)
+}
+
+/// 将扇区数转化为带单位的字符串，取能整除的最大单位，是 `string_to_hmsim_block` 的逆过程
+pub fn hmsim_block_to_string(block: u64) -> String {
+    let byte = block.saturating_mul(512);
+    for (unit, multiplier) in [("T", 1u64 << 40), ("G", 1 << 30), ("M", 1 << 20), ("K", 1 << 10)] {
+        if byte > 0 && byte.is_multiple_of(multiplier) {
+            return format!("{}{}", byte / multiplier, unit);
+        }
+    }
+    byte.to_string()
 }
 
 /// 将字符串表示的范围大小转化成 SizePair 结构体
 pub fn size_range_to_start_end(size: &str) -> Result<SizePair, HMSimError> {
-    Ok(parse_dash(size)?)
+    parse_dash(size)
 }
 
 /// 将形如 a:b 的形式转化为 (f32, f32)
 pub fn rate_to_num(size: &str) -> Result<(f32, f32), HMSimError> {
-    Ok(parse_colon(size)?)
+    parse_colon(size)
 }
 
 /// 将形如 a-b 的形式转化为 (u64, u64)
 pub fn range_to_num(size: &str) -> Result<(u64, u64), HMSimError> {
-    Ok(parse_dash_num(size)?)
+    parse_dash_num(size)
+}
+
+/// 将形如 a-b 的形式转化为 (f64, f64)，a 和 b 可以是小数
+pub fn time_range_to_num(range: &str) -> Result<(f64, f64), HMSimError> {
+    parse_dash_float(range)
 }
 
 /// 把用横杠(-)分隔的两个字符转化成两个 HMSimBlock 结构体
 fn parse_dash(size: &str) -> Result<SizePair, HMSimError> {
-    let regex = Regex::new(r"(\d+[A-Za-z]+)-(\d+[A-Za-z]+)").unwrap();
+    let regex = Regex::new(r"^\s*(\d+[A-Za-z]*)-(\d+[A-Za-z]*)\s*$").unwrap();
 
     let mut size_parse_pair = SizePair::new();
 
@@ -34,39 +58,62 @@ fn parse_dash(size: &str) -> Result<SizePair, HMSimError> {
         size_parse_pair.size_end = unit_parse(&captures[2])?;
         Ok(size_parse_pair)
     } else {
-        return Err(HMSimError::ParseError);
+        Err(HMSimError::parse(size, "a-b，例如 4K-1M"))
     }
 }
 
-/// 把用横杠(-)分隔的两个字符转化成两个 HMSimBlock 结构体
+/// 把用横杠(-)分隔的两个数字转化成 (u64, u64)
 fn parse_dash_num(size: &str) -> Result<(u64, u64), HMSimError> {
-    let regex = Regex::new(r"(\d+)-(\d+)").unwrap();
+    let regex = Regex::new(r"^\s*(\d+)-(\d+)\s*$").unwrap();
+    let expected = "a-b，a 和 b 均为非负整数，例如 40-40960";
 
     if let Some(captures) = regex.captures(size) {
-        let first = captures[1].parse::<u64>().unwrap();
-        let second = captures[2].parse::<u64>().unwrap();
+        let first = captures[1].parse::<u64>().map_err(|_| HMSimError::parse(size, expected))?;
+        let second = captures[2].parse::<u64>().map_err(|_| HMSimError::parse(size, expected))?;
+        if first > second {
+            return Err(HMSimError::parse(size, "a-b，a 不大于 b"));
+        }
+        Ok((first, second))
+    } else {
+        Err(HMSimError::parse(size, expected))
+    }
+}
+
+/// 把用横杠(-)分隔的两个非负数转化成 (f64, f64)
+fn parse_dash_float(range: &str) -> Result<(f64, f64), HMSimError> {
+    let regex = Regex::new(r"^\s*(\d+(?:\.\d+)?)-(\d+(?:\.\d+)?)\s*$").unwrap();
+    let expected = "a-b，a 和 b 为非负数，例如 0-1000.5";
+
+    if let Some(captures) = regex.captures(range) {
+        let first = captures[1].parse::<f64>().map_err(|_| HMSimError::parse(range, expected))?;
+        let second = captures[2].parse::<f64>().map_err(|_| HMSimError::parse(range, expected))?;
+        if first > second {
+            return Err(HMSimError::parse(range, "a-b，a 不大于 b"));
+        }
         Ok((first, second))
     } else {
-        return Err(HMSimError::ParseError);
+        Err(HMSimError::parse(range, expected))
     }
 }
 
 /// 把用冒号分隔的两个字符转化成两个数字
 fn parse_colon(size: &str) -> Result<(f32, f32), HMSimError> {
-    let regex = Regex::new(r"(\d+):(\d+)").unwrap();
+    let regex = Regex::new(r"^\s*(\d+(?:\.\d+)?):(\d+(?:\.\d+)?)\s*$").unwrap();
+    let expected = "a:b，a 和 b 为非负数，例如 1:3";
 
     if let Some(captures) = regex.captures(size) {
-        let first = captures[1].parse::<f32>().unwrap();
-        let second = captures[2].parse::<f32>().unwrap();
+        let first = captures[1].parse::<f32>().map_err(|_| HMSimError::parse(size, expected))?;
+        let second = captures[2].parse::<f32>().map_err(|_| HMSimError::parse(size, expected))?;
         Ok((first, second))
     } else {
-        return Err(HMSimError::ParseError);
+        Err(HMSimError::parse(size, expected))
     }
 }
 
-/// 对 KB, MB 为单位的字符串进行正则匹配
+/// 对 KB, MB 为单位的字符串进行正则匹配，不加单位默认为 B
 fn unit_parse(size: &str) -> Result<HMSimBlock, HMSimError> {
-    let regex = Regex::new(r"(\d+)([A-Za-z]+)").unwrap();
+    let regex = Regex::new(r"^\s*(\d+)\s*([A-Za-z]*)\s*$").unwrap();
+    let expected = "数字加单位 B/K/KB/M/MB/G/GB/T/TB(忽略大小写)，例如 256K";
 
     let mut hmsim_block = HMSimBlock::new();
 
@@ -74,199 +121,121 @@ fn unit_parse(size: &str) -> Result<HMSimBlock, HMSimError> {
 
     if let Some(captures) = regex.captures(size) {
         // 提取数字部分
-        let number = captures[1].parse::<u64>().unwrap();
+        let number = captures[1].parse::<u64>().map_err(|_| HMSimError::parse(size, expected))?;
 
         // 提取单位部分
         let unit = &captures[2];
-        
-        if unit.eq_ignore_ascii_case("b") {
-            hmsim_block.byte = number;
+
+        let multiplier: u64 = if unit.is_empty() || unit.eq_ignore_ascii_case("b") {
+            1
         } else if unit.eq_ignore_ascii_case("k") || unit.eq_ignore_ascii_case("kb") {
-            hmsim_block.byte = number * 1024;
+            1024
         } else if unit.eq_ignore_ascii_case("m") || unit.eq_ignore_ascii_case("mb") {
-            hmsim_block.byte = number * 1024 * 1024;
+            1024 * 1024
         } else if unit.eq_ignore_ascii_case("g") || unit.eq_ignore_ascii_case("gb") {
-            hmsim_block.byte = number * 1024 * 1024 * 1024;
+            1024 * 1024 * 1024
         } else if unit.eq_ignore_ascii_case("t") || unit.eq_ignore_ascii_case("tb") {
-            hmsim_block.byte = number * 1024 * 1024 * 1024 * 1024;
+            1024 * 1024 * 1024 * 1024
         } else {
-            return Err(HMSimError::ParseError);
-        }
+            return Err(HMSimError::parse(size, expected));
+        };
 
+        hmsim_block.byte = number
+            .checked_mul(multiplier)
+            .ok_or_else(|| HMSimError::parse(size, "不超过 16EB 的大小"))?;
         hmsim_block.block = hmsim_block.byte / 512;
     } else {
-        return Err(HMSimError::ParseError);
+        return Err(HMSimError::parse(size, expected));
     }
 
     Ok(hmsim_block)
 }
 
-/// 数学分布转化为 Dist 枚举
+/// 数学分布转化为 Dist 枚举，写法见 `DIST_SYNTAX`
 pub fn dist_analyze(dist: &str) -> Result<Dist, HMSimError> {
-
-    let tmp: Vec<&str> = dist.split(':').collect();
+    let tmp: Vec<&str> = dist.trim().split(':').collect();
     let dist_name = tmp[0];
-    let param: Vec<f64> = tmp.iter().skip(1).map(|s| s.parse::<f64>().unwrap()).collect();
 
-    match dist_name {
-        "exp" => Ok(Dist::Exponential(param[0])),       // 指数分布
-        "uni" => Ok(Dist::Uniform),                     // 均匀分布
-        "poi" => Ok(Dist::Poisson(param[0])),           // 泊松分布
-        _ => Err(HMSimError::ParseError)
-    }
+    // 已知的分布给出该分布的写法，否则列出所有支持的分布
+    let expected = match DIST_SYNTAX.iter().find(|(name, _)| *name == dist_name) {
+        Some((_, syntax)) => syntax.to_string(),
+        None => DIST_SYNTAX.iter().map(|(_, syntax)| *syntax).collect::<Vec<_>>().join("，"),
+    };
+
+    let param = tmp
+        .iter()
+        .skip(1)
+        .map(|s| s.parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| HMSimError::parse(dist, &expected))?;
+
+    Dist::from_parts(dist_name, &param).ok_or_else(|| HMSimError::parse(dist, &expected))
 }
 
-/// 将子命令参数转化为 TapeTrace 结构体
-pub fn command_gen_tape_trace_to_tape_trace_struct(
-    total_size: HMSimBlock,
-    // rwrate: (f32, f32),
-    block_size: HMSimBlock,
-    rw: Option<String>,
-    read_order: Option<String>,
-    write_order: Option<String>,
-    write_offset: Option<HMSimBlock>,
-    write_size: Option<(u64, u64)>,
-    read_size: Option<(u64, u64)>,
-    rwsize: Option<(u64, u64)>,
-    batch: Option<String>,
-    batch_iow_num: Option<(u64, u64)>,
-    batch_ior_num: Option<(u64, u64)>,
-    time_interval_distribution: Option<Dist>,
-    req_length_distribution: Option<Dist>
-) -> Result<TapeTrace, HMSimError> {
-    let mut tape_trace = TapeTrace::new();
-
-    tape_trace.total_size = total_size.block;
-
-    tape_trace.block_size = block_size.block;
-
-
-    if let Some(rw) = rw {
-        tape_trace.rw = rw;
-    } else {
-        tape_trace.rw = String::new();
-    }
+/// 请求大小的分布，`empirical:<file>` 使用该 trace 中请求大小的经验分布
+pub fn req_dist_analyze(dist: &str) -> Result<Dist, HMSimError> {
+    empirical_analyze(dist, |profile| &profile.lengths)
+}
 
-    // debug!("read_order: {:?}", read_order);
-    match read_order {
-        Some(value) => {
-            match value.as_str() {
-                "rand" => {
-                    tape_trace.read_order = String::from("rand");
-                },
-                "seq" => {
-                    tape_trace.read_order = String::from("seq");
-                },
-                _ => {
-                    return Err(HMSimError::ParseError)
-                }
-            }
+/// 时间间隔的分布，`empirical:<file>` 使用该 trace 中时间间隔的经验分布
+pub fn time_dist_analyze(dist: &str) -> Result<Dist, HMSimError> {
+    empirical_analyze(dist, |profile| &profile.gaps)
+}
+
+/// 寻道距离的分布，`empirical:<file>` 使用该 trace 中寻道距离的经验分布
+pub fn seek_dist_analyze(dist: &str) -> Result<Dist, HMSimError> {
+    empirical_analyze(dist, |profile| &profile.seeks)
+}
+
+/// 读写比例，`empirical:<file>` 使用该 trace 中读写请求数量的比例
+pub fn rwrate_analyze(rate: &str) -> Result<(f32, f32), HMSimError> {
+    match rate.trim().strip_prefix("empirical:") {
+        Some(file) => {
+            let profile = TraceProfile::cached(file)?;
+            Ok((profile.read_count as f32, profile.write_count as f32))
         },
-        None => {
-            tape_trace.read_order = String::from("rand");
-        }
+        None => rate_to_num(rate),
     }
+}
 
-    match write_order {
-        Some(value) => {
-            match value.as_str() {
-                "rand" => {
-                    tape_trace.write_order = String::from("rand");
-                },
-                "seq" => {
-                    tape_trace.write_order = String::from("seq");
-                },
-                _ => {
-                    return Err(HMSimError::ParseError)
-                }
+/// 解析 `empirical:<file>` 形式的经验分布，由 `field` 选择 trace 的哪一项特征，其它形式交给 `dist_analyze`
+fn empirical_analyze(dist: &str, field: fn(&TraceProfile) -> &Arc<Empirical>) -> Result<Dist, HMSimError> {
+    match dist.trim().strip_prefix("empirical:") {
+        Some(file) => {
+            let profile = TraceProfile::cached(file)?;
+            let empirical = field(&profile);
+            if empirical.is_empty() {
+                return Err(HMSimError::CommandError(format!("{} 中的请求太少，无法统计经验分布", file)));
             }
+            Ok(Dist::Empirical(empirical.clone()))
         },
-        None => {
-            tape_trace.write_order = String::from("rand");
-        }
+        None => dist_analyze(dist),
     }
+}
 
-    match write_offset {
-        Some(value) => {
-            tape_trace.write_offset = value.block;
-        },
-        None => {
-            tape_trace.write_offset = 0;
+/// 设置读写比例，未指定 rw 时由比例推断，指定了 rw 时两者必须一致
+pub fn apply_rwrate(tape_trace: &mut TapeTrace, (read_rate, write_rate): (f32, f32)) -> Result<(), HMSimError> {
+    let rw = match (read_rate > 0.0, write_rate > 0.0) {
+        (true, true) => OpMix::Rw,
+        (true, false) => OpMix::R,
+        (false, true) => OpMix::W,
+        (false, false) => {
+            return Err(HMSimError::parse(&format!("{}:{}", read_rate, write_rate), "a:b，a 和 b 不能同时为 0"));
         }
+    };
+    match tape_trace.rw {
+        None => tape_trace.rw = Some(rw),
+        Some(current) if current != rw => {
+            return Err(HMSimError::CommandError(format!(
+                "rwrate={}:{} 与 rw={} 冲突，该比例对应 rw={}",
+                read_rate, write_rate, current, rw
+            )));
+        },
+        Some(_) => {},
     }
-
-    // tape_trace.read_rate = rwrate.0;
-    // tape_trace.write_rate = rwrate.1;
-
-    if let Some(size_pair) = write_size {
-        tape_trace.write_size_start = size_pair.0;
-        tape_trace.write_size_end = size_pair.1;
-        tape_trace.write_size_range = size_pair.1 - size_pair.0;
-    } else {
-        tape_trace.write_size_start = 0;
-        tape_trace.write_size_end = 0;
-        tape_trace.write_size_range = 0;
-    }
-
-    if let Some(size_pair) = read_size {
-        tape_trace.read_size_start = size_pair.0;
-        tape_trace.read_size_end = size_pair.1;
-        tape_trace.read_size_range = size_pair.1 - size_pair.0;
-    } else {
-        tape_trace.read_size_start = 0;
-        tape_trace.read_size_end = 0;
-        tape_trace.read_size_range = 0;
-    }
-
-    if let Some(size_pair) = rwsize {
-        tape_trace.rwsize_start = size_pair.0;
-        tape_trace.rwsize_end = size_pair.1;
-        tape_trace.rwsize_range = size_pair.1 - size_pair.0;
-    } else {
-        tape_trace.rwsize_start = 0;
-        tape_trace.rwsize_end = 0;
-        tape_trace.rwsize_range = 0;
-    }
-
-    if let Some(size_pair) = batch_iow_num {
-        tape_trace.batch_iow_num_begin = size_pair.0;
-        tape_trace.batch_iow_num_end = size_pair.1;
-        tape_trace.batch_iow_num_range = size_pair.1 - size_pair.0;
-    } else {
-        tape_trace.batch_iow_num_begin = 0;
-        tape_trace.batch_iow_num_end = 0;
-        tape_trace.batch_iow_num_range = 0;
-    }
-
-    if let Some(size_pair) = batch_ior_num {
-        tape_trace.batch_ior_num_begin = size_pair.0;
-        tape_trace.batch_ior_num_end = size_pair.1;
-        tape_trace.batch_ior_num_range = size_pair.1 - size_pair.0;
-    } else {
-        tape_trace.batch_iow_num_begin = 0;
-        tape_trace.batch_iow_num_end = 0;
-        tape_trace.batch_iow_num_range = 0;
-    }
-
-    if let Some(batch) = batch {
-        tape_trace.batch = batch;
-    } else {
-        tape_trace.batch = String::new();
-    }
-    
-    if let Some(time_interval_dist) = time_interval_distribution {
-        tape_trace.time_interval_dist = time_interval_dist;
-    } else {
-        tape_trace.time_interval_dist = Dist::None
-    }
-    
-    if let Some(req_length_dist) = req_length_distribution {
-        tape_trace.req_length_dist = req_length_dist;
-    } else {
-        tape_trace.req_length_dist = Dist::None
-    }
-
-    Ok(tape_trace)
+    tape_trace.read_rate = read_rate;
+    tape_trace.write_rate = write_rate;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -285,7 +254,24 @@ mod tests {
         let size = "12M";
         println!("{:?}", string_to_hmsim_block(size));
     }
-    // fn test_command_gen_tape_trace_to_tape_trace_struct() {
-    //     let tape_trace = TapeTrace::new();
-    // }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(string_to_hmsim_block("4096").unwrap().block, 8);
+        assert!(matches!(string_to_hmsim_block("10Q"), Err(HMSimError::ParseError { .. })));
+        assert!(matches!(string_to_hmsim_block("99999999999T"), Err(HMSimError::ParseError { .. })));
+        for size in ["0", "512", "1536", "256K", "230G", "10T"] {
+            assert_eq!(hmsim_block_to_string(string_to_hmsim_block(size).unwrap().block), size);
+        }
+        assert!(matches!(range_to_num("5-"), Err(HMSimError::ParseError { .. })));
+        assert!(matches!(range_to_num("8-1"), Err(HMSimError::ParseError { .. })));
+        assert_eq!(time_range_to_num("0.5-10").unwrap(), (0.5, 10.0));
+        assert!(matches!(time_range_to_num("10-0.5"), Err(HMSimError::ParseError { .. })));
+        assert!(matches!(dist_analyze("exp"), Err(HMSimError::ParseError { .. })));
+        assert!(matches!(dist_analyze("exp:x"), Err(HMSimError::ParseError { .. })));
+        assert!(matches!(dist_analyze("uni"), Err(HMSimError::ParseError { .. })));
+        assert!(matches!(dist_analyze("gamma:0:1"), Err(HMSimError::ParseError { .. })));
+        assert_eq!(dist_analyze("weibull:10:2").unwrap(), Dist::Weibull(10.0, 2.0));
+        assert_eq!(dist_analyze("none").unwrap(), Dist::None);
+    }
 }
@@ -0,0 +1,32 @@
+//! 集成测试共用的测试数据和辅助函数，每个测试文件只用到其中一部分
+#![allow(dead_code)]
+
+use std::{env, process};
+
+use diskpine::trace::{TraceReader, TraceRecord};
+
+/// 测试用的 HMSim 格式 trace
+pub const TRACE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test.trace");
+
+/// 读出 trace 中的全部请求
+pub fn read(path: &str) -> Vec<TraceRecord> {
+    TraceReader::open(path).unwrap().map(|r| r.unwrap()).collect()
+}
+
+/// 每个请求的到达时间，即之前所有请求的时间间隔之和
+pub fn arrivals(records: &[TraceRecord]) -> Vec<f64> {
+    let mut clock = 0.0;
+    records
+        .iter()
+        .map(|r| {
+            let arrival = clock;
+            clock += r.next_inter;
+            arrival
+        })
+        .collect()
+}
+
+/// 临时目录下的文件名，带上进程号，避免并行运行的测试互相覆盖
+pub fn temp_path(name: &str) -> String {
+    env::temp_dir().join(format!("diskpine-{}-{}", process::id(), name)).display().to_string()
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::fs;
+
+use diskpine::{
+    commands::{
+        filter::{filter, Filter, FilterArgs},
+        generate_tape_trace::OpMix,
+    },
+    trace::Op,
+};
+
+use common::{read, temp_path, TRACE};
+
+#[test]
+fn test_filter() {
+    let output = temp_path("filter.trace");
+    let all = read(TRACE);
+
+    // 不指定条件时原样输出
+    let stats = filter(&Filter { file: TRACE.to_string(), output: output.clone(), force: true, ..Filter::default() }).unwrap();
+    assert_eq!((stats.scanned, stats.kept), (all.len() as u64, all.len() as u64));
+    assert_eq!(read(&output), all);
+
+    // 命令行写法的条件与直接构造的相同
+    let args = FilterArgs {
+        file: TRACE.to_string(),
+        output: output.clone(),
+        op: Some(OpMix::W),
+        offset: Some(String::from("0-3G")),
+        length: Some(String::from("4K-4K")),
+        time: Some(String::from("0-20.5")),
+        index: Some(String::from("1-100")),
+    };
+    let condition = Filter { force: true, ..args.resolve().unwrap() };
+    assert_eq!(condition.offset, Some((0, 3 * 2 * 1024 * 1024)));
+    assert_eq!(condition.length, Some((8, 8)));
+
+    let stats = filter(&condition).unwrap();
+    let kept = read(&output);
+    assert_eq!((kept.len(), stats.kept), (2, 2));
+    assert!(kept.iter().all(|r| r.op == Op::Write && r.len_sectors == 8));
+    assert!(stats.first_arrival.unwrap() >= 0.0 && stats.last_arrival.unwrap() < 20.5);
+
+    // 保留的请求之间的时间间隔之和等于首尾两个请求的到达时间之差
+    let gaps: f64 = kept[..kept.len() - 1].iter().map(|r| r.next_inter).sum();
+    assert!((gaps - (stats.last_arrival.unwrap() - stats.first_arrival.unwrap())).abs() < 1e-6);
+
+    fs::remove_file(&output).unwrap();
+}
@@ -0,0 +1,428 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use diskpine::{
+    commands::generate_tape_trace::{generate_tape_trace, AccessOrder, BatchMode, Budget, OpMix, RateBasis, TapeTrace},
+    config::TapeTraceConfig,
+    error::HMSimError,
+    trace::{Op, TraceReader},
+    utils::{req_dist_analyze, seek_dist_analyze, time_dist_analyze},
+    Dist,
+};
+
+use common::{read, temp_path};
+
+/// 随机读写的 TapeTrace，输出到临时目录
+fn tape_trace(name: &str) -> TapeTrace {
+    let mut trace = TapeTrace::new();
+    trace.total_size = 100 * 2048;
+    trace.block_size = 512;
+    trace.rw = Some(OpMix::Rw);
+    trace.read_order = AccessOrder::Rand;
+    trace.write_order = AccessOrder::Seq;
+    trace.write_offset = 1024 * 2048;
+    trace.read_size_start = 1;
+    trace.read_size_end = 8;
+    trace.write_size_start = 1;
+    trace.write_size_end = 8;
+    trace.seed = Some(2024);
+    trace.output = temp_path(&format!("{}.trace", name));
+    trace
+}
+
+#[test]
+fn test_output_and_seed() {
+    let first = tape_trace("first");
+    let second = tape_trace("second");
+
+    let stats = generate_tape_trace(first.clone()).unwrap();
+    generate_tape_trace(second.clone()).unwrap();
+    assert_eq!(stats.seed, 2024);
+
+    // 相同的种子生成完全相同的 trace
+    let content = fs::read_to_string(&first.output).unwrap();
+    assert_eq!(content, fs::read_to_string(&second.output).unwrap());
+
+    let records: Vec<_> = TraceReader::open(&first.output).unwrap().map(|r| r.unwrap()).collect();
+    assert_eq!(records.len() as u64, stats.read_op + stats.write_op);
+    let writes = records.iter().filter(|r| r.op == Op::Write).count() as u64;
+    assert_eq!(writes, stats.write_op);
+
+    // 不指定 force 时不覆盖已经存在的文件
+    match generate_tape_trace(first.clone()) {
+        Err(HMSimError::CommandError(_)) => {},
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(content, fs::read_to_string(&first.output).unwrap());
+
+    let mut forced = first.clone();
+    forced.force = true;
+    forced.seed = Some(1);
+    generate_tape_trace(forced).unwrap();
+    assert_ne!(content, fs::read_to_string(&first.output).unwrap());
+
+    fs::remove_file(&first.output).unwrap();
+    fs::remove_file(&second.output).unwrap();
+}
+
+#[test]
+fn test_rwrate() {
+    // 按请求数量 1:3，读请求成批生成
+    let mut by_op = tape_trace("rwrate-op");
+    by_op.block_size = 8;
+    by_op.read_rate = 1.0;
+    by_op.write_rate = 3.0;
+    by_op.batch = BatchMode::R;
+    by_op.batch_ior_num_begin = 4;
+    by_op.batch_ior_num_end = 8;
+    let stats = generate_tape_trace(by_op.clone()).unwrap();
+    let rate = stats.op_rate_w_r.unwrap();
+    assert!((rate - 3.0).abs() < 0.05, "op rate(w:r): {}", rate);
+
+    // 按数据量 2:1，读写请求大小范围不同
+    let mut by_data = tape_trace("rwrate-data");
+    by_data.block_size = 8;
+    by_data.read_rate = 2.0;
+    by_data.write_rate = 1.0;
+    by_data.rwrate_by = RateBasis::Data;
+    by_data.read_size_end = 2;
+    by_data.write_size_start = 8;
+    by_data.write_size_end = 16;
+    let stats = generate_tape_trace(by_data.clone()).unwrap();
+    let rate = stats.data_rate_w_r.unwrap();
+    assert!((rate - 0.5).abs() < 0.02, "data rate(w:r): {}", rate);
+
+    fs::remove_file(&by_op.output).unwrap();
+    fs::remove_file(&by_data.output).unwrap();
+}
+
+#[test]
+fn test_req_dist_for_writes() {
+    // 请求大小分布同时作用于读写请求，按 blk_size 向下对齐
+    let mut trace = tape_trace("req-dist");
+    trace.block_size = 8;
+    trace.req_length_dist = Dist::Constant(20.0);
+    trace.time_interval_dist = Dist::Uniform(1.0, 2.0);
+    generate_tape_trace(trace.clone()).unwrap();
+
+    for record in TraceReader::open(&trace.output).unwrap() {
+        let record = record.unwrap();
+        assert_eq!(record.len_sectors, 16);
+        assert!((1.0..=2.0).contains(&record.next_inter));
+    }
+
+    // 小于 blk_size 的请求大小不会写出，也不计入请求数量
+    trace.total_size = 0;
+    trace.count = 1000;
+    trace.rw = Some(OpMix::W);
+    trace.req_length_dist = Dist::Uniform(0.0, 16.0);
+    trace.force = true;
+    let stats = generate_tape_trace(trace.clone()).unwrap();
+    let records = TraceReader::open(&trace.output).unwrap().count() as u64;
+    assert_eq!((stats.read_op, stats.write_op, stats.requests), (0, 1000, 1000));
+    assert_eq!(records, 1000);
+
+    // 参数不合法的分布
+    trace.req_length_dist = Dist::Gamma(0.0, 1.0);
+    assert!(matches!(generate_tape_trace(trace.clone()), Err(HMSimError::CommandError(_))));
+
+    // 请求大小总是小于 blk_size 时不会生成任何请求，报错而不是一直循环
+    trace.req_length_dist = Dist::Constant(4.0);
+    match generate_tape_trace(trace.clone()) {
+        Err(HMSimError::CommandError(msg)) => assert!(msg.contains("没有生成有效的请求"), "{}", msg),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // 生成失败时不留下不完整的输出文件，重新运行时不需要 force
+    assert!(!Path::new(&trace.output).exists());
+    trace.force = false;
+    trace.req_length_dist = Dist::Constant(20.0);
+    generate_tape_trace(trace.clone()).unwrap();
+
+    // rsize 的起始值为 0 时同样不写出大小为 0 的读请求
+    trace.rw = Some(OpMix::R);
+    trace.req_length_dist = Dist::None;
+    trace.read_size_start = 0;
+    trace.read_size_end = 2;
+    trace.force = true;
+    let stats = generate_tape_trace(trace.clone()).unwrap();
+    let records = read(&trace.output);
+    assert!(records.iter().all(|r| r.len_sectors > 0));
+    assert_eq!((stats.read_op, stats.requests, records.len() as u64), (1000, 1000, 1000));
+
+    fs::remove_file(&trace.output).unwrap();
+}
+
+#[test]
+fn test_empirical_dist() {
+    // 请求大小和时间间隔只会取 test.trace 中出现过的值(读请求超出已写区域时会被截断)
+    let source = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test.trace");
+    let records: Vec<_> = TraceReader::open(source).unwrap().map(|r| r.unwrap()).collect();
+
+    let mut trace = tape_trace("empirical");
+    trace.block_size = 8;
+    trace.req_length_dist = req_dist_analyze(&format!("empirical:{}", source)).unwrap();
+    trace.time_interval_dist = time_dist_analyze(&format!("empirical:{}", source)).unwrap();
+    trace.seek_dist = seek_dist_analyze(&format!("empirical:{}", source)).unwrap();
+    generate_tape_trace(trace.clone()).unwrap();
+
+    for record in TraceReader::open(&trace.output).unwrap() {
+        let record = record.unwrap();
+        if record.op == Op::Write {
+            assert!(records.iter().any(|r| r.len_sectors == record.len_sectors), "{}", record);
+        }
+        assert!(records.iter().any(|r| r.next_inter == record.next_inter), "{}", record);
+    }
+
+    fs::remove_file(&trace.output).unwrap();
+}
+
+#[test]
+fn test_locality() {
+    // hotcold 读：80% 的读请求落在前 20% 的地址空间
+    let mut hotcold = tape_trace("hotcold");
+    hotcold.rw = Some(OpMix::R);
+    hotcold.read_order = AccessOrder::Hotcold;
+    hotcold.block_size = 8;
+    hotcold.read_size_end = 1;
+    let region = hotcold.write_offset;
+    generate_tape_trace(hotcold.clone()).unwrap();
+    let reads: Vec<_> = TraceReader::open(&hotcold.output).unwrap().map(|r| r.unwrap()).collect();
+    let hot = reads.iter().filter(|r| r.offset_sectors < region / 5).count() as f64 / reads.len() as f64;
+    assert!((hot - 0.8).abs() < 0.05, "hot share: {}", hot);
+
+    // zipf 覆盖写：地址都在 wregion 内，且越靠前的 extent 越热
+    let mut zipf = tape_trace("zipf");
+    zipf.rw = Some(OpMix::W);
+    zipf.write_order = AccessOrder::Zipf;
+    zipf.write_region = 64 * 2048;
+    zipf.extent_size = 2048;
+    zipf.write_size_end = 1;
+    generate_tape_trace(zipf.clone()).unwrap();
+    let writes: Vec<_> = TraceReader::open(&zipf.output).unwrap().map(|r| r.unwrap()).collect();
+    assert!(writes.iter().all(|r| r.end_sectors() <= zipf.write_region));
+    let first = writes.iter().filter(|r| r.offset_sectors < 2048).count();
+    let last = writes.iter().filter(|r| r.offset_sectors >= 63 * 2048).count();
+    assert!(first > 10 * last.max(1), "first: {}, last: {}", first, last);
+
+    // 覆盖写需要指定区域
+    let mut no_region = tape_trace("no-region");
+    no_region.write_order = AccessOrder::Rand;
+    no_region.write_offset = 0;
+    no_region.read_order = AccessOrder::Seq;
+    assert!(matches!(generate_tape_trace(no_region), Err(HMSimError::CommandError(_))));
+
+    fs::remove_file(&hotcold.output).unwrap();
+    fs::remove_file(&zipf.output).unwrap();
+}
+
+#[test]
+fn test_schedule() {
+    // 先顺序写，再按 iops 随机读写一段时间，最后只读固定数量的请求
+    let path = temp_path("schedule.toml");
+    fs::write(
+        &path,
+        r#"
+        [[phase]]
+        name = "fill"
+        size = "8M"
+        rw = "w"
+        wsize = "2-2"
+
+        [[phase]]
+        name = "mixed"
+        duration = 1
+        iops = 2000
+        rwrate = "7:3"
+
+        [[phase]]
+        name = "burst"
+        count = 100
+        rw = "r"
+        "#,
+    )
+    .unwrap();
+
+    let mut base = tape_trace("schedule");
+    base.write_offset = 0;
+    let config = TapeTraceConfig::load(&path).unwrap();
+    assert!(config.resolve(&TapeTraceConfig::default()).is_ok());
+    base.phases = config.phase.iter().map(|phase| phase.apply_phase(&base).unwrap()).collect();
+    let stats = generate_tape_trace(base.clone()).unwrap();
+
+    let records: Vec<_> = TraceReader::open(&base.output).unwrap().map(|r| r.unwrap()).collect();
+    let phases = &stats.phases;
+    assert_eq!(phases.len(), 3);
+    assert_eq!(phases[0].requests, 16);
+    assert_eq!(phases[2].requests, 100);
+    assert_eq!(phases[2].first_request + phases[2].requests, records.len() as u64);
+
+    // 各阶段的请求在 trace 中首尾相连
+    for pair in phases.windows(2) {
+        assert_eq!(pair[0].first_request + pair[0].requests, pair[1].first_request);
+        assert_eq!(pair[0].end_time, pair[1].start_time);
+    }
+    assert!(records[..16].iter().all(|r| r.op == Op::Write));
+    assert!(records[records.len() - 100..].iter().all(|r| r.op == Op::Read));
+    // 2000 IOPS 持续 1 秒
+    assert!((phases[1].requests as f64 - 2000.0).abs() < 200.0, "{:?}", phases[1]);
+    assert!(phases[1].end_time - phases[1].start_time >= 1000.0);
+
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&base.output).unwrap();
+}
+
+#[test]
+fn test_seq_read_phases() {
+    // 第二个顺序读阶段的范围比第一个小，顺序读的偏移量在阶段之间重新从 0 开始
+    let path = temp_path("seq-read.toml");
+    fs::write(
+        &path,
+        r#"
+        [[phase]]
+        name = "a"
+        rw = "r"
+        ro = "seq"
+        size = "1M"
+
+        [[phase]]
+        name = "b"
+        rw = "r"
+        ro = "seq"
+        size = "64K"
+        "#,
+    )
+    .unwrap();
+
+    let base = tape_trace("seq-read");
+    let config = TapeTraceConfig::load(&path).unwrap();
+    let mut trace = base.clone();
+    trace.phases = config.phase.iter().map(|phase| phase.apply_phase(&base).unwrap()).collect();
+    let stats = generate_tape_trace(trace.clone()).unwrap();
+    assert_eq!(stats.phases[0].read_data, 2048);
+    assert_eq!(stats.phases[1].read_data, 128);
+
+    let records: Vec<_> = TraceReader::open(&trace.output).unwrap().map(|r| r.unwrap()).collect();
+    assert!(records.iter().all(|r| r.len_sectors > 0));
+    let phase_b = &records[stats.phases[1].first_request as usize..];
+    assert_eq!(phase_b[0].offset_sectors, 0);
+    assert!(phase_b.iter().all(|r| r.end_sectors() <= 128));
+
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&trace.output).unwrap();
+}
+
+#[test]
+fn test_phase_write_offset() {
+    // 后面阶段的 woff 同样生效：第二个阶段可以读 woff 之前的数据
+    let path = temp_path("phase-woff.toml");
+    fs::write(
+        &path,
+        r#"
+        [[phase]]
+        name = "fill"
+        count = 4
+        rw = "w"
+        wsize = "1-1"
+
+        [[phase]]
+        name = "read"
+        count = 200
+        rw = "r"
+        woff = "1G"
+        "#,
+    )
+    .unwrap();
+
+    let mut base = tape_trace("phase-woff");
+    base.write_offset = 0;
+    let config = TapeTraceConfig::load(&path).unwrap();
+    let mut trace = base.clone();
+    trace.phases = config.phase.iter().map(|phase| phase.apply_phase(&base).unwrap()).collect();
+    let stats = generate_tape_trace(trace.clone()).unwrap();
+
+    let records: Vec<_> = TraceReader::open(&trace.output).unwrap().map(|r| r.unwrap()).collect();
+    let reads = &records[stats.phases[1].first_request as usize..];
+    assert!(reads.iter().all(|r| r.op == Op::Read && r.end_sectors() <= 1024 * 2048));
+    assert!(reads.iter().any(|r| r.offset_sectors >= 4 * 512), "reads stay inside the first phase");
+
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&trace.output).unwrap();
+}
+
+#[test]
+fn test_seq_read_order_in_write_phase() {
+    // 只写的阶段中 ro 不起作用，ro=seq 不会让后面的顺序写跳到 size 之后
+    let path = temp_path("write-ro-seq.toml");
+    fs::write(
+        &path,
+        r#"
+        [[phase]]
+        name = "fill"
+        count = 4
+        rw = "w"
+        wsize = "1-1"
+
+        [[phase]]
+        name = "more"
+        count = 4
+        rw = "w"
+        ro = "seq"
+        size = "2M"
+        wsize = "1-1"
+        "#,
+    )
+    .unwrap();
+
+    let mut base = tape_trace("write-ro-seq");
+    base.write_offset = 0;
+    let config = TapeTraceConfig::load(&path).unwrap();
+    let mut trace = base.clone();
+    trace.phases = config.phase.iter().map(|phase| phase.apply_phase(&base).unwrap()).collect();
+    generate_tape_trace(trace.clone()).unwrap();
+
+    let offsets: Vec<u64> = read(&trace.output).iter().map(|r| r.offset_sectors).collect();
+    assert_eq!(offsets, (0..8).map(|i| i * 512).collect::<Vec<_>>());
+
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&trace.output).unwrap();
+}
+
+#[test]
+fn test_budgets() {
+    // 请求数量：batch 不会让请求数量超出 count
+    let mut by_count = tape_trace("count");
+    by_count.total_size = 0;
+    by_count.count = 1000;
+    by_count.batch = BatchMode::Rw;
+    by_count.batch_ior_num_begin = 3;
+    by_count.batch_ior_num_end = 7;
+    by_count.batch_iow_num_begin = 3;
+    by_count.batch_iow_num_end = 7;
+    let stats = generate_tape_trace(by_count.clone()).unwrap();
+    let records: Vec<_> = TraceReader::open(&by_count.output).unwrap().map(|r| r.unwrap()).collect();
+    assert_eq!((stats.requests, records.len() as u64), (1000, 1000));
+    assert_eq!(stats.stopped_by, Budget::Count);
+
+    // 模拟时长：按 2000 IOPS 生成 1 秒
+    let mut by_duration = tape_trace("duration");
+    by_duration.total_size = 0;
+    by_duration.duration = 1.0;
+    by_duration.time_interval_dist = Dist::Exponential(2.0);
+    let stats = generate_tape_trace(by_duration.clone()).unwrap();
+    let elapsed: f64 = TraceReader::open(&by_duration.output).unwrap().map(|r| r.unwrap().next_inter).sum();
+    assert!(stats.duration >= 1.0 && (elapsed / 1000.0 - stats.duration).abs() < 1e-3, "{:?}", stats);
+    assert!((stats.requests as f64 - 2000.0).abs() < 200.0, "{:?}", stats);
+    assert_eq!(stats.stopped_by, Budget::Duration);
+
+    // 同时指定时，先用完的条件结束生成
+    let mut combined = tape_trace("combined");
+    combined.count = 10;
+    let stats = generate_tape_trace(combined.clone()).unwrap();
+    assert_eq!((stats.requests, stats.stopped_by), (10, Budget::Count));
+
+    fs::remove_file(&by_count.output).unwrap();
+    fs::remove_file(&by_duration.output).unwrap();
+    fs::remove_file(&combined.output).unwrap();
+}
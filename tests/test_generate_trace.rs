@@ -0,0 +1,86 @@
+mod common;
+
+use diskpine::{
+    commands::generate_trace::{generate_trace, DiskTrace, Pattern},
+    trace::{Op, TraceReader},
+    Dist,
+};
+
+use common::temp_path;
+
+/// 请求数量为 count 的 DiskTrace，输出到临时目录
+fn disk_trace(name: &str, count: u64) -> DiskTrace {
+    let mut trace = DiskTrace::new();
+    trace.count = count;
+    trace.length_start = 8;
+    trace.length_end = 256;
+    trace.seed = Some(2024);
+    trace.force = true;
+    trace.output = temp_path(&format!("disk-{}.trace", name));
+    trace
+}
+
+#[test]
+fn test_patterns() {
+    // 随机访问：偏移和长度按 align 对齐，且不超出地址范围
+    let mut rand = disk_trace("rand", 1000);
+    rand.range_start = 1000;
+    rand.range_end = 100_000;
+    rand.align = 16;
+    rand.read_rate = 3.0;
+    rand.write_rate = 1.0;
+    let stats = generate_trace(rand.clone()).unwrap();
+    assert_eq!((stats.requests, stats.read_op + stats.write_op), (1000, 1000));
+    assert!(stats.read_op > 650 && stats.read_op < 850, "{:?}", stats);
+    let records: Vec<_> = TraceReader::open(&rand.output).unwrap().map(|r| r.unwrap()).collect();
+    assert_eq!(records.len(), 1000);
+    for record in &records {
+        assert_eq!((record.offset_sectors % 16, record.len_sectors % 16), (0, 0));
+        assert!(record.offset_sectors >= 1008 && record.end_sectors() <= 99_984, "{:?}", record);
+    }
+
+    // 顺序访问：从 addr_start 开始首尾相接，超出地址范围时回到起始地址
+    let mut seq = disk_trace("seq", 100);
+    seq.pattern = Pattern::Seq;
+    seq.addr_start = Some(1024);
+    seq.range_end = 4096;
+    seq.length_start = 64;
+    seq.length_end = 64;
+    seq.read_rate = 0.0;
+    generate_trace(seq.clone()).unwrap();
+    let records: Vec<_> = TraceReader::open(&seq.output).unwrap().map(|r| r.unwrap()).collect();
+    assert!(records.iter().all(|r| r.op == Op::Write && r.len_sectors == 64));
+    assert_eq!(records[0].offset_sectors, 1024);
+    assert_eq!(records[1].offset_sectors, 1088);
+    assert_eq!(records[48].offset_sectors, 0);
+
+    // 固定步长：数据量用完即停止
+    let mut stride = disk_trace("stride", 0);
+    stride.pattern = Pattern::Stride;
+    stride.addr_start = Some(0);
+    stride.stride = 2048;
+    stride.length_start = 8;
+    stride.length_end = 8;
+    stride.total_size = 80;
+    let stats = generate_trace(stride.clone()).unwrap();
+    assert_eq!(stats.requests, 10);
+    let records: Vec<_> = TraceReader::open(&stride.output).unwrap().map(|r| r.unwrap()).collect();
+    let offsets: Vec<_> = records.iter().map(|r| r.offset_sectors).collect();
+    assert_eq!(offsets, (0..10).map(|i| i * 2048).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_queue_depth() {
+    let mut trace = disk_trace("qd", 40);
+    trace.queue_depth = 4;
+    trace.time_interval_dist = Dist::Constant(5.0);
+    let stats = generate_trace(trace.clone()).unwrap();
+
+    // 每 4 个请求为一组，只有每组的最后一个请求有时间间隔
+    let records: Vec<_> = TraceReader::open(&trace.output).unwrap().map(|r| r.unwrap()).collect();
+    for (i, record) in records.iter().enumerate() {
+        let expected = if i % 4 == 3 { 5.0 } else { 0.0 };
+        assert_eq!(record.next_inter, expected, "request {}", i);
+    }
+    assert!((stats.duration - 0.05).abs() < 1e-9, "{:?}", stats);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use diskpine::{
+    commands::merge::{merge, Merge, MergeArgs, MergeMode},
+    error::HMSimError,
+    trace::{Op, TraceReader},
+};
+
+use common::temp_path;
+
+/// 在临时目录中写入一个 trace 文件，返回文件名
+fn write_trace(name: &str, content: &str) -> String {
+    let path = temp_path(&format!("merge-{}.trace", name));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn merge_args(files: Vec<String>, mode: MergeMode, window: Option<u64>, output: &str) -> Merge {
+    Merge { files, mode, window, output: write_trace(output, ""), force: true }
+}
+
+#[test]
+fn test_merge() {
+    let a = write_trace("a", "W Hit 0 8 0.000000 10.000000\nW Hit 8 8 0.000000 10.000000\nW Hit 16 8 0.000000 0.000000\n");
+    let b = write_trace("b", "R Hit 0 8 0.000000 4.000000\nR Hit 8 8 0.000000 4.000000\nR Hit 16 8 0.000000 4.000000\n");
+
+    // 首尾相接时保留原来的时间间隔，每个输入平移到自己的窗口
+    let concat = merge_args(vec![a.clone(), b.clone()], MergeMode::Concat, Some(1024), "concat");
+    let stats = merge(concat.clone()).unwrap();
+    assert_eq!((stats.requests, stats.duration), (6, 0.032));
+    assert_eq!(stats.inputs[1].base, 1024);
+    let records: Vec<_> = TraceReader::open(&concat.output).unwrap().map(|r| r.unwrap()).collect();
+    let offsets: Vec<_> = records.iter().map(|r| r.offset_sectors).collect();
+    assert_eq!(offsets, [0, 8, 16, 1024, 1032, 1040]);
+
+    // 交错合并时按到达时间排序，合并后的到达时间与原来相同
+    let interleave = merge_args(vec![a.clone(), b.clone()], MergeMode::Interleave, None, "interleave");
+    let stats = merge(interleave.clone()).unwrap();
+    assert_eq!(stats.requests, 6);
+    let records: Vec<_> = TraceReader::open(&interleave.output).unwrap().map(|r| r.unwrap()).collect();
+    let mut arrival = 0.0;
+    let mut merged = vec![];
+    for record in &records {
+        merged.push((record.op, arrival));
+        arrival += record.next_inter;
+    }
+    assert_eq!(
+        merged,
+        [(Op::Write, 0.0), (Op::Read, 0.0), (Op::Read, 4.0), (Op::Read, 8.0), (Op::Write, 10.0), (Op::Write, 20.0)]
+    );
+
+    // 请求超出窗口、输出与输入相同时报错
+    match merge(merge_args(vec![a.clone(), b.clone()], MergeMode::Concat, Some(16), "small")) {
+        Err(HMSimError::CommandError(msg)) => assert!(msg.contains("第 3 行"), "{}", msg),
+        other => panic!("{:?}", other),
+    }
+    let mut same = merge_args(vec![a.clone()], MergeMode::Concat, None, "same");
+    same.output = a.clone();
+    assert!(merge(same).is_err());
+
+    // 写法不同但指向同一个文件的输出也会被拒绝，输入不会被覆盖
+    let content = fs::read_to_string(&a).unwrap();
+    let path = Path::new(&a);
+    let mut alias = merge_args(vec![a.clone()], MergeMode::Concat, None, "alias");
+    alias.output = path.parent().unwrap().join(".").join(path.file_name().unwrap()).display().to_string();
+    match merge(alias) {
+        Err(HMSimError::CommandError(msg)) => assert!(msg.contains("不能同时是输入文件"), "{}", msg),
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(fs::read_to_string(&a).unwrap(), content);
+
+    // 命令行参数中的 window 带单位
+    let args = MergeArgs { file: vec![a, b], mode: MergeMode::Interleave, window: Some(String::from("1M")), output: String::from("-") };
+    let resolved = args.resolve().unwrap();
+    assert_eq!((resolved.window, resolved.mode, resolved.force), (Some(2048), MergeMode::Interleave, false));
+}
@@ -1,6 +1,89 @@
-use diskpine::commands::origin_to_sim::origin_to_sim;
+mod common;
+
+use diskpine::{
+    commands::origin_to_sim::{origin_to_sim, OriginToSim, ParseMode},
+    trace::{Op, TraceReader, TraceRecord},
+};
+
+use common::{temp_path, TRACE};
 
 #[test]
 fn test() {
-    origin_to_sim("/root/diskpine/tests/test.csv", true).unwrap();
-}
\ No newline at end of file
+    let origin = OriginToSim::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test.csv"), true);
+    origin_to_sim(&OriginToSim { force: true, ..origin }).unwrap();
+
+    // 转换结果写在当前目录下，与 tests/test.trace 逐条比较。
+    // tests/test.trace 中记录的是与上一个请求的时间戳之差，转换结果为与下一个请求的时间戳之差
+    let output: Vec<TraceRecord> = TraceReader::open("test.trace").unwrap().map(|r| r.unwrap()).collect();
+    let expected: Vec<TraceRecord> = TraceReader::open(TRACE)
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    std::fs::remove_file("test.trace").unwrap();
+
+    assert_eq!(output.len(), expected.len());
+    for (i, record) in output.iter().enumerate() {
+        let next_inter = expected.get(i + 1).map_or(0.0, |next| next.next_inter);
+        assert_eq!(TraceRecord { next_inter: expected[i].next_inter, ..record.clone() }, expected[i]);
+        assert!((record.next_inter - next_inter).abs() < 1e-3, "{} {}", record.next_inter, next_inter);
+    }
+}
+
+/// 表头行、空行、小写的读写类型、行尾的空列和损坏的行
+const QUIRKS: &str = "Timestamp,Hostname,DiskNumber,Type,Offset,Size,ResponseTime\n\
+    128166372005310872,src1,0,Write,4096,4096,63790,,\n\
+    \n\
+    128166372005320872,src1,1,read,8192,4096,42009\n\
+    128166372005330872,src1,0,Write,oops,4096,14863\n\
+    128166372005340872,web,0,write,0,8192,76909\n\
+    128166372005350872,src1,0\n\
+    128166372005360872,src1,0,WRITE,12288,4096,42549\n";
+
+#[test]
+fn test_quirks() {
+    let stem = format!("diskpine-{}-quirks", std::process::id());
+    let csv = temp_path("quirks.csv");
+    std::fs::write(&csv, QUIRKS).unwrap();
+    let read = |path: &str| -> Vec<TraceRecord> {
+        let records = TraceReader::open(path).unwrap().map(|r| r.unwrap()).collect();
+        std::fs::remove_file(path).unwrap();
+        records
+    };
+
+    // 默认遇到损坏的行时报错并给出行号，--strict 连表头也不接受
+    let err = origin_to_sim(&OriginToSim::new(&csv, true)).unwrap_err().to_string();
+    assert!(err.contains("第 5 行") && err.contains("offset"), "{}", err);
+    // 转换失败时不留下不完整的输出文件
+    assert!(!std::path::Path::new(&format!("{}.trace", stem)).exists());
+    let strict = OriginToSim { parse: ParseMode::Strict, ..OriginToSim::new(&csv, true) };
+    let err = origin_to_sim(&strict).unwrap_err().to_string();
+    assert!(err.contains("第 1 行"), "{}", err);
+
+    let skip = OriginToSim { parse: ParseMode::SkipBad, ..OriginToSim::new(&csv, true) };
+    let stats = origin_to_sim(&skip).unwrap();
+    assert_eq!((stats.lines, stats.headers, stats.blank, stats.converted, stats.skipped), (8, 1, 1, 4, 2));
+    assert_eq!((stats.skipped_reasons["offset"], stats.skipped_reasons["columns"]), (1, 1));
+    // 输出文件已经存在时需要指定 force
+    let err = origin_to_sim(&skip).unwrap_err().to_string();
+    assert!(err.contains("--force"), "{}", err);
+    let records = read(&format!("{}.trace", stem));
+    let ops: Vec<Op> = records.iter().map(|r| r.op).collect();
+    assert_eq!(ops, [Op::Write, Op::Read, Op::Write, Op::Write]);
+    // 时间间隔为与下一个请求的时间戳之差，最后一个请求为 0；时间戳按 f64 解析，约有 0.001 ms 的误差
+    assert!((records[2].next_inter - 2.0).abs() < 0.01, "{}", records[2].next_inter);
+    assert_eq!(records[3].next_inter, 0.0);
+
+    // 只转换 src1 的请求，并按设备输出，时间间隔按同一设备的下一个请求计算
+    let split = OriginToSim { hostname: Some(String::from("src1")), split_device: true, force: true, ..skip };
+    let stats = origin_to_sim(&split).unwrap();
+    assert_eq!((stats.converted, stats.filtered), (3, 1));
+    let files: Vec<(&str, u64)> = stats.outputs.iter().map(|o| (o.file.as_str(), o.requests)).collect();
+    let (disk0, disk1) = (format!("{}-src1-0.trace", stem), format!("{}-src1-1.trace", stem));
+    assert_eq!(files, [(disk0.as_str(), 2), (disk1.as_str(), 1)]);
+    let records = read(&disk0);
+    assert!((records[0].next_inter - 5.0).abs() < 0.01, "{}", records[0].next_inter);
+    assert_eq!(records[1].next_inter, 0.0);
+    assert_eq!(read(&disk1).len(), 1);
+
+    std::fs::remove_file(&csv).unwrap();
+}
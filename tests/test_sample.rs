@@ -0,0 +1,78 @@
+mod common;
+
+use std::fs;
+
+use diskpine::{
+    commands::{
+        sample::{load_meta, meta_path, sample, Sample, SampleArgs, SampleMode},
+        trace_foot_size::foot_size_report,
+    },
+};
+
+use common::{arrivals, read, temp_path, TRACE};
+
+#[test]
+fn test_sample() {
+    let output = temp_path("sample.trace");
+
+    // 每秒只保留前 500ms 内到达的请求
+    let args = SampleArgs {
+        file: TRACE.to_string(),
+        output: output.clone(),
+        mode: SampleMode::Time,
+        rate: 0.5,
+        period: 1000.0,
+        block: String::from("4K"),
+        seed: None,
+    };
+    let stats = sample(&Sample { force: true, ..args.resolve().unwrap() }).unwrap();
+    assert_eq!((stats.scanned, stats.kept, stats.seed), (15, 8, None));
+
+    // 保留下来的请求的到达时间不变
+    let input = read(TRACE);
+    let records = read(&output);
+    let original = arrivals(&input);
+    let expected: Vec<f64> = original.iter().copied().filter(|t| t % 1000.0 < 500.0).collect();
+    for (a, b) in arrivals(&records).iter().zip(&expected) {
+        assert!((a - b).abs() < 1e-6, "{} {}", a, b);
+    }
+
+    // 抽样参数记录在 <output>.sample.json 中
+    let meta = load_meta(&output).unwrap().unwrap();
+    assert_eq!((meta.source.as_str(), meta.sample.mode, meta.sample.rate, meta.kept), (TRACE, SampleMode::Time, 0.5, 8));
+    assert!(load_meta(TRACE).unwrap().is_none());
+
+    // 按时间抽样的 trace 只估计请求数量和数据量，落盘量无法估计
+    let report = foot_size_report(&output, None, None).unwrap();
+    let estimated = report.estimated.unwrap();
+    assert_eq!((report.sample_rate, report.sample_mode), (Some(0.5), Some(SampleMode::Time)));
+    assert_eq!(estimated.write_count, report.stats.write_count * 2);
+    assert_eq!((estimated.write_footprint, estimated.union_footprint, estimated.read_after_write), (None, None, None));
+
+    // 所有请求落在同一个块中，spatial 抽样要么全部保留、要么全部丢弃，种子相同时结果相同
+    let base = Sample { file: TRACE.to_string(), output: output.clone(), force: true, ..Sample::default() };
+    let spatial = Sample { mode: SampleMode::Spatial, rate: 0.5, block: 1 << 31, seed: Some(11), ..base.clone() };
+    let stats = sample(&spatial).unwrap();
+    assert!(stats.kept == 0 || stats.kept == 15, "{:?}", stats);
+    assert_eq!(sample(&spatial).unwrap().kept, stats.kept);
+    assert_eq!(load_meta(&output).unwrap().unwrap().sample.seed, Some(11));
+
+    let stats = sample(&Sample { rate: 1.0, ..base }).unwrap();
+    assert_eq!(stats.kept, 15);
+    assert!(stats.seed.is_some());
+
+    // trace-foot-size 按抽样比例估计原 trace，只有 spatial 抽样时估计落盘量
+    let report = foot_size_report(&output, Some(0.25), Some(SampleMode::Spatial)).unwrap();
+    assert_eq!(report.estimated.unwrap().write_footprint, Some(report.stats.write_footprint * 4));
+    let report = foot_size_report(&output, None, None).unwrap();
+    assert_eq!(report.sample_mode, Some(SampleMode::Uniform));
+    assert_eq!(report.estimated.unwrap().write_footprint, None);
+
+    // trace 被其他命令覆盖后，留下的抽样信息与 trace 不一致，不再使用
+    fs::write(&output, fs::read_to_string(TRACE).unwrap().lines().next().unwrap()).unwrap();
+    assert!(load_meta(&output).unwrap().is_none());
+    assert!(foot_size_report(&output, None, None).unwrap().estimated.is_none());
+
+    fs::remove_file(&output).unwrap();
+    fs::remove_file(meta_path(&output)).unwrap();
+}
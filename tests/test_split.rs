@@ -0,0 +1,74 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use diskpine::{
+    commands::split::{split, SplitArgs, SplitStats},
+    trace::{Op, TraceRecord},
+};
+
+use common::{read, temp_path, TRACE};
+
+fn args(prefix: &str) -> SplitArgs {
+    SplitArgs { file: TRACE.to_string(), prefix: Some(prefix.to_string()), requests: None, volume: None, time: None, by_op: false }
+}
+
+/// 覆盖之前生成的分片
+fn run(args: SplitArgs) -> SplitStats {
+    let mut split_args = args.resolve().unwrap();
+    split_args.force = true;
+    split(&split_args).unwrap()
+}
+
+#[test]
+fn test_split() {
+    let dir = temp_path("split");
+    fs::create_dir_all(&dir).unwrap();
+    let prefix = Path::new(&dir).join("test").display().to_string();
+    let input = read(TRACE);
+
+    let err = args(&prefix).resolve().unwrap_err();
+    assert!(err.to_string().contains("至少"), "{}", err);
+
+    // 每 4 个请求一个分片，拼起来与原 trace 相同
+    let stats = split(&SplitArgs { requests: Some(4), ..args(&prefix) }.resolve().unwrap()).unwrap();
+    assert_eq!(stats.requests, 15);
+    let counts: Vec<u64> = stats.outputs.iter().map(|o| o.requests).collect();
+    assert_eq!(counts, [4, 4, 4, 3]);
+    assert_eq!(stats.outputs[2].file, format!("{}-002.trace", prefix));
+    let joined: Vec<TraceRecord> = stats.outputs.iter().flat_map(|o| read(&o.file)).collect();
+    assert_eq!(joined.len(), input.len());
+    for (a, b) in input.iter().zip(&joined) {
+        assert_eq!((a.offset_sectors, a.len_sectors), (b.offset_sectors, b.len_sectors));
+        assert!((a.next_inter - b.next_inter).abs() < 1e-6);
+    }
+
+    // 已经存在的分片需要 --force 才会覆盖
+    assert!(split(&SplitArgs { requests: Some(4), ..args(&prefix) }.resolve().unwrap()).is_err());
+
+    // 按 1 秒切分，没有请求的窗口不生成文件
+    let stats = run(SplitArgs { time: Some(1000.0), ..args(&prefix) });
+    let windows: Vec<(String, u64)> = stats
+        .outputs
+        .iter()
+        .map(|o| (o.file.trim_start_matches(&prefix).to_string(), o.requests))
+        .collect();
+    assert_eq!(windows, [("-000.trace".to_string(), 5), ("-002.trace".to_string(), 1), ("-004.trace".to_string(), 9)]);
+
+    // 数据量达到 32K 后开始新的分片
+    let stats = run(SplitArgs { volume: Some(String::from("32K")), ..args(&prefix) });
+    assert!(stats.outputs.iter().all(|o| o.data >= 64 || o.file == stats.outputs.last().unwrap().file));
+    assert_eq!(stats.outputs.iter().map(|o| o.data).sum::<u64>(), 208);
+
+    // 读写分开，写请求的到达时间差不变
+    let stats = run(SplitArgs { by_op: true, ..args(&prefix) });
+    assert_eq!(stats.outputs.len(), 2);
+    let writes = read(&format!("{}-write.trace", prefix));
+    let reads = read(&format!("{}-read.trace", prefix));
+    assert_eq!((reads.len(), writes.len()), (1, 14));
+    assert!(writes.iter().all(|r| r.op == Op::Write));
+    // 第 10 个写请求之后原来有一个读请求，时间间隔合并
+    assert!((writes[9].next_inter - (0.4752 + 1.9872)).abs() < 1e-6, "{}", writes[9].next_inter);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use std::fs;
+
+use diskpine::commands::timewarp::{timewarp, Timewarp, TimewarpArgs};
+
+use common::{read, temp_path, TRACE};
+
+#[test]
+fn test_timewarp() {
+    let output = temp_path("timewarp.trace");
+    let input = read(TRACE);
+
+    // 截断空闲时间后按目标 IOPS 缩放
+    let args = TimewarpArgs {
+        file: TRACE.to_string(),
+        output: output.clone(),
+        factor: None,
+        iops: Some(100.0),
+        max_gap: Some(100.0),
+        zero: false,
+    };
+    let stats = timewarp(&Timewarp { force: true, ..args.resolve().unwrap() }).unwrap();
+    let records = read(&output);
+    assert_eq!(stats.requests, input.len() as u64);
+    assert!(stats.clamped > 0);
+    assert!((stats.output_iops.unwrap() - 100.0).abs() < 1e-6, "{:?}", stats);
+    assert!(records.iter().all(|r| r.next_inter <= 100.0 * stats.factor + 1e-9));
+
+    // 只改时间间隔，地址和大小不变
+    for (a, b) in input.iter().zip(&records) {
+        assert_eq!((a.op, a.offset_sectors, a.len_sectors), (b.op, b.offset_sectors, b.len_sectors));
+    }
+
+    let base = Timewarp { file: TRACE.to_string(), output: output.clone(), force: true, ..Timewarp::default() };
+    let stats = timewarp(&Timewarp { factor: Some(0.5), ..base.clone() }).unwrap();
+    assert!((stats.output_duration * 2.0 - stats.input_duration).abs() < 1e-9);
+
+    let stats = timewarp(&Timewarp { zero: true, ..base.clone() }).unwrap();
+    assert_eq!((stats.output_duration, stats.output_iops), (0.0, None));
+    assert!(read(&output).iter().all(|r| r.next_inter == 0.0));
+
+    // 输出文件已存在且没有 force 时报错
+    assert!(timewarp(&Timewarp { zero: true, force: false, ..base }).is_err());
+
+    fs::remove_file(&output).unwrap();
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use std::fs;
+
+use diskpine::commands::transform::{transform, Transform, TransformArgs};
+
+use common::{arrivals, read, temp_path, TRACE};
+
+#[test]
+fn test_transform() {
+    let output = temp_path("transform.trace");
+    let args = TransformArgs {
+        file: TRACE.to_string(),
+        output: output.clone(),
+        shift: Some(String::from("-1M")),
+        scale_lba: None,
+        modulo_capacity: Some(String::from("3G")),
+        align: Some(String::from("4K")),
+        split_max: Some(String::from("16K")),
+    };
+    let stats = transform(&Transform { force: true, ..args.resolve().unwrap() }).unwrap();
+
+    let input = read(TRACE);
+    let records = read(&output);
+    assert_eq!((stats.input_requests, stats.output_requests), (input.len() as u64, records.len() as u64));
+    assert_eq!(stats.split, 1);
+
+    // 所有请求对齐到 4K，不超过 16K，且落在 3G 之内
+    let capacity = 3 * 2 * 1024 * 1024;
+    for record in &records {
+        assert_eq!((record.offset_sectors % 8, record.len_sectors % 8), (0, 0));
+        assert!(record.len_sectors <= 32 && record.end_sectors() <= capacity, "{:?}", record);
+    }
+    assert_eq!(records[0].offset_sectors, 6259488 - 2048);
+    // 9953800 - 2048 超出 3G，回绕到 3G 之内
+    assert_eq!(records[4].offset_sectors, 9953800 - 2048 - capacity);
+
+    // 拆分出的请求连续到达，原来每个请求的到达时间不变
+    let mut original = arrivals(&input);
+    original.dedup();
+    let mut transformed = arrivals(&records);
+    transformed.dedup();
+    assert_eq!(original.len(), transformed.len());
+    for (a, b) in original.iter().zip(&transformed) {
+        assert!((a - b).abs() < 1e-6);
+    }
+
+    // 地址变成负数时报错，并给出文件名和行号
+    let negative = TransformArgs { shift: Some(String::from("-1T")), modulo_capacity: None, ..args.clone() };
+    let err = transform(&Transform { force: true, ..negative.resolve().unwrap() }).unwrap_err();
+    assert!(err.to_string().contains("第 1 行"), "{}", err);
+
+    fs::remove_file(&output).unwrap();
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::fs;
+
+use diskpine::{
+    commands::{
+        generate_trace::Pattern,
+        warmup_trace::{warmup_trace, WarmupTrace},
+    },
+    trace::{Op, TraceReader},
+};
+
+use common::temp_path;
+
+/// 预热 1M、随机请求 4M、设备容量 16M 的 WarmupTrace，输出到临时目录
+fn small_warmup(name: &str) -> WarmupTrace {
+    let mut trace = WarmupTrace::new();
+    trace.warmup_size = 2048;
+    trace.warmup_len = 256;
+    trace.rand.size = 4 * 2048;
+    trace.rand.capacity = 16 * 2048;
+    trace.rand.length_end = 64;
+    trace.seed = Some(2024);
+    trace.force = true;
+    trace.output = temp_path(&format!("{}.trace", name));
+    trace.rand_output = temp_path(&format!("{}-rand.trace", name));
+    trace
+}
+
+#[test]
+fn test_warmup_and_cache() {
+    let trace = small_warmup("warmup");
+    let _ = fs::remove_file(trace.meta_path());
+
+    let first = warmup_trace(trace.clone()).unwrap();
+    assert!(!first.cached);
+    assert_eq!((first.warmup.requests, first.warmup.write_op), (8, 8));
+
+    // 预热请求从地址 0 开始顺序写，之后是随机请求部分的全部内容
+    let records: Vec<_> = TraceReader::open(&trace.output).unwrap().map(|r| r.unwrap()).collect();
+    assert_eq!(records.len() as u64, first.warmup.requests + first.rand.requests);
+    for (i, record) in records.iter().take(8).enumerate() {
+        assert_eq!((record.op, record.offset_sectors, record.len_sectors), (Op::Write, i as u64 * 256, 256));
+    }
+    let content = fs::read_to_string(&trace.output).unwrap();
+    assert!(content.ends_with(&fs::read_to_string(&trace.rand_output).unwrap()));
+
+    // 参数相同时复用随机请求，不指定种子时可以复用任意种子生成的随机请求
+    let mut unseeded = trace.clone();
+    unseeded.seed = None;
+    let second = warmup_trace(unseeded).unwrap();
+    assert!(second.cached);
+    assert_eq!(second.rand, first.rand);
+    assert_eq!(fs::read_to_string(&trace.output).unwrap(), content);
+
+    // 参数或种子不同时重新生成
+    let mut changed = trace.clone();
+    changed.rand.read_rate = 0.0;
+    let third = warmup_trace(changed).unwrap();
+    assert!(!third.cached);
+    assert_eq!(third.rand.read_op, 0);
+    let mut reseeded = trace.clone();
+    reseeded.seed = Some(1);
+    assert!(!warmup_trace(reseeded).unwrap().cached);
+
+    // 随机预热只写设备容量内的数据
+    let mut rand = trace.clone();
+    rand.warmup_pattern = Pattern::Rand;
+    let stats = warmup_trace(rand.clone()).unwrap();
+    assert_eq!(stats.warmup.pattern, Pattern::Rand);
+    let records: Vec<_> = TraceReader::open(&rand.output).unwrap().map(|r| r.unwrap()).collect();
+    assert!(records.iter().all(|r| r.end_sectors() <= rand.rand.capacity));
+
+    // 不指定 force 时不覆盖已经存在的输出文件
+    let mut existing = trace.clone();
+    existing.force = false;
+    assert!(warmup_trace(existing).is_err());
+}